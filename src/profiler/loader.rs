@@ -0,0 +1,68 @@
+//! Loads many independent files on a pool of worker tasks
+
+use std::cmp;
+use std::comm::{Chan, SharedChan};
+use std::task;
+use std::vec;
+
+/// Calls `load` with each of `paths` on `jobs` worker tasks.
+/// Each worker is handed the next file as soon as it is done with the previous one,
+/// but the results are returned in the order of `paths`. `progress` is called with
+/// the number of files loaded so far and the path of the last one each time a file
+/// has been loaded. Files which were not loaded because their worker failed have
+/// no result. Rendering the results is left to the caller, so nothing a worker
+/// produces depends on the order the files finish in.
+pub fn load_all<T: Send>(paths: ~[~str], jobs: uint, load: fn(~str) -> T,
+		progress: |uint, &str|) -> ~[Option<T>] {
+	let total = paths.len();
+	let mut results = vec::from_fn(total, |_| None);
+	if total == 0 {
+		return results;
+	}
+
+	let mut workers = ~[];
+	let done_port = {
+		let (done_port, done_chan) = SharedChan::new();
+		for id in range(0, cmp::min(cmp::max(jobs, 1), total)) {
+			let (job_port, job_chan) = Chan::new();
+			let done_chan = done_chan.clone();
+			task::spawn(proc() {
+				// Load files until the channel is closed
+				loop {
+					match job_port.recv_opt() {
+						Some((index, path)) => done_chan.send((id, index, load(path))),
+						None => break
+					}
+				}
+			});
+			workers.push(Some(job_chan));
+		}
+		done_port
+	};
+
+	let mut next = 0;
+	for job in workers.iter() {
+		job.get_ref().send((next, paths[next].clone()));
+		next += 1;
+	}
+
+	let mut done = 0;
+	while done < total {
+		// Fails once no worker is left
+		let (id, index, result) = match done_port.recv_opt() {
+			Some(msg) => msg,
+			None => break
+		};
+		results[index] = Some(result);
+		done += 1;
+		progress(done, paths[index]);
+
+		if next < total && workers[id].get_ref().try_send((next, paths[next].clone())) {
+			next += 1;
+		} else {
+			// Dropping the channel stops the worker
+			workers[id] = None;
+		}
+	}
+	results
+}
@@ -1,111 +1,276 @@
+#[feature(phase)];
+
+#[phase(syntax, link)]
 extern mod xml;
 
 use std::io::File;
 use std::io::Reader;
 use std::path::Path;
-use profile::{FieldPermission, ObjectPermission, RecordTypeVisibility, 
-			  Profile, get_element_value};
+use profile::{FieldPermission, ObjectPermission, RecordTypeVisibility, Profile};
 
 pub mod profile;
+mod loader;
 
 fn main() {
 	let args = std::os::args();
-	if args.len() != 2 {
-		// file must be specified in args
-		// ie ~/Workspaces/Ruby/ProfileParser/profiles/Accounting.profile
-		println!("Usage: {} <file>", args[0]);
-		return;
+	let (jobs, files) = match parse_args(args.tail()) {
+		Some(parsed) => parsed,
+		None => {
+			// files must be specified in args
+			// ie ~/Workspaces/Ruby/ProfileParser/profiles/Accounting.profile
+			println!("Usage: {} [--jobs <n>] <file>...", args[0]);
+			return;
+		}
+	};
+
+	let total = files.len();
+	let mut err = std::io::stderr();
+	let results = loader::load_all(files.clone(), jobs, load_profile, |done, file| {
+		writeln!(&mut err, "[{}/{}] {}", done, total, file);
+	});
+
+	// Files are printed in the order they were given, whichever worker
+	// finished first
+	for (file, result) in files.iter().zip(results.move_iter()) {
+		match result {
+			Some(Ok(loaded)) => {
+				for line in render(&loaded).iter() {
+					println!("{}", *line);
+				}
+				for msg in loaded.errors.iter() {
+					println!("{}: {}", *file, *msg);
+				}
+			}
+			Some(Err(msg)) => println!("{}: {}", *file, msg),
+			None => println!("{}: Loading failed", *file)
+		}
 	}
-	
-	let filename: ~str = args[1].clone();	
-	let contents: ~str = match read_file(filename) {
-		Some(c) => c,
-		None => ~"Error reading file contents"
+}
+
+// Splits the arguments into the number of jobs and the files to load
+fn parse_args(args: &[~str]) -> Option<(uint, ~[~str])> {
+	let mut jobs = std::rt::default_sched_threads();
+	let mut files = ~[];
+	let mut iter = args.iter();
+	loop {
+		match iter.next() {
+			Some(arg) if arg.as_slice() == "--jobs" || arg.as_slice() == "-j" => {
+				match iter.next().and_then(|n| from_str::<uint>(*n)) {
+					Some(n) if n > 0 => jobs = n,
+					_ => return None
+				}
+			}
+			Some(arg) => files.push(arg.clone()),
+			None => break
+		}
+	}
+
+	if files.is_empty() {
+		None
+	} else {
+		Some((jobs, files))
+	}
+}
+
+// An entry of a file which is not part of the profile read from it
+enum Entry {
+	// An element which is not a profile entry
+	Unknown(xml::Element),
+	// A profile entry which could not be read, with the reason
+	Invalid(xml::Atom, ~str)
+}
+
+// What was read from a single file, rendered by `main` once all files are loaded
+struct LoadedFile {
+	// The profile, if the root element is one
+	profile: Option<Profile>,
+	entries: ~[Entry],
+	errors: ~[~str]
+}
+
+// Parses a profile with a parser and builder of its own, so it can run on
+// any worker
+fn load_profile(filename: ~str) -> Result<LoadedFile, ~str> {
+	let contents: ~[u8] = match read_file(filename) {
+		Ok(c) => c,
+		Err(msg) => return Err(msg)
 	};
-	
+
 	let mut p = xml::Parser::new();
+	// Profiles may come from anywhere, so their size is limited
+	p.set_config(xml::ParserConfig::untrusted());
+	p.set_recover(true);
 	let mut e = xml::ElementBuilder::new();
+	e.set_whitespace(xml::DropWhitespace);
+	e.set_coalesce(true);
+	// Hand each child of the root over as soon as it is read, instead of
+	// building the whole profile first
+	e.set_stream_depth(Some(1));
+	let mut reader = ProfileReader::new(p.atoms());
+	let mut errors = ~[];
 
-	p.parse_str(contents, |event| {
+	p.parse_bytes(contents, |event| {
 		match event {
 			Ok(event) => match e.push_event(event) {
-				Ok(Some(e)) => {
-					if e.name == ~"Profile" {
-						handle_root(&e)
-					}
-					else {
-						handle_element(&e)
-					}
-				},
+				Ok(Some(xml::Root(elem))) => reader.finish(&elem),
+				Ok(Some(xml::Subtree(elem))) => reader.handle_entry(&elem),
 				Ok(None) => (),
-				Err(e) => println!("{}", e),
+				Err(e) => errors.push(e),
 			},
-			Err(e) => println!("Line: {} Column: {} Msg: {}", e.line, e.col, e.msg),
+			Err(e) => errors.push(format!("Line: {} Column: {} Msg: {}", e.line, e.col, e.msg)),
+		}
+	});
+	p.finish(|event| {
+		match event {
+			Err(e) => errors.push(format!("Line: {} Column: {} Msg: {}", e.line, e.col, e.msg)),
+			_ => ()
 		}
 	});
+
+	Ok(LoadedFile { profile: reader.result, entries: reader.entries, errors: errors })
 }
 
-fn handle_root(e: &xml::Element) {
-	println!("Root found: {}", e.name);
-	let mut p = Profile::new();
-	let mut fperms = ~[];
-	let mut operms = ~[];
-	let mut rtvis  = ~[];
-	for x in e.children.iter() {
-		match *x {
-			xml::Element(ref e) => {
-				match e.name {
-					~"fieldPermissions" => fperms.push(FieldPermission::from_xml(e)),
-					~"objectPermissions" => operms.push(ObjectPermission::from_xml(e)),
-					~"recordTypeVisibilities" => rtvis.push(RecordTypeVisibility::from_xml(e)),
-					~"userLicense" => p.set_user_license(get_element_value(e)),
-					_ => handle_element(e),
-				}
-			},
-			_ => warn!("handle_root received unknown XML!")
+// Returns the lines to print for a file
+fn render(loaded: &LoadedFile) -> ~[~str] {
+	let mut output = ~[];
+	for entry in loaded.entries.iter() {
+		match *entry {
+			Unknown(ref e) => e.accept(&mut ElementPrinter { output: &mut output }),
+			Invalid(ref name, ref msg) => output.push(format!("Invalid {}: {}", *name, *msg))
 		}
 	}
 
-	if !fperms.is_empty() {
-		println!("Field Perm Total: {}", fperms.len());
-		p.push_field_perms(fperms);
+	match loaded.profile {
+		Some(ref profile) => {
+			output.push(~"Root found: Profile");
+			if !profile.fieldPermissions.is_empty() {
+				output.push(format!("Field Perm Total: {}", profile.fieldPermissions.len()));
+			}
+			if !profile.objectPermissions.is_empty() {
+				output.push(format!("Object Perm Total: {}", profile.objectPermissions.len()));
+			}
+			if !profile.recordTypeVisibilities.is_empty() {
+				output.push(format!("Record Type Visibility Total: {}", profile.recordTypeVisibilities.len()));
+			}
+			output.push(profile.to_str());
+		}
+		None => ()
 	}
+	output
+}
 
-	if !operms.is_empty() {
-		println!("Object Perm Total: {}", operms.len());
-		p.push_object_perms(operms);
+// The names of the entries of a profile, interned in the parser's table so
+// they are compared without looking at the strings
+struct Names {
+	profile: xml::Atom,
+	field_perms: xml::Atom,
+	object_perms: xml::Atom,
+	record_types: xml::Atom,
+	user_license: xml::Atom
+}
+
+// Collects the entries of a profile as they are streamed from the builder
+struct ProfileReader {
+	names: Names,
+	entries: ~[Entry],
+	profile: Profile,
+	// The profile, once the root has been read
+	result: Option<Profile>,
+	fperms: ~[FieldPermission],
+	operms: ~[ObjectPermission],
+	rtvis: ~[RecordTypeVisibility]
+}
+
+impl ProfileReader {
+	fn new(atoms: &mut xml::Atoms) -> ProfileReader {
+		ProfileReader {
+			names: Names {
+				profile: atoms.intern("Profile"),
+				field_perms: atoms.intern("fieldPermissions"),
+				object_perms: atoms.intern("objectPermissions"),
+				record_types: atoms.intern("recordTypeVisibilities"),
+				user_license: atoms.intern("userLicense")
+			},
+			entries: ~[],
+			profile: Profile::new(),
+			result: None,
+			fperms: ~[],
+			operms: ~[],
+			rtvis: ~[]
+		}
 	}
 
-	if !rtvis.is_empty() {
-		println!("Record Type Visibility Total: {}", rtvis.len());
-		p.push_record_types(rtvis);
+	fn handle_entry(&mut self, e: &xml::Element) {
+		let result = if e.name == self.names.field_perms {
+			xml::FromXml::from_xml(e).map(|fp| self.fperms.push(fp))
+		} else if e.name == self.names.object_perms {
+			xml::FromXml::from_xml(e).map(|op| self.operms.push(op))
+		} else if e.name == self.names.record_types {
+			xml::FromXml::from_xml(e).map(|rtv| self.rtvis.push(rtv))
+		} else if e.name == self.names.user_license {
+			Ok(self.profile.set_user_license(e.content_str()))
+		} else {
+			Ok(self.entries.push(Unknown(e.clone())))
+		};
+		match result {
+			Err(msg) => self.entries.push(Invalid(e.name.clone(), msg)),
+			Ok(()) => ()
+		}
 	}
 
-	println!("{}", p.to_str());
-}
+	// Called with the root once all of its children have been handled
+	fn finish(&mut self, root: &xml::Element) {
+		if root.name != self.names.profile {
+			self.entries.push(Unknown(root.clone()));
+			return;
+		}
 
-fn handle_element(e: &xml::Element) {
-	println!("Element Name: {}", e.name);
-	for x in e.children.iter() {
-		match *x {
-			xml::Element(ref e) => handle_element(e),
-			xml::CharacterNode(ref cn) => println!("Charnode: {}", *cn),
-			xml::CDATANode(ref cd) => println!("CDATAnode: {}", *cd),
-			xml::CommentNode(ref co) => println!("Comment: {}", *co),
-			//println!("Child Element: {}", handle_element(e)),
-			_ => println!("No more elements found")
+		let mut profile = std::util::replace(&mut self.profile, Profile::new());
+		let fperms = std::util::replace(&mut self.fperms, ~[]);
+		if !fperms.is_empty() {
+			profile.push_field_perms(fperms);
+		}
+		let operms = std::util::replace(&mut self.operms, ~[]);
+		if !operms.is_empty() {
+			profile.push_object_perms(operms);
 		}
+		let rtvis = std::util::replace(&mut self.rtvis, ~[]);
+		if !rtvis.is_empty() {
+			profile.push_record_types(rtvis);
+		}
+		self.result = Some(profile);
+	}
+}
+
+// Prints the elements and character data below an element
+struct ElementPrinter<'a> {
+	output: &'a mut ~[~str]
+}
+
+impl<'a> xml::Visitor for ElementPrinter<'a> {
+	fn enter(&mut self, e: &xml::Element) -> xml::VisitAction {
+		self.output.push(format!("Element Name: {}", e.name));
+		xml::VisitChildren
+	}
+
+	fn visit_node(&mut self, node: &xml::XML) {
+		let line = match *node {
+			xml::CharacterNode(ref cn) => format!("Charnode: {}", *cn),
+			xml::CDATANode(ref cd) => format!("CDATAnode: {}", *cd),
+			xml::CommentNode(ref co) => format!("Comment: {}", *co),
+			_ => ~"No more elements found"
+		};
+		self.output.push(line);
 	}
 }
 
 
 
-fn read_file(filepath: &str) -> Option<~str> {
+fn read_file(filepath: &str) -> Result<~[u8], ~str> {
 	let f = &Path::new(filepath);
 	if !f.exists() {
-		println!("File '{}' does not exist", filepath);
-		return None;
+		return Err(format!("File '{}' does not exist", filepath));
 	}
 
-	Some(File::open(f).read_to_str())
-}
\ No newline at end of file
+	Ok(File::open(f).read_to_end())
+}
@@ -30,36 +30,15 @@ impl Profile {
 	}
 
 	pub fn push_field_perms(&mut self, fps: ~[FieldPermission]) {
-		if fps.len() > 0 {
-			let mut count = 0;
-			for fp in fps.iter() {
-				self.fieldPermissions.push(fp.clone());
-				count += 1;
-			}
-			println!("{} field permissions added to profile", count);
-		}
+		self.fieldPermissions.push_all_move(fps);
 	}
 
 	pub fn push_object_perms(&mut self, ops: ~[ObjectPermission]) {
-		if ops.len() > 0 {
-			let mut count = 0;
-			for op in ops.iter() {
-				self.objectPermissions.push(op.clone());
-				count += 1;
-			}
-			println!("{} object permissions added to profile", count);
-		}
+		self.objectPermissions.push_all_move(ops);
 	}
 
 	pub fn push_record_types(&mut self, rtvs: ~[RecordTypeVisibility]) {
-		if rtvs.len() > 0 {
-			let mut count = 0;
-			for rtv in rtvs.iter() {
-				self.recordTypeVisibilities.push(rtv.clone());
-				count += 1;
-			}
-			println!("{} record type visibilities added to profile", count);
-		}
+		self.recordTypeVisibilities.push_all_move(rtvs);
 	}
 }
 
@@ -120,45 +99,27 @@ impl FieldPermission {
 		}
 	}
 
-	pub fn from_xml(e: &xml::Element) -> FieldPermission {
-		let mut fp = FieldPermission::new();
-		for x in e.children.iter() {
-			match *x {
-				xml::Element(ref e) => {
-					match e.name {
-						~"field" => { fp.set_field(get_element_value(e)); },
-						~"readable" => { fp.set_readable(get_element_value(e)); },
-						~"editable" => { fp.set_editable(get_element_value(e));	},
-						_ => warn!("Encountered unknown element name"),
-					}
-				}, 
-				_ => (),
-			}
-		}
-		fp
-	}
-
 	pub fn set_field(&mut self, f: ~str) {
 		self.field = f
 	}
 
 	pub fn set_readable(&mut self, rd: ~str) -> bool {
-		match from_str::<bool>(rd) {
-			Some(b) => self.readable = b,
-			None => self.readable = false
-		} 
+		self.readable = parse_bool(rd);
 		self.readable
 	}
 
 	pub fn set_editable(&mut self, ed: ~str) -> bool {
-		match from_str::<bool>(ed) {
-			Some(b) => self.editable = b,
-			None => self.editable = false
-		}
+		self.editable = parse_bool(ed);
 		self.editable
 	}
 }
 
+xml_struct!(FieldPermission {
+	default editable,
+	default field,
+	default readable
+})
+
 /// ObjectPermission definition
 #[deriving(Clone,Eq)]
 pub struct ObjectPermission {
@@ -184,81 +145,51 @@ impl ObjectPermission {
 		}
 	}
 
-	pub fn from_xml(e: &xml::Element) -> ObjectPermission {
-		let mut op = ObjectPermission::new();
-		for x in e.children.iter() {
-			match *x {
-				xml::Element(ref e) => {
-					match e.name {
-						~"object" => { op.set_object(get_element_value(e)); },
-						~"allowCreate" => { op.set_allow_create(get_element_value(e)); },
-						~"allowRead" => { op.set_allow_read(get_element_value(e)); },
-						~"allowEdit" => { op.set_allow_edit(get_element_value(e)); },
-						~"allowDelete" => {	op.set_allow_delete(get_element_value(e)); },
-						~"viewAllRecords" => { op.set_view_all(get_element_value(e)); },
-						~"modifyAllRecords" => { op.set_modify_all(get_element_value(e)); },
-						_ => println!("Skipped element name"),
-					}
-				}
-				_ => (),
-			}
-		}
-		op
-	}
-
 	pub fn set_object(&mut self, o: ~str) {
 		self.object = o;
 	}
 
 	pub fn set_allow_read(&mut self, ar: ~str) -> bool {
-		match from_str::<bool>(ar) {
-			Some(b) => self.allowRead = b,
-			None => self.allowRead = false,
-		}
+		self.allowRead = parse_bool(ar);
 		self.allowRead
 	}
 
 	pub fn set_allow_create(&mut self, ac: ~str) -> bool {
-		match from_str::<bool>(ac) {
-			Some(b) => self.allowCreate = b,
-			None => self.allowCreate = false,
-		}
+		self.allowCreate = parse_bool(ac);
 		self.allowCreate
 	}
 
 	pub fn set_allow_edit(&mut self, ae: ~str) -> bool {
-		match from_str::<bool>(ae) {
-			Some(b) => self.allowEdit = b,
-			None => self.allowEdit = false,
-		}
+		self.allowEdit = parse_bool(ae);
 		self.allowEdit
 	}
 
 	pub fn set_allow_delete(&mut self, ad: ~str) -> bool {
-		match from_str::<bool>(ad) {
-			Some(b) => self.allowDelete = b,
-			None => self.allowDelete = false,
-		}
+		self.allowDelete = parse_bool(ad);
 		self.allowDelete
 	}
 
 	pub fn set_view_all(&mut self, va: ~str) -> bool {
-		match from_str::<bool>(va) {
-			Some(b) => self.viewAllRecords = b,
-			None => self.viewAllRecords = false,
-		}
+		self.viewAllRecords = parse_bool(va);
 		self.viewAllRecords
 	}
 
 	pub fn set_modify_all(&mut self, ma: ~str) -> bool {
-		match from_str::<bool>(ma) {
-			Some(b) => self.modifyAllRecords = b,
-			None => self.modifyAllRecords = false,
-		}
+		self.modifyAllRecords = parse_bool(ma);
 		self.modifyAllRecords
 	}
 }
 
+xml_struct!(ObjectPermission {
+	default allowCreate,
+	default allowDelete,
+	default allowEdit,
+	default allowRead,
+	default modifyAllRecords,
+	default object,
+	default viewAllRecords
+})
+
 /// RecordTypeVisibility definition
 #[deriving(Clone,Eq)]
 pub struct RecordTypeVisibility {
@@ -276,53 +207,36 @@ impl RecordTypeVisibility {
 		}
 	}
 
-	pub fn from_xml(e: &xml::Element) -> RecordTypeVisibility {
-		let mut rtv = RecordTypeVisibility::new();
-		for x in e.children.iter() {
-			match *x {
-				xml::Element(ref e) => {
-					match e.name {
-						~"recordType" => { rtv.set_record_type(get_element_value(e)); },
-						~"default" => { rtv.set_default(get_element_value(e)); },
-						~"visible" => { rtv.set_visible(get_element_value(e)); },
-						_ => warn!("Invalid element name"),
-					}
-				},
-				_ => (),
-			}
-		}
-		rtv
-	}
-
 	pub fn set_record_type(&mut self, rt: ~str) {
 		self.recordType = rt
 	}
 
 	pub fn set_default(&mut self, def: ~str) -> bool {
-		match from_str::<bool>(def) {
-			Some(b) => self.default = b,
-			None => self.default = false,
-		}
+		self.default = parse_bool(def);
 		self.default
 	}
 
 	pub fn set_visible(&mut self, vis: ~str) -> bool {
-		match from_str::<bool>(vis) {
-			Some(b) => self.visible = b,
-			None => self.visible = false,
-		}
+		self.visible = parse_bool(vis);
 		self.visible
 	}
 }
 
+xml_struct!(RecordTypeVisibility {
+	default default,
+	default recordType,
+	default visible
+})
+
 /// helper methods
 pub fn get_element_value(e: &xml::Element) -> ~str {
-	let mut value = ~"";
-	for x in e.children.iter() {
-		match *x {
-			xml::CharacterNode(ref c) => value.push_str(*c),
-			_ => println!("No CharacterNode found!")
-		}
+	e.content_str()
+}
+
+// Reads a boolean like the fields mapped with `default` do, invalid values being false
+fn parse_bool(value: &str) -> bool {
+	match xml::XmlText::from_xml_text(value) {
+		Ok(b) => b,
+		Err(_) => false
 	}
-	value
-}
\ No newline at end of file
+}
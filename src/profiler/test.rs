@@ -0,0 +1,42 @@
+#[feature(phase)];
+
+#[phase(syntax, link)]
+extern mod xml;
+
+mod profile;
+
+#[cfg(test)]
+mod profile_tests {
+	use xml::{Element, FromXml};
+	use profile::{FieldPermission, ObjectPermission, RecordTypeVisibility};
+
+	#[test]
+	fn test_missing_child() {
+		let elem: Element = from_str("<fieldPermissions><editable>true</editable>\
+<field>Account.Name</field></fieldPermissions>").unwrap();
+		let fp: Result<FieldPermission, ~str> = FromXml::from_xml(&elem);
+		assert_eq!(fp, Ok(FieldPermission { field: ~"Account.Name", readable: false, editable: true }));
+
+		let elem: Element = from_str("<objectPermissions><object>Account</object>\
+<viewAllRecords>maybe</viewAllRecords></objectPermissions>").unwrap();
+		let op: Result<ObjectPermission, ~str> = FromXml::from_xml(&elem);
+		assert_eq!(op.err(), Some(~"Invalid boolean 'maybe'"));
+	}
+
+	#[test]
+	fn test_invalid_child() {
+		let elem: Element = from_str("<recordTypeVisibilities><default>yes</default>\
+<recordType>Account.Business</recordType><visible>true</visible></recordTypeVisibilities>").unwrap();
+		let rtv: Result<RecordTypeVisibility, ~str> = FromXml::from_xml(&elem);
+		assert_eq!(rtv, Err(~"Invalid boolean 'yes'"));
+	}
+
+	#[test]
+	fn test_setters() {
+		let mut fp = FieldPermission::new();
+		assert!(fp.set_readable(~"true"));
+		assert!(!fp.set_editable(~"maybe"));
+		fp.set_field(~"Account.Name");
+		assert_eq!(fp, FieldPermission { field: ~"Account.Name", readable: true, editable: false });
+	}
+}
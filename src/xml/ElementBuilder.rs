@@ -4,85 +4,318 @@
 // This project is MIT licensed.
 // Please see the COPYING file for more information.
 
-use super::{Event, PI, StartTag, EndTag, Characters, CDATA, Comment};
+use super::{Event, XmlDecl, PI, StartTag, EndTag, Characters, CDATA, Comment};
 use super::{Element, CharacterNode, CDATANode, CommentNode, PINode};
-use std::hashmap::HashMap;
+use super::{XML, Attribute, Atom, Span, Trivia, WhitespaceMode, KeepWhitespace};
+use super::base::apply_whitespace;
+use super::namespace::{XMLNS_NS, declaration};
+use std::util;
+
+/// An element finished by `ElementBuilder::push_event`
+pub enum Built {
+    /// The root element, which ends the document
+    Root(Element),
+    /// An element returned on its own while streaming, see `set_stream_depth`
+    Subtree(Element)
+}
 
 // DOM Builder
 /// An ELement Builder, building `Element`s from `Event`s as produced by `Parser`
 pub struct ElementBuilder {
     priv stack: ~[Element],
-    priv default_ns: ~[Option<~str>],
-    priv prefixes: HashMap<~str, ~str>
+    priv whitespace: WhitespaceMode,
+    priv coalesce: bool,
+    priv preserve: ~[bool],
+    priv stream_depth: Option<uint>,
+    priv stream_names: ~[~str],
+    // Whether each open element is returned on its own once finished
+    priv streamed: ~[bool],
+    // Whether the last child of each open element was streamed
+    priv after_streamed: ~[bool],
+    priv lossless: bool,
+    // Declarations added to the root element, as made with `define_prefix`
+    priv declarations: ~[Attribute]
+}
+
+// Applies `mode` to the pending text and appends what is left to `children`
+fn flush_text(children: &mut ~[XML], pending: &mut Option<~str>, mode: WhitespaceMode) {
+    match pending.take() {
+        Some(text) => match apply_whitespace(mode, text) {
+            Some(text) => children.push(CharacterNode(text)),
+            None => ()
+        },
+        None => ()
+    }
 }
 
 impl ElementBuilder {
     /// Returns a new `ElementBuilder`
     pub fn new() -> ElementBuilder {
-        let mut e = ElementBuilder {
+        ElementBuilder {
             stack: ~[],
-            default_ns: ~[],
-            prefixes: HashMap::with_capacity(2),
-        };
-        e.prefixes.swap(~"http://www.w3.org/XML/1998/namespace", ~"xml");
-        e.prefixes.swap(~"http://www.w3.org/2000/xmlns/", ~"xmlns");
-        e
+            whitespace: KeepWhitespace,
+            coalesce: false,
+            preserve: ~[],
+            stream_depth: None,
+            stream_names: ~[],
+            streamed: ~[],
+            after_streamed: ~[],
+            lossless: false,
+            declarations: ~[]
+        }
     }
 
-    /// Bind a prefix to a namespace
+    /// Binds `prefix` to `ns` on the root element, so the prefix is used for `ns`
+    /// when the built tree is written. Declarations read from the document are
+    /// kept as well, and take precedence.
     pub fn define_prefix(&mut self, prefix: ~str, ns: ~str) {
-        self.prefixes.swap(ns, prefix);
+        self.declarations.push(Attribute { name: Atom::new(prefix), ns: Some(XMLNS_NS.to_owned()), value: ns });
     }
 
+    /// Declares `ns` as the default namespace on the root element, unless the
+    /// document declares one there itself.
     pub fn set_default_ns(&mut self, ns: ~str) {
-        self.default_ns = ~[Some(ns)];
+        self.declarations.push(Attribute { name: Atom::new("xmlns"), ns: None, value: ns });
+    }
+
+    /// Sets how whitespace in the character data of built elements is handled.
+    /// Elements marked `xml:space="preserve"` always keep their whitespace.
+    pub fn set_whitespace(&mut self, mode: WhitespaceMode) {
+        self.whitespace = mode;
+    }
+
+    /// When enabled, adjacent character data and CDATA children are merged into
+    /// a single `CharacterNode`, before whitespace is handled.
+    pub fn set_coalesce(&mut self, coalesce: bool) {
+        self.coalesce = coalesce;
+    }
+
+    /// Returns the elements at `depth` on their own as soon as they are finished,
+    /// instead of adding them to their parent. The children of the root element
+    /// are at depth 1. Streamed elements are not kept by the builder, so a document
+    /// can be processed one subtree at a time without building all of it. Character
+    /// data, comments and processing instructions next to a streamed element are
+    /// dropped with it.
+    pub fn set_stream_depth(&mut self, depth: Option<uint>) {
+        self.stream_depth = depth;
+    }
+
+    /// Returns the elements whose local name is in `names` on their own as soon as
+    /// they are finished, like `set_stream_depth`. Matching elements inside an
+    /// element which is streamed are kept in it. The root element is never streamed.
+    pub fn set_stream_names(&mut self, names: ~[~str]) {
+        self.stream_names = names;
+    }
+
+    /// When enabled, the source text handed to `push_event_source` is recorded as
+    /// the `Trivia` of the built elements. All character data is kept as it is read,
+    /// regardless of the whitespace and coalescing settings.
+    pub fn set_lossless(&mut self, lossless: bool) {
+        self.lossless = lossless;
+    }
+
+    /// Returns the number of open elements.
+    pub fn depth(&self) -> uint {
+        self.stack.len()
+    }
+
+    // Whether an element named `name` starting at `depth` is streamed
+    fn is_streamed(&self, depth: uint, name: &str) -> bool {
+        if depth == 0 || self.streamed.iter().any(|s| *s) {
+            return false;
+        }
+        self.stream_depth == Some(depth) || self.stream_names.iter().any(|n| n.as_slice() == name)
+    }
+
+    // Appends `node` to the open element, unless it follows a streamed element
+    fn push_node(&mut self, node: XML) {
+        let l = self.stack.len();
+        if l > 0 && !self.after_streamed[l-1] {
+            self.stack[l-1].children.push(node);
+        }
+    }
+
+    // Coalesces and strips the text children of a finished element, as configured
+    fn normalize_text(&self, elem: &mut Element, preserve: bool) {
+        let mode = if preserve { KeepWhitespace } else { self.whitespace };
+        if self.lossless || (mode == KeepWhitespace && !self.coalesce) {
+            return;
+        }
+
+        let children = util::replace(&mut elem.children, ~[]);
+        let mut pending = None;
+        for child in children.move_iter() {
+            match child {
+                CharacterNode(text)
+                | CDATANode(text) if self.coalesce => {
+                    if pending.is_some() {
+                        pending.get_mut_ref().push_str(text);
+                    } else {
+                        pending = Some(text);
+                    }
+                }
+                CharacterNode(text) => {
+                    flush_text(&mut elem.children, &mut pending, mode);
+                    pending = Some(text);
+                }
+                other => {
+                    flush_text(&mut elem.children, &mut pending, mode);
+                    elem.children.push(other);
+                }
+            }
+        }
+        flush_text(&mut elem.children, &mut pending, mode);
+    }
+
+    /// Hands an `Event` read from `span` to the builder.
+    /// Behaves like `push_event`, but also records the `Span` of each built `Element`,
+    /// from the start of its start tag to the end of its end tag.
+    pub fn push_event_at(&mut self, e: Event, span: Span) -> Result<Option<Built>, ~str> {
+        let is_start = match e {
+            StartTag(_) => true,
+            EndTag(_) => {
+                let l = self.stack.len();
+                if l > 0 {
+                    let start = self.stack[l-1].span.take().map(|s| s.start);
+                    self.stack[l-1].span = Some(~Span {
+                        start: start.unwrap_or(span.start.clone()),
+                        end: span.end.clone()
+                    });
+                }
+                false
+            }
+            _ => false
+        };
+
+        let result = self.push_event(e);
+        if is_start {
+            let l = self.stack.len();
+            self.stack[l-1].span = Some(~span);
+        }
+        result
+    }
+
+    /// Hands an `Event` to the builder together with the text it was read from.
+    /// Behaves like `push_event`, but in lossless mode also records `source`
+    /// in the `Trivia` of the open element. An empty `source` for an `EndTag`
+    /// means the element was written as `<a/>`.
+    pub fn push_event_source(&mut self, e: Event, source: &str) -> Result<Option<Built>, ~str> {
+        if !self.lossless {
+            return self.push_event(e);
+        }
+
+        match e {
+            StartTag(_) => {
+                let result = self.push_event(e);
+                let l = self.stack.len();
+                let elem = &mut self.stack[l-1];
+                elem.trivia = Some(~Trivia {
+                    start_tag: source.to_owned(),
+                    end_tag: None,
+                    name: elem.name.clone(),
+                    ns: elem.ns.clone(),
+                    attributes: elem.attributes.clone(),
+                    children: ~[],
+                    before: ~"",
+                    after: ~""
+                });
+                result
+            }
+            EndTag(_) => {
+                let l = self.stack.len();
+                if l > 0 && !source.is_empty() {
+                    match self.stack[l-1].trivia {
+                        Some(ref mut trivia) => trivia.end_tag = Some(source.to_owned()),
+                        None => ()
+                    }
+                }
+                self.push_event(e)
+            }
+            XmlDecl(_) => self.push_event(e),
+            _ => {
+                let l = self.stack.len();
+                let count = if l > 0 { self.stack[l-1].children.len() } else { 0 };
+                let result = self.push_event(e);
+                if l > 0 && self.stack[l-1].children.len() > count {
+                    // The node was appended to the open element
+                    let node = self.stack[l-1].children.last_opt().map(|n| n.clone());
+                    match (node, &mut self.stack[l-1].trivia) {
+                        (Some(node), &Some(ref mut trivia)) => {
+                            trivia.children.push((node, source.to_owned()))
+                        }
+                        _ => ()
+                    }
+                }
+                result
+            }
+        }
     }
 
     /// Hands an `Event` to the builder.
     /// While no root element has been finished `Ok(None)` is returned.
-    /// Once sufficent data has been received an `Element` is returned as `Ok(elem)`.
+    /// Once sufficent data has been received the root is returned as `Ok(Some(Root(elem)))`.
+    /// When streaming, finished subtrees are returned as `Ok(Some(Subtree(elem)))`
+    /// before the root.
     /// Upon Error `Err("message")` is returned.
-    pub fn push_event(&mut self, e: Event) -> Result<Option<Element>, ~str> {
+    pub fn push_event(&mut self, e: Event) -> Result<Option<Built>, ~str> {
         match e {
-            PI(cont) => {
-                let l = self.stack.len();
-                if l > 0 {
-                    self.stack[l-1].children.push(PINode(cont));
-                }
+            XmlDecl(_) => Ok(None),
+            PI(pi) => {
+                self.push_node(PINode(pi));
                 Ok(None)
             }
-            StartTag(StartTag { name, ns, prefix: _, attributes }) => {
+            StartTag(StartTag { name, ns, prefix, attributes }) => {
+                // Namespace declarations are kept as attributes and the prefix as a
+                // hint, so the serializer can reuse the prefixes of the source document
                 let mut elem = Element {
-                    name: name.clone(),
-                    ns: ns.clone(),
-                    default_ns: None,
-                    prefixes: self.prefixes.clone(),
-                    attributes: ~[],
-                    children: ~[]
+                    name: name,
+                    ns: ns,
+                    prefix: prefix,
+                    attributes: attributes,
+                    children: ~[],
+                    span: None,
+                    trivia: None
                 };
-
-                if !self.default_ns.is_empty() {
-                    let cur_default = self.default_ns.last().clone();
-                    self.default_ns.push(cur_default);
+                if self.stack.is_empty() {
+                    for decl in self.declarations.iter() {
+                        let (prefix, _) = declaration(decl).unwrap();
+                        let declared = elem.attributes.iter().any(|a| {
+                            declaration(a).map_default(false, |(p, _)| p == prefix)
+                        });
+                        if !declared {
+                            elem.attributes.push(decl.clone());
+                        }
+                    }
                 }
 
-                for attr in attributes.iter() {
-                    if attr.ns == None && attr.name.as_slice() == "xmlns" {
-                        self.default_ns.pop_opt();
-                        if attr.value.len() == 0 {
-                            self.default_ns.push(None);
-                        } else {
-                            self.default_ns.push(Some(attr.value.clone()));
+                let inherited = self.preserve.last_opt().map_default(false, |p| *p);
+                let space = elem.attribute_with_name_and_ns("space",
+                                Some(~"http://www.w3.org/XML/1998/namespace"));
+                self.preserve.push(match space {
+                    Some(attr) if attr.value.as_slice() == "preserve" => true,
+                    Some(attr) if attr.value.as_slice() == "default" => false,
+                    _ => inherited
+                });
+
+                let l = self.stack.len();
+                let streamed = self.is_streamed(l, elem.name.as_slice());
+                if streamed {
+                    // The text before a streamed element goes with it, so nothing
+                    // collects between streamed siblings
+                    let parent = &mut self.stack[l-1];
+                    loop {
+                        let text = match parent.children.last_opt() {
+                            Some(&Element(_)) | None => false,
+                            Some(_) => true
+                        };
+                        if !text {
+                            break;
                         }
-                        continue;
-                    }
-                    if attr.ns == Some(~"http://www.w3.org/2000/xmlns/") {
-                        elem.prefixes.swap(attr.value.clone(), attr.name.clone());
+                        parent.children.pop();
                     }
-                    elem.attributes.push(attr.clone());
                 }
-                elem.default_ns = self.default_ns.last_opt().unwrap_or(&None).clone();
-
+                self.streamed.push(streamed);
+                self.after_streamed.push(false);
                 self.stack.push(elem);
 
                 Ok(None)
@@ -91,37 +324,37 @@ impl ElementBuilder {
                 if self.stack.len() == 0 {
                     return Err(~"Elements not properly nested");
                 }
-                self.default_ns.pop_opt();
-                let elem = self.stack.pop();
+                let mut elem = self.stack.pop();
+                let preserve = self.preserve.pop();
+                let streamed = self.streamed.pop();
+                self.after_streamed.pop();
                 let l = self.stack.len();
                 if elem.name != name || elem.ns != ns {
                     Err(~"Elements not properly nested")
                 } else if l == 0 {
-                    Ok(Some(elem))
+                    self.normalize_text(&mut elem, preserve);
+                    Ok(Some(Root(elem)))
+                } else if streamed {
+                    self.normalize_text(&mut elem, preserve);
+                    self.after_streamed[l-1] = true;
+                    Ok(Some(Subtree(elem)))
                 } else {
+                    self.normalize_text(&mut elem, preserve);
+                    self.after_streamed[l-1] = false;
                     self.stack[l-1].children.push(Element(elem));
                     Ok(None)
                 }
             }
             Characters(chars) => {
-                let l = self.stack.len();
-                if l > 0 {
-                    self.stack[l-1].children.push(CharacterNode(chars));
-                }
+                self.push_node(CharacterNode(chars));
                 Ok(None)
             }
             CDATA(chars) => {
-                let l = self.stack.len();
-                if l > 0 {
-                    self.stack[l-1].children.push(CDATANode(chars));
-                }
+                self.push_node(CDATANode(chars));
                 Ok(None)
             }
             Comment(cont) => {
-                let l = self.stack.len();
-                if l > 0 {
-                    self.stack[l-1].children.push(CommentNode(cont));
-                }
+                self.push_node(CommentNode(cont));
                 Ok(None)
             }
         }
@@ -3,561 +3,271 @@
 //
 // This project is MIT licensed.
 // Please see the COPYING file for more information.
-//
-// The parser herein is derived from OFXMLParser as included with
-// ObjFW, Copyright (c) 2008-2013 Jonathan Schleifer.
-// Permission to license this derived work under MIT license has been granted by ObjFW's author.
-
-use super::{unescape, Attribute, Event, PI, StartTag, EndTag, Characters, CDATA, Comment, Error};
-use std::hashmap::HashMap;
 
-// Event based parser
-enum State {
-    OutsideTag,
-    TagOpened,
-    InProcessingInstructions,
-    InTagName,
-    InCloseTagName,
-    InTag,
-    InAttrName,
-    InAttrValue,
-    ExpectDelimiter,
-    ExpectClose,
-    ExpectSpaceOrClose,
-    InExclamationMark,
-    InCDATAOpening,
-    InCDATA,
-    InCommentOpening,
-    InComment1,
-    InComment2,
-    InDoctype
-}
-
-/// A streaming XML parser
-pub struct Parser {
-    priv line: uint,
-    priv col: uint,
-    priv buf: ~str,
-    priv name: ~str,
-    priv prefix: Option<~str>,
-    priv namespaces: ~[HashMap<~str, ~str>],
-    priv attr_name: ~str,
-    priv attr_prefix: Option<~str>,
-    priv attributes: ~[Attribute],
-    priv delim: Option<char>,
-    priv st: State,
-    priv level: uint
+use super::{Event, StartTag, EndTag, Characters, Error, Span, SyntaxError};
+use super::{WhitespaceMode, KeepWhitespace};
+use super::base::apply_whitespace;
+use super::encoding;
+use super::namespace::XML_NS;
+use super::atom::Atoms;
+use super::reader::{Reader, ReaderState};
+
+
+#[deriving(Clone,Eq)]
+/// Limits on the resources a `Parser` or `Reader` uses for a document, to read
+/// untrusted input safely. A limit of `None` is not checked. Exceeding a limit is reported as an
+/// `Error` of the matching `ErrorKind` and stops parsing, also when recovering.
+pub struct ParserConfig {
+    /// The deepest elements may be nested, counting the root element as 1
+    max_depth: Option<uint>,
+    /// The most attributes an element may have, including namespace declarations
+    max_attributes: Option<uint>,
+    /// The longest name of an element or attribute, in bytes
+    max_name_length: Option<uint>,
+    /// The longest character data, attribute value, CDATA section, comment or
+    /// processing instruction, in bytes
+    max_text_size: Option<uint>,
+    /// The largest document, in bytes of all text passed to the parser
+    max_document_size: Option<uint>,
+    /// The most character and entity references replaced in the whole document
+    max_entity_expansions: Option<uint>
 }
 
-impl Parser {
-    /// Returns a new `Parser`
-    pub fn new() -> Parser {
-        let mut p = Parser {
-            line: 1,
-            col: 0,
-            buf: ~"",
-            name: ~"",
-            prefix: None,
-            namespaces: ~[HashMap::with_capacity(2)],
-            attr_name: ~"",
-            attr_prefix: None,
-            attributes: ~[],
-            delim: None,
-            st: OutsideTag,
-            level: 0
-        };
-        p.namespaces[0].swap(~"xml", ~"http://www.w3.org/XML/1998/namespace");
-        p.namespaces[0].swap(~"xmlns", ~"http://www.w3.org/2000/xmlns/");
-        p
-    }
-
-    /**
-     * Parses the string `data`.
-     * The callback `cb` is called for each `Event`, or `Error` generated while parsing
-     * the string.
-     *
-     * ~~~
-     * let mut p = Parser::new();
-     * do p.parse_str("<a href='http://rust-lang.org'>Rust</a>") |event| {
-     *     match event {
-     *        [...]
-     *     }
-     * }
-     * ~~~
-     */
-    pub fn parse_str(&mut self, data: &str, cb: |Result<Event, Error>|) {
-        for c in data.chars() {
-            if c == '\n' {
-                self.line += 1u;
-                self.col = 0u;
-            } else {
-                self.col += 1u;
-            }
-
-            match self.parse_character(c) {
-                Ok(None) => continue,
-                Err(e) => {
-                    cb(Err(e));
-                    return;
-                }
-                Ok(Some(event)) => {
-                    cb(Ok(event));
-                }
-            }
+impl ParserConfig {
+    /// Returns a configuration without any limits
+    pub fn new() -> ParserConfig {
+        ParserConfig {
+            max_depth: None,
+            max_attributes: None,
+            max_name_length: None,
+            max_text_size: None,
+            max_document_size: None,
+            max_entity_expansions: None
         }
     }
-}
 
-#[inline]
-fn parse_qname(qname: &str) -> (Option<~str>, ~str) {
-    match qname.find(':') {
-        None => {
-            (None, qname.to_owned())
-        },
-        Some(i) => {
-            (Some(qname.slice_to(i).to_owned()), qname.slice_from(i+1).to_owned())
+    /// Returns limits suitable for documents from untrusted sources, which still
+    /// allow the largest Salesforce metadata files.
+    pub fn untrusted() -> ParserConfig {
+        ParserConfig {
+            max_depth: Some(256),
+            max_attributes: Some(256),
+            max_name_length: Some(1024),
+            max_text_size: Some(10 * 1024 * 1024),
+            max_document_size: Some(100 * 1024 * 1024),
+            max_entity_expansions: Some(1000000)
         }
     }
 }
 
-impl Parser {
-    fn namespace_for_prefix(&self, prefix: &~str) -> Option<~str> {
-        for ns in self.namespaces.rev_iter() {
-            match ns.find(prefix) {
-                None => continue,
-                Some(namespace) => {
-                    if namespace.len() == 0 {
-                        return None;
-                    } else {
-                        return Some(namespace.to_owned());
-                    }
-                }
-            }
-        }
-        None
-    }
 
-    fn error(&self, msg: ~str) -> Result<Option<Event>, Error> {
-        Err(Error { line: self.line, col: self.col, msg: msg })
-    }
+/**
+ * A streaming XML parser.
+ * The text passed in is read with a `Reader`, markup split between two pieces is
+ * kept until the rest of it arrives.
+ */
+pub struct Parser {
+    priv state: ReaderState,
+    // The text passed in which was not read yet, as it ends inside markup
+    priv pending: ~str,
+    priv config: ParserConfig,
+    priv recover: bool,
+    priv whitespace: WhitespaceMode,
+    // Whether `xml:space="preserve"` applies, for each open element
+    priv preserve: ~[bool],
+    priv atoms: Atoms
+}
 
-    fn parse_character(&mut self, c: char) -> Result<Option<Event>, Error> {
-        // println(fmt!("Now in state: %?", self.st));
-        match self.st {
-            OutsideTag => self.outside_tag(c),
-            TagOpened => self.tag_opened(c),
-            InProcessingInstructions => self.in_processing_instructions(c),
-            InTagName => self.in_tag_name(c),
-            InCloseTagName => self.in_close_tag_name(c),
-            InTag => self.in_tag(c),
-            InAttrName => self.in_attr_name(c),
-            InAttrValue => self.in_attr_value(c),
-            ExpectDelimiter => self.expect_delimiter(c),
-            ExpectClose => self.expect_close(c),
-            ExpectSpaceOrClose => self.expect_space_or_close(c),
-            InExclamationMark => self.in_exclamation_mark(c),
-            InCDATAOpening => self.in_CDATA_opening(c),
-            InCDATA => self.in_CDATA(c),
-            InCommentOpening => self.in_comment_opening(c),
-            InComment1 => self.in_comment1(c),
-            InComment2 => self.in_comment2(c),
-            InDoctype => self.in_doctype(c),
+// Tracks `xml:space` on the open elements and applies the whitespace mode `mode`
+// to character data, returning `None` for text which is dropped
+fn apply_space(event: Event, mode: WhitespaceMode, preserve: &mut ~[bool]) -> Option<Event> {
+    match event {
+        StartTag(tag) => {
+            let inherited = preserve.last_opt().map_default(false, |p| *p);
+            let space = tag.attributes.iter().find(|a| {
+                a.name.as_slice() == "space" &&
+                a.ns.as_ref().map_default(false, |ns| ns.as_slice() == XML_NS)
+            }).map(|a| a.value.clone());
+            preserve.push(match space {
+                Some(ref value) if value.as_slice() == "preserve" => true,
+                Some(ref value) if value.as_slice() == "default" => false,
+                _ => inherited
+            });
+            Some(StartTag(tag))
         }
-    }
-
-    fn outside_tag(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            '<' if self.buf.len() > 0 => {
-                self.st = TagOpened;
-                let buf = unescape(self.buf);
-                self.buf.clear();
-                return Ok(Some(Characters(buf)));
-            }
-            '<' => self.st = TagOpened,
-            _ => self.buf.push_char(c)
+        EndTag(tag) => {
+            preserve.pop_opt();
+            Some(EndTag(tag))
         }
-        Ok(None)
-    }
-
-    fn tag_opened(&mut self, c: char) -> Result<Option<Event>, Error> {
-        self.st = match c {
-            '?' => InProcessingInstructions,
-            '!' => InExclamationMark,
-            '/' => InCloseTagName,
-            _ => {
-                self.buf.push_char(c);
-                InTagName
-            }
-        };
-        Ok(None)
-    }
-
-    fn in_processing_instructions(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            '?' => {
-                self.level = 1;
-                self.buf.push_char(c);
-            }
-            '>' if self.level == 1 => {
-                self.level = 0;
-                self.st = OutsideTag;
-                let buf = self.buf.slice_chars(0, self.buf.char_len()-1).to_owned();
-                self.buf.clear();
-                return Ok(Some(PI(buf)));
-            }
-            _ => self.buf.push_char(c)
+        Characters(text) => {
+            let preserved = preserve.last_opt().map_default(false, |p| *p);
+            let mode = if preserved { KeepWhitespace } else { mode };
+            apply_whitespace(mode, text).map(|text| Characters(text))
         }
-        Ok(None)
+        event => Some(event)
     }
+}
 
-    fn in_tag_name(&mut self, c: char) -> Result<Option<Event>, Error> {
-        fn set_name(p: &mut Parser) {
-            let (prefix, name) = parse_qname(p.buf);
-            p.prefix = prefix;
-            p.name = name;
-            p.buf.clear();
-        };
-
-        match c {
-            '/'
-            | '>' => {
-                set_name(self);
-                let prefix = self.prefix.take();
-                let ns = match prefix {
-                    None => self.namespace_for_prefix(&~""),
-                    Some(ref pre) => {
-                        self.namespace_for_prefix(pre).or_else(|| {
-                            fail!("Unbound prefix: '{}'", *pre)
-                        })
-                    }
-                };
-
-                self.namespaces.push(HashMap::new());
-                self.st = if c == '/' {
-                    ExpectClose
-                } else {
-                    OutsideTag
-                };
-
-                return Ok(Some(StartTag(StartTag {
-                    name: self.name.clone(),
-                    ns: ns,
-                    prefix: prefix,
-                    attributes: ~[]
-                })));
-            }
-            ' '
-            | '\t'
-            | '\r'
-            | '\n' => {
-                self.namespaces.push(HashMap::new());
-                set_name(self);
-                self.st = InTag;
-            }
-            _ => self.buf.push_char(c)
-        }
-        Ok(None)
-    }
-
-    fn in_close_tag_name(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            ' '
-            | '\t'
-            | '\r'
-            | '\n'
-            | '>' => {
-                let (prefix, name) = parse_qname(self.buf);
-                self.buf.clear();
-
-                let ns = match prefix {
-                    None => self.namespace_for_prefix(&~""),
-                    Some(ref pre) => {
-                        self.namespace_for_prefix(pre).or_else(|| {
-                            fail!("Unbound prefix: '{}'", *pre)
-                        })
-                    }
-                };
-
-                self.namespaces.pop();
-                self.st = if c == '>' {
-                    OutsideTag
-                } else {
-                    ExpectSpaceOrClose
-                };
-
-                Ok(Some(EndTag(EndTag { name: name, ns: ns, prefix: prefix })))
-            }
-            _ => {
-                self.buf.push_char(c);
-                Ok(None)
-            }
-        }
-    }
-
-    fn in_tag(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            '/'
-            | '>' => {
-                let name = self.name.clone();
-                let mut attributes = self.attributes.clone();
-                self.attributes = ~[];
-                let prefix = self.prefix.clone();
-                let ns = match prefix {
-                    None => self.namespace_for_prefix(&~""),
-                    Some(ref pre) => {
-                        self.namespace_for_prefix(pre).or_else(|| {
-                            fail!("Unbound prefix: '{}'", *pre)
-                        })
-                    }
-                };
-
-                for attr in attributes.mut_iter() {
-                    attr.ns.mutate( |ref pre| {
-                        self.namespace_for_prefix(pre).unwrap_or_else( || {
-                            fail!("Unbound prefix: '{}'", *pre)
-                        })
-                    });
-                }
-
-                self.st = if c == '/' {
-                    ExpectClose
-                } else {
-                    self.name.clear();
-                    self.prefix = None;
-                    OutsideTag
-                };
-
-                return Ok(Some(StartTag(StartTag {
-                    name: name,
-                    ns: ns,
-                    prefix: prefix,
-                    attributes: attributes
-                })));
-            }
-            ' '
-            | '\t'
-            | '\r'
-            | '\n' => (),
-            _ => {
-                self.buf.push_char(c);
-                self.st = InAttrName;
-            }
+impl Parser {
+    /// Returns a new `Parser`
+    pub fn new() -> Parser {
+        Parser {
+            state: ReaderState::new(),
+            pending: ~"",
+            config: ParserConfig::new(),
+            recover: false,
+            whitespace: KeepWhitespace,
+            preserve: ~[],
+            atoms: Atoms::new()
         }
-        Ok(None)
     }
 
-    fn in_attr_name(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            '=' => {
-                self.level = 0;
-
-                let (prefix, name) = parse_qname(self.buf);
-                self.attr_prefix = prefix;
-                self.attr_name = name;
-
-                self.buf.clear();
-                self.st = ExpectDelimiter;
-            }
-            ' '
-            | '\t'
-            | '\r'
-            | '\n' => self.level = 1,
-            _ if self.level == 0 => self.buf.push_char(c),
-            _ => return self.error(~"Space occured in attribute name")
-        }
-        Ok(None)
+    /// Returns the table the names of elements and attributes are interned in.
+    /// Names can be looked up in it to compare them cheaply to the names read.
+    pub fn atoms<'a>(&'a mut self) -> &'a mut Atoms {
+        &mut self.atoms
     }
 
-    fn in_attr_value(&mut self, c: char) -> Result<Option<Event>, Error> {
-        if c == self.delim.expect("In attribute value, but no delimiter set") {
-            self.delim = None;
-            self.st = InTag;
-            let name = self.attr_name.clone();
-            self.attr_name.clear();
-            let value = unescape(self.buf);
-            self.buf.clear();
-            let prefix = self.attr_prefix.clone();
-            self.attr_prefix = None;
-
-            match prefix {
-                None if name.as_slice() == "xmlns" => {
-                    let last = &mut self.namespaces[self.namespaces.len()-1];
-                    last.swap(~"", value.clone());
-                }
-                Some(ref prefix) if prefix.as_slice() == "xmlns" => {
-                    let last = &mut self.namespaces[self.namespaces.len()-1];
-                    last.swap(name.clone(), value.clone());
-                }
-                _ => ()
-            }
-
-            self.attributes.push(Attribute { name: name, ns: prefix, value: value });
-        } else {
-            self.buf.push_char(c);
-        }
-        Ok(None)
+    /// Replaces the table names are interned in, e.g. to share a table between
+    /// the parsers of many documents.
+    pub fn set_atoms(&mut self, atoms: Atoms) {
+        self.atoms = atoms;
     }
 
-    fn expect_delimiter(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            '"'
-            | '\'' => {
-                self.delim = Some(c);
-                self.st = InAttrValue;
-            }
-            ' '
-            | '\t'
-            | '\r'
-            | '\n' => (),
-            _ => return self.error(~"Attribute value not enclosed in ' or \"")
-        }
-        Ok(None)
+    /// Sets how whitespace in character data is reported.
+    /// Elements marked `xml:space="preserve"` always keep their whitespace.
+    pub fn set_whitespace(&mut self, mode: WhitespaceMode) {
+        self.whitespace = mode;
     }
 
-    fn expect_close(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            '>' => {
-                self.st = OutsideTag;
-                let name = self.name.clone();
-                self.name.clear();
-                let prefix = self.prefix.take();
-                let ns = match prefix {
-                    None => self.namespace_for_prefix(&~""),
-                    Some(ref pre) => {
-                        self.namespace_for_prefix(pre).or_else(|| {
-                            fail!("Unbound prefix: '{}'", *pre)
-                        })
-                    }
-                };
-                self.namespaces.pop();
-                Ok(Some(EndTag(EndTag { name: name, ns: ns, prefix: prefix })))
-            }
-            _ => self.error(~"Expected '>' to close tag")
-       }
+    /// Sets the limits checked while parsing. By default nothing is limited.
+    pub fn set_config(&mut self, config: ParserConfig) {
+        self.config = config;
     }
 
-    fn expect_space_or_close(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            ' '
-            | '\t'
-            | '\r'
-            | '\n' => Ok(None),
-            '>' => {
-                self.st = OutsideTag;
-                Ok(None)
-            }
-            _ => self.error(~"Expected '>' to close tag, or LWS")
-       }
+    /// Enables or disables error recovery.
+    /// When recovering, the parser reports each `Error` and carries on: mismatched end
+    /// tags close the elements left open inside them, unknown end tags are dropped,
+    /// and other broken markup is skipped up to the next '<'. At the end of the
+    /// document `finish` closes all elements still open. The events reported stay
+    /// balanced, so they can be handed to an `ElementBuilder` for a best-effort tree.
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
     }
 
-    fn in_exclamation_mark(&mut self, c: char) -> Result<Option<Event>, Error> {
-        self.st = match c {
-            '-' => InCommentOpening,
-            '[' => InCDATAOpening,
-            'D' => InDoctype,
-            _ => return self.error(~"Malformed XML")
-        };
-        Ok(None)
+    /**
+     * Parses the string `data`.
+     * The callback `cb` is called for each `Event`, or `Error` generated while parsing
+     * the string. Unless recovery is enabled, parsing stops at the first `Error`
+     * and later calls are ignored.
+     * Call `finish` once the whole document has been passed in.
+     *
+     * ~~~
+     * let mut p = Parser::new();
+     * p.parse_str("<a href='http://rust-lang.org'>Rust</a>", |event| {
+     *     match event {
+     *        [...]
+     *     }
+     * });
+     * ~~~
+     */
+    pub fn parse_str(&mut self, data: &str, cb: |Result<Event, Error>|) {
+        self.parse_str_spanned(data, |result| cb(result.map(|(event, _)| event)));
     }
 
-    fn in_CDATA_opening(&mut self, c: char) -> Result<Option<Event>, Error> {
-        static CDATA_PATTERN: [char, ..6] = ['C', 'D', 'A', 'T', 'A', '['];
-        if c == CDATA_PATTERN[self.level] {
-            self.level += 1;
-        } else {
-            return self.error(~"Invalid CDATA opening sequence")
-        }
-
-        if self.level == 6 {
-            self.level = 0;
-            self.st = InCDATA;
+    /**
+     * Parses the string `data` like `parse_str`, additionally reporting the `Span`
+     * each `Event` was read from. Offsets count bytes of all text passed to the parser.
+     *
+     * As required by XML 1.0, "\r\n" and a lone "\r" are read as a single "\n",
+     * also when split between two calls, and count as a single line break in
+     * positions. Whitespace characters in attribute values are replaced by spaces,
+     * unlike those written as character references.
+     */
+    pub fn parse_str_spanned(&mut self, data: &str, cb: |Result<(Event, Span), Error>|) {
+        if self.state.failed() {
+            return;
         }
-        Ok(None)
+        self.pending.push_str(data);
+        self.read(true, cb);
     }
 
-    fn in_CDATA(&mut self, c: char) -> Result<Option<Event>, Error> {
-        match c {
-            ']' => {
-                self.buf.push_char(c);
-                self.level += 1;
-            }
-            '>' if self.level >= 2 => {
-                self.st = OutsideTag;
-                self.level = 0;
-                let buf = self.buf.slice_chars(0, self.buf.char_len()-2).to_owned();
-                self.buf.clear();
-                return Ok(Some(CDATA(buf)))
-            }
-            _ => {
-                self.buf.push_char(c);
-                self.level = 0;
-            }
-        }
-        Ok(None)
+    /**
+     * Signals the end of the document.
+     * An `Error` is passed to `cb` if the document ended in the middle of markup,
+     * left elements unclosed, or did not contain a root element.
+     * When recovering, the `EndTag`s of all elements still open are reported as well.
+     */
+    pub fn finish(&mut self, cb: |Result<Event, Error>|) {
+        self.finish_spanned(|result| cb(result.map(|(event, _)| event)));
     }
 
-    fn in_comment_opening(&mut self, c: char) -> Result<Option<Event>, Error> {
-        if c == '-' {
-            self.st = InComment1;
-            self.level = 0;
-            Ok(None)
-        } else {
-            self.error(~"Expected 2nd '-' to start comment")
+    /**
+     * Signals the end of the document like `finish`, additionally reporting the
+     * `Span` of each `Event`.
+     */
+    pub fn finish_spanned(&mut self, cb: |Result<(Event, Span), Error>|) {
+        if self.state.failed() {
+            return;
         }
+        self.read(false, cb);
     }
 
-    fn in_comment1(&mut self, c: char) -> Result<Option<Event>, Error> {
-        if c == '-' {
-            self.level += 1;
-        } else {
-            self.level = 0;
-        }
-
-        if self.level == 2 {
-            self.level = 0;
-            self.st = InComment2;
-        }
-
-        self.buf.push_char(c);
-
-        Ok(None)
+    /**
+     * Parses the bytes of a complete document.
+     * The encoding is taken from the byte order mark or the XML declaration,
+     * defaulting to UTF-8. UTF-16, ISO-8859-1 and Windows-1252 are also supported.
+     * Events are reported to `cb` as with `parse_str`.
+     */
+    pub fn parse_bytes(&mut self, data: &[u8], cb: |Result<Event, Error>|) {
+        self.parse_bytes_spanned(data, |result| cb(result.map(|(event, _)| event)));
     }
 
-    fn in_comment2(&mut self, c: char) -> Result<Option<Event>, Error> {
-        if c != '>' {
-            self.error(~"Not more than one adjacent '-' allowed in a comment")
-        } else {
-            self.st = OutsideTag;
-            let buf = self.buf.slice_chars(0, self.buf.char_len()-2).to_owned();
-            self.buf.clear();
-            Ok(Some(Comment(buf)))
+    /**
+     * Parses the bytes of a complete document like `parse_bytes`, additionally
+     * reporting the `Span` of each `Event`. Offsets count bytes of the decoded text.
+     */
+    pub fn parse_bytes_spanned(&mut self, data: &[u8], cb: |Result<(Event, Span), Error>|) {
+        match encoding::decode_document(data) {
+            Ok(s) => self.parse_str_spanned(s, cb),
+            Err(msg) => {
+                self.state.fail();
+                let (line, col) = {
+                    let position = self.state.position();
+                    (position.line, position.col)
+                };
+                cb(Err(Error { line: line, col: col, msg: msg, kind: SyntaxError }))
+            }
         }
     }
 
-    fn in_doctype(&mut self, c: char) -> Result<Option<Event>, Error> {
-        static DOCTYPE_PATTERN: [char, ..6] = ['O', 'C', 'T', 'Y', 'P', 'E'];
-        match self.level {
-            0..5 => if c == DOCTYPE_PATTERN[self.level] {
-                self.level += 1;
-            } else {
-                return self.error(~"Invalid DOCTYPE");
-            },
-            6 => {
-                match c {
-                    ' '
-                    | '\t'
-                    | '\r'
-                    | '\n' => (),
-                    _ => return self.error(~"Invalid DOCTYPE")
+    // Reads the pending text. If `partial` is set more text follows, and markup the
+    // pending text ends in is kept for the next call.
+    fn read(&mut self, partial: bool, cb: |Result<(Event, Span), Error>|) {
+        let (state, read) = {
+            let mut reader = Reader::resume(self.pending.as_slice(), &self.state,
+                                            self.config.clone(), self.recover, partial);
+            loop {
+                match reader.next() {
+                    Some(Ok(event)) => {
+                        let span = reader.span();
+                        let event = event.into_event(&mut self.atoms);
+                        match apply_space(event, self.whitespace, &mut self.preserve) {
+                            Some(event) => cb(Ok((event, span))),
+                            None => ()
+                        }
+                    }
+                    Some(Err(e)) => cb(Err(e)),
+                    None => break
                 }
-                self.level += 1;
             }
-            _ if c == '>' => {
-                self.level = 0;
-                self.st = OutsideTag;
-            }
-            _ => ()
+            reader.suspend()
+        };
+        self.state = state;
+        if read == self.pending.len() {
+            self.pending.clear();
+        } else if read > 0 {
+            self.pending = self.pending.slice_from(read).to_owned();
         }
-        Ok(None)
     }
 }
@@ -0,0 +1,396 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{Event, XmlDecl, PI, StartTag, EndTag, Characters, CDATA, Comment};
+use super::SerializeOptions;
+use super::base::{is_space, is_name, escape_text, escape_attribute, check_unescaped};
+use super::namespace::{Scope, XML_NS, XMLNS_NS};
+use super::reader::check_qname;
+use super::atom::Atom;
+use std::ascii::StrAsciiExt;
+
+// An element whose start tag has been written
+struct OpenElement {
+    name: Atom,
+    ns: Option<~str>,
+    qname: ~str,
+    // Whether any child node has been written
+    has_children: bool,
+    // Whether non-whitespace character data has been written
+    mixed: bool
+}
+
+/// A streaming XML writer, writing `Event`s as produced by `Parser` to a `Writer`.
+/// Namespace declarations and prefixes are added as needed, so events can be written
+/// without tracking which namespaces are in scope.
+pub struct XmlWriter<W> {
+    priv writer: W,
+    priv opts: SerializeOptions,
+    priv stack: ~[OpenElement],
+    priv scope: Scope,
+    // Whether the '>' of the last start tag is still pending
+    priv tag_open: bool,
+    priv written: bool,
+    priv root_written: bool
+}
+
+impl<W: Writer> XmlWriter<W> {
+    /// Returns a new `XmlWriter` writing compact output to `writer`
+    pub fn new(writer: W) -> XmlWriter<W> {
+        XmlWriter::with_options(writer, SerializeOptions::compact())
+    }
+
+    /// Returns a new `XmlWriter` formatting its output according to `opts`.
+    /// Attribute order is left unchanged. If `opts` contains a declaration it is
+    /// written before the first event, unless that event is an `XmlDecl`.
+    pub fn with_options(writer: W, opts: SerializeOptions) -> XmlWriter<W> {
+        XmlWriter {
+            writer: writer,
+            opts: opts,
+            stack: ~[],
+            scope: Scope::new(),
+            tag_open: false,
+            written: false,
+            root_written: false
+        }
+    }
+
+    /// Returns the underlying `Writer`
+    pub fn unwrap(self) -> W {
+        self.writer
+    }
+
+    /// Writes an `Event`.
+    /// Returns `Err("message")` if the event would not produce well-formed XML,
+    /// e.g. an `EndTag` not matching the open element.
+    pub fn write_event(&mut self, e: Event) -> Result<(), ~str> {
+        if !self.written {
+            match e {
+                XmlDecl(_) => (),
+                _ => match self.opts.declaration.clone() {
+                    Some(decl) => self.write_decl(&decl),
+                    None => ()
+                }
+            }
+        }
+
+        match e {
+            XmlDecl(decl) => {
+                if self.written {
+                    return Err(~"XML declaration must be the first event");
+                }
+                self.write_decl(&decl);
+                Ok(())
+            }
+            PI(pi) => {
+                if pi.data.contains("?>") {
+                    return Err(~"Processing instruction contains '?>'");
+                }
+                if !is_name(pi.target) || pi.target.eq_ignore_ascii_case("xml") {
+                    return Err(format!("Invalid processing instruction target '{}'", pi.target));
+                }
+                if !pi.space.chars().all(is_space) {
+                    return Err(~"Processing instruction target not followed by whitespace");
+                }
+                let mut pi = pi;
+                pi.data = check!(check_unescaped(pi.data, &self.opts.escape));
+                self.begin_node();
+                write!(&mut self.writer, "{}", pi);
+                Ok(())
+            }
+            StartTag(tag) => self.start_tag(tag),
+            EndTag(tag) => self.end_tag(tag),
+            Characters(chars) => {
+                if self.stack.is_empty() {
+                    if chars.chars().all(is_space) {
+                        return Ok(());
+                    }
+                    return Err(~"Character data outside of the root element");
+                }
+                // Whitespace between elements is replaced by our own indentation
+                if self.indenting() && chars.chars().all(is_space) {
+                    return Ok(());
+                }
+                let chars = check!(escape_text(chars, &self.opts.escape));
+                self.close_start_tag();
+                let l = self.stack.len();
+                self.stack[l-1].has_children = true;
+                self.stack[l-1].mixed = true;
+                write!(&mut self.writer, "{}", chars);
+                Ok(())
+            }
+            CDATA(chars) => {
+                if self.stack.is_empty() {
+                    return Err(~"CDATA outside of the root element");
+                }
+                if chars.contains("]]>") {
+                    return Err(~"CDATA contains ']]>'");
+                }
+                let chars = check!(check_unescaped(chars, &self.opts.escape));
+                self.close_start_tag();
+                let l = self.stack.len();
+                self.stack[l-1].has_children = true;
+                self.stack[l-1].mixed = true;
+                write!(&mut self.writer, "<![CDATA[{}]]>", chars);
+                Ok(())
+            }
+            Comment(cont) => {
+                if cont.contains("--") || cont.ends_with("-") {
+                    return Err(~"Comment contains '--'");
+                }
+                let cont = check!(check_unescaped(cont, &self.opts.escape));
+                self.begin_node();
+                write!(&mut self.writer, "<!--{}-->", cont);
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that all elements have been closed and writes the final line break,
+    /// if configured.
+    pub fn finish(&mut self) -> Result<(), ~str> {
+        match self.stack.last_opt() {
+            Some(elem) => return Err(format!("Unclosed element '{}'", elem.qname)),
+            None => ()
+        }
+        if self.opts.final_newline {
+            write!(&mut self.writer, "{}", self.opts.newline);
+        }
+        Ok(())
+    }
+
+    fn write_decl(&mut self, decl: &XmlDecl) {
+        let q = self.opts.quote;
+        write!(&mut self.writer, "<?xml version={}{}{}", q, decl.version, q);
+        match decl.encoding {
+            Some(ref enc) => write!(&mut self.writer, " encoding={}{}{}", q, *enc, q),
+            None => ()
+        }
+        match decl.standalone {
+            Some(standalone) => {
+                write!(&mut self.writer, " standalone={}{}{}", q,
+                       if standalone { "yes" } else { "no" }, q)
+            }
+            None => ()
+        }
+        write!(&mut self.writer, "?>");
+        self.written = true;
+    }
+
+    // Returns true if line breaks should be written in the current element
+    fn indenting(&self) -> bool {
+        self.opts.indent.is_some() && !self.stack.last_opt().map_default(false, |e| e.mixed)
+    }
+
+    // Writes the pending '>' of the last start tag
+    fn close_start_tag(&mut self) {
+        if self.tag_open {
+            write!(&mut self.writer, ">");
+            self.tag_open = false;
+        }
+    }
+
+    // Prepares writing a child node: an element, comment or PI
+    fn begin_node(&mut self) {
+        self.close_start_tag();
+        if self.indenting() && self.written {
+            self.write_indent(self.stack.len());
+        }
+        let l = self.stack.len();
+        if l > 0 {
+            self.stack[l-1].has_children = true;
+        }
+        self.written = true;
+    }
+
+    fn write_indent(&mut self, depth: uint) {
+        match self.opts.indent {
+            Some(ref indent) => {
+                write!(&mut self.writer, "{}", self.opts.newline);
+                for _ in range(0, depth) {
+                    write!(&mut self.writer, "{}", *indent);
+                }
+            }
+            None => ()
+        }
+    }
+
+    // Binds `prefix` to `ns` on the current element, adding the declaration to `declared`
+    fn declare(&mut self, prefix: &str, ns: &str, declared: &mut ~[(~str, ~str)]) {
+        self.scope.bind(prefix, ns);
+        declared.push((prefix.to_owned(), ns.to_owned()));
+    }
+
+    // Returns a prefix for `ns`, declaring a new one if none is in scope
+    fn prefix_or_declare(&mut self, ns: &str, hint: Option<~str>, declared: &mut ~[(~str, ~str)]) -> ~str {
+        if ns == XML_NS {
+            return ~"xml";
+        }
+        match hint {
+            Some(ref p) if !p.is_empty() && self.scope.namespace_for(*p) == Some(ns) => {
+                return p.clone();
+            }
+            Some(ref p) if !p.is_empty() && !declared.iter().any(|&(ref d, _)| d == p) => {
+                self.declare(*p, ns, declared);
+                return p.clone();
+            }
+            _ => match self.scope.prefix_for(ns) {
+                Some(p) => return p,
+                None => ()
+            }
+        }
+        let p = self.scope.generate_prefix();
+        self.declare(p, ns, declared);
+        p
+    }
+
+    fn start_tag(&mut self, tag: StartTag) -> Result<(), ~str> {
+        if self.stack.is_empty() && self.root_written {
+            return Err(~"Document contains more than one root element");
+        }
+        // The bindings made for a tag which can not be written are dropped again
+        self.scope.push();
+        let result = self.write_start_tag(tag);
+        if result.is_err() {
+            self.scope.pop();
+        }
+        result
+    }
+
+    fn write_start_tag(&mut self, tag: StartTag) -> Result<(), ~str> {
+        let StartTag { name, ns, prefix, attributes } = tag;
+        let mut declared = ~[];
+        let mut plain = ~[];
+        let mut default_declared = false;
+
+        // Explicit declarations are kept as they are
+        for attr in attributes.move_iter() {
+            if attr.ns == None && attr.name.as_slice() == "xmlns" {
+                self.scope.bind("", attr.value);
+                default_declared = true;
+            } else if attr.ns == Some(XMLNS_NS.to_owned()) {
+                self.declare(attr.name.as_slice(), attr.value, &mut declared);
+            } else {
+                plain.push(attr);
+            }
+        }
+
+        let qname = match ns {
+            None => {
+                if self.scope.namespace_for("") != Some("") {
+                    self.scope.bind("", "");
+                    default_declared = true;
+                }
+                name.to_str()
+            }
+            Some(ref uri) => {
+                if prefix.is_none() && self.scope.namespace_for("") == Some(uri.as_slice()) {
+                    name.to_str()
+                } else if prefix.is_none() && !default_declared {
+                    self.scope.bind("", *uri);
+                    default_declared = true;
+                    name.to_str()
+                } else {
+                    let p = self.prefix_or_declare(*uri, prefix.clone(), &mut declared);
+                    format!("{}:{}", p, name)
+                }
+            }
+        };
+        check!(check_qname(qname));
+
+        let q = self.opts.quote;
+        let mut names: ~[~str] = ~[];
+        let mut values = ~[];
+        for attr in plain.move_iter() {
+            let attr_name = match attr.ns {
+                None => attr.name.to_str(),
+                Some(ref uri) => {
+                    let p = self.prefix_or_declare(*uri, None, &mut declared);
+                    format!("{}:{}", p, attr.name)
+                }
+            };
+            check!(check_qname(attr_name));
+            if names.contains(&attr_name) {
+                return Err(format!("Duplicate attribute '{}'", attr_name));
+            }
+            names.push(attr_name);
+            values.push(attr.value);
+        }
+
+        // All values are escaped before the tag is written, so nothing is written
+        // if one of them can not be
+        let mut escaped = {
+            let mut raw: ~[&str] = ~[];
+            if default_declared {
+                // The empty prefix is always bound, an empty namespace meaning none
+                raw.push(self.scope.namespace_for("").unwrap());
+            }
+            for &(_, ref ns) in declared.iter() {
+                raw.push(ns.as_slice());
+            }
+            for value in values.iter() {
+                raw.push(value.as_slice());
+            }
+            let mut escaped = ~[];
+            for value in raw.iter() {
+                escaped.push(check!(escape_attribute(*value, q, &self.opts.escape)));
+            }
+            escaped.move_iter()
+        };
+
+        // The tag is valid, so only now is the indentation before it written
+        if self.stack.is_empty() {
+            self.root_written = true;
+        }
+        self.begin_node();
+        write!(&mut self.writer, "<{}", qname);
+        if default_declared {
+            write!(&mut self.writer, " xmlns={}{}{}", q, escaped.next().unwrap(), q);
+        }
+        for &(ref p, _) in declared.iter() {
+            write!(&mut self.writer, " xmlns:{}={}{}{}", *p, q, escaped.next().unwrap(), q);
+        }
+        for (name, value) in names.iter().zip(escaped) {
+            write!(&mut self.writer, " {}={}{}{}", *name, q, value, q);
+        }
+        self.tag_open = true;
+
+        self.stack.push(OpenElement {
+            name: name,
+            ns: ns,
+            qname: qname,
+            has_children: false,
+            mixed: false
+        });
+        Ok(())
+    }
+
+    fn end_tag(&mut self, tag: EndTag) -> Result<(), ~str> {
+        match self.stack.last_opt() {
+            Some(elem) if elem.name == tag.name && elem.ns == tag.ns => (),
+            _ => return Err(~"Elements not properly nested")
+        }
+
+        let elem = self.stack.pop();
+        self.scope.pop();
+
+        if self.tag_open {
+            self.tag_open = false;
+            if self.opts.self_closing {
+                write!(&mut self.writer, "/>");
+            } else {
+                write!(&mut self.writer, "></{}>", elem.qname);
+            }
+            return Ok(());
+        }
+
+        if self.opts.indent.is_some() && elem.has_children && !elem.mixed {
+            self.write_indent(self.stack.len());
+        }
+        write!(&mut self.writer, "</{}>", elem.qname);
+        Ok(())
+    }
+}
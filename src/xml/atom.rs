@@ -0,0 +1,114 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use std::borrow;
+use std::fmt;
+use std::hashmap::HashMap;
+use std::to_bytes::{IterBytes, Cb};
+use extra::arc::Arc;
+
+/// A name of an element or attribute.
+/// Atoms handed out by an `Atoms` table share a single copy of each name, so
+/// cloning them does not allocate and equal atoms of the same table compare
+/// without looking at the strings.
+#[deriving(Clone)]
+pub struct Atom {
+    priv name: Arc<~str>
+}
+
+impl Atom {
+    /// Returns a new `Atom` for `name`, which is not shared with any table
+    pub fn new(name: &str) -> Atom {
+        Atom { name: Arc::new(name.to_owned()) }
+    }
+
+    /// Returns the name as a string slice
+    pub fn as_slice<'a>(&'a self) -> &'a str {
+        self.name.get().as_slice()
+    }
+}
+
+impl Eq for Atom {
+    fn eq(&self, other: &Atom) -> bool {
+        // Atoms from the same table share their name, others rarely have the same length
+        let (a, b) = (self.name.get(), other.name.get());
+        borrow::ref_eq(a, b) || (a.len() == b.len() && a.as_slice() == b.as_slice())
+    }
+}
+
+impl TotalEq for Atom {
+    fn equals(&self, other: &Atom) -> bool {
+        *self == *other
+    }
+}
+
+impl Ord for Atom {
+    fn lt(&self, other: &Atom) -> bool {
+        self.as_slice() < other.as_slice()
+    }
+}
+
+impl TotalOrd for Atom {
+    fn cmp(&self, other: &Atom) -> Ordering {
+        self.as_slice().cmp(&other.as_slice())
+    }
+}
+
+impl IterBytes for Atom {
+    fn iter_bytes(&self, lsb0: bool, f: Cb) -> bool {
+        self.as_slice().iter_bytes(lsb0, f)
+    }
+}
+
+impl ToStr for Atom {
+    fn to_str(&self) -> ~str {
+        self.as_slice().to_owned()
+    }
+}
+
+impl fmt::Default for Atom {
+    fn fmt(value: &Atom, f: &mut fmt::Formatter) {
+        write!(f.buf, "{}", value.as_slice())
+    }
+}
+
+/// A table of interned names.
+/// `Parser` interns the names of all elements and attributes it reads, so the
+/// `Element`s built from its events share their names. Looking names up before
+/// parsing allows comparing them to the names read cheaply:
+///
+/// ~~~
+/// let mut p = Parser::new();
+/// let field = p.atoms().intern("field");
+/// [...]
+/// if elem.name == field { [...] }
+/// ~~~
+pub struct Atoms {
+    priv table: HashMap<~str, Atom>
+}
+
+impl Atoms {
+    /// Returns a new, empty `Atoms` table
+    pub fn new() -> Atoms {
+        Atoms { table: HashMap::new() }
+    }
+
+    /// Returns the `Atom` for `name`, adding it to the table if necessary
+    pub fn intern(&mut self, name: &str) -> Atom {
+        match self.table.find_equiv(&name) {
+            Some(atom) => return atom.clone(),
+            None => ()
+        }
+        let atom = Atom::new(name);
+        self.table.insert(name.to_owned(), atom.clone());
+        atom
+    }
+
+    /// Returns the number of names in the table
+    pub fn len(&self) -> uint {
+        self.table.len()
+    }
+}
@@ -5,9 +5,16 @@
 // Please see the COPYING file for more information.
 
 use std::str;
+use std::char;
+use std::cmp;
 use std::fmt;
+use std::num::from_str_radix;
+use super::serialize::{write_element, write_node, SerializeOptions};
 use std::to_str::ToStr;
-use std::hashmap::HashMap;
+use std::util;
+use super::namespace::XMLNS_NS;
+use super::atom::Atom;
+use super::encoding::{Encoding, UTF8};
 
 condition! {
     pub unrecognized_entity: (~str) -> ~str;
@@ -33,9 +40,153 @@ pub fn escape(input: &str) -> ~str {
     result
 }
 
+#[deriving(Clone,Eq)]
+/// Options controlling how character data and attribute values are escaped
+pub struct EscapeOptions {
+    /// Whether `'` and `"` are always escaped as `&apos;` and `&quot;`, as in the
+    /// files produced by Salesforce. Otherwise only the quote delimiting an
+    /// attribute value is escaped.
+    escape_quotes: bool,
+    /// The encoding the output is written in. Characters it can not represent are
+    /// written as character references.
+    encoding: Encoding,
+    /// Whether characters not allowed in XML 1.0 are replaced by U+FFFD, as done by
+    /// `to_str`. Otherwise they are an error.
+    replace_invalid: bool
+}
+
+impl EscapeOptions {
+    /// Returns options escaping only what is necessary, for UTF-8 output
+    pub fn minimal() -> EscapeOptions {
+        EscapeOptions { escape_quotes: false, encoding: UTF8, replace_invalid: false }
+    }
+
+    /// Returns options matching the files produced by Salesforce, which escape
+    /// quotes everywhere, for UTF-8 output
+    pub fn salesforce() -> EscapeOptions {
+        EscapeOptions { escape_quotes: true, encoding: UTF8, replace_invalid: false }
+    }
+}
+
+// Whether `c` is a control character XML 1.0 allows but discourages
+fn is_discouraged(c: char) -> bool {
+    (c >= '\x7f' && c <= '\x84') || (c >= '\x86' && c <= '\x9f')
+}
+
+// Escapes `input`, writing characters as returned by `special` where it returns a
+// replacement. Discouraged characters and those the encoding can not represent
+// are written as character references.
+fn escape_with(input: &str, opts: &EscapeOptions,
+               special: |char| -> Option<&'static str>) -> Result<~str, ~str> {
+    let mut result = str::with_capacity(input.len());
+
+    for c in input.chars() {
+        match special(c) {
+            Some(s) => result.push_str(s),
+            None if !is_xml_char(c) && opts.replace_invalid => result.push_char('\uFFFD'),
+            None if !is_xml_char(c) => return Err(format!("Invalid character 0x{:x}", c as uint)),
+            None if is_discouraged(c) || !opts.encoding.can_encode(c) => {
+                result.push_str(format!("&#x{:X};", c as uint))
+            }
+            None => result.push_char(c)
+        }
+    }
+    Ok(result)
+}
+
+/// Escapes character data according to `opts`.
+/// `&`, `<` and `>` are replaced by entities, and carriage returns by character
+/// references, so they are not read back as line breaks. Returns an error for
+/// characters not allowed in XML 1.0, unless they are replaced.
+pub fn escape_text(input: &str, opts: &EscapeOptions) -> Result<~str, ~str> {
+    escape_with(input, opts, |c| {
+        match c {
+            '&' => Some("&amp;"),
+            '<' => Some("&lt;"),
+            '>' => Some("&gt;"),
+            '\r' => Some("&#13;"),
+            '\'' if opts.escape_quotes => Some("&apos;"),
+            '"' if opts.escape_quotes => Some("&quot;"),
+            _ => None
+        }
+    })
+}
+
+/// Escapes an attribute value delimited by `quote` according to `opts`.
+/// Besides `&`, `<`, `>` and the quote, tabs and line breaks are replaced by
+/// character references, as they would be read back as spaces otherwise.
+/// Returns an error for characters not allowed in XML 1.0, unless they are replaced.
+pub fn escape_attribute(input: &str, quote: char, opts: &EscapeOptions) -> Result<~str, ~str> {
+    escape_with(input, opts, |c| {
+        match c {
+            '&' => Some("&amp;"),
+            '<' => Some("&lt;"),
+            '>' => Some("&gt;"),
+            '\t' => Some("&#9;"),
+            '\n' => Some("&#10;"),
+            '\r' => Some("&#13;"),
+            '\'' if opts.escape_quotes || quote == '\'' => Some("&apos;"),
+            '"' if opts.escape_quotes || quote == '"' => Some("&quot;"),
+            _ => None
+        }
+    })
+}
+
+// Checks text which is written without escaping, like comments and CDATA sections,
+// only contains characters allowed in XML 1.0 and representable in the encoding.
+// Returns the text, with characters not allowed replaced if `opts` says so.
+pub fn check_unescaped(input: &str, opts: &EscapeOptions) -> Result<~str, ~str> {
+    let mut result = str::with_capacity(input.len());
+    for c in input.chars() {
+        if !is_xml_char(c) {
+            if !opts.replace_invalid {
+                return Err(format!("Invalid character 0x{:x}", c as uint));
+            }
+            result.push_char('\uFFFD');
+            continue;
+        }
+        if !opts.encoding.can_encode(c) {
+            return Err(format!("Character 0x{:x} can not be written in {}", c as uint,
+                               opts.encoding.name()));
+        }
+        result.push_char(c);
+    }
+    Ok(result)
+}
+
 #[inline]
-/// Unescapes all valid XML entities in a string.
+/// Unescapes all valid XML entities and character references in a string.
+/// Other references are passed to the `unrecognized_entity` condition. A trailing
+/// '&' without a closing ';' is kept as it is.
 pub fn unescape(input: &str) -> ~str {
+    unescape_with(input, |ent| {
+        if ent.ends_with(";") {
+            Ok(unrecognized_entity::cond.raise(ent.to_owned()))
+        } else {
+            Ok(ent.to_owned())
+        }
+    }).unwrap()
+}
+
+/// Unescapes all valid XML entities and character references in a string like
+/// `unescape`, but returns an error for a reference to an entity other than the
+/// five predefined ones, to a character not allowed in XML, or lacking its ';'.
+pub fn try_unescape(input: &str) -> Result<~str, ~str> {
+    unescape_with(input, |ent| {
+        if !ent.ends_with(";") {
+            Err(format!("Unterminated reference '{}'", ent))
+        } else if ent.starts_with("&#") {
+            Err(format!("Invalid reference '{}'", ent))
+        } else {
+            Err(format!("Unknown entity '{}'", ent))
+        }
+    })
+}
+
+// Unescapes `input`, replacing references which are neither predefined nor valid
+// character references with the result of `unknown`, which is also called with
+// a reference the input ends in before its ';'
+fn unescape_with(input: &str, unknown: |&str| -> Result<~str, ~str>) -> Result<~str, ~str> {
     let mut result = str::with_capacity(input.len());
 
     let mut ent = ~"";
@@ -65,15 +216,105 @@ pub fn unescape(input: &str) -> ~str {
             } else if ent_s == "&amp;" {
                 result.push_char('&');
             } else {
-                result.push_str(unrecognized_entity::cond.raise(ent.clone()));
+                let resolved = if ent_s.starts_with("&#") {
+                    char_reference(ent_s.slice(2, ent_s.len()-1))
+                } else {
+                    None
+                };
+                match resolved {
+                    Some(c) => result.push_char(c),
+                    None => result.push_str(check!(unknown(ent_s)))
+                }
             }
             in_entity = false;
         }
     }
-    result
+    if in_entity {
+        result.push_str(check!(unknown(ent)));
+    }
+    Ok(result)
+}
+
+// Resolves the body of a character reference, e.g. "x41" or "65"
+pub fn char_reference(body: &str) -> Option<char> {
+    let code = if body.starts_with("x") {
+        from_str_radix::<u32>(body.slice_from(1), 16)
+    } else {
+        from_str_radix::<u32>(body, 10)
+    };
+    match code.and_then(|c| char::from_u32(c)) {
+        Some(c) if is_xml_char(c) => Some(c),
+        _ => None
+    }
+}
+
+/// Returns true if `c` is allowed to appear in an XML 1.0 document.
+pub fn is_xml_char(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' | ' '..'\ud7ff' | '\ue000'..'\ufffd' | '\U00010000'..'\U0010ffff' => true,
+        _ => false
+    }
+}
+
+/// Returns true if `c` may start an XML name.
+pub fn is_name_start_char(c: char) -> bool {
+    match c {
+        ':' | 'A'..'Z' | '_' | 'a'..'z' | '\u00c0'..'\u00d6' | '\u00d8'..'\u00f6'
+        | '\u00f8'..'\u02ff' | '\u0370'..'\u037d' | '\u037f'..'\u1fff' | '\u200c'..'\u200d'
+        | '\u2070'..'\u218f' | '\u2c00'..'\u2fef' | '\u3001'..'\ud7ff' | '\uf900'..'\ufdcf'
+        | '\ufdf0'..'\ufffd' | '\U00010000'..'\U000effff' => true,
+        _ => false
+    }
+}
+
+/// Returns true if `c` may appear in an XML name after its first character.
+pub fn is_name_char(c: char) -> bool {
+    is_name_start_char(c) || match c {
+        '-' | '.' | '0'..'9' | '\u00b7' | '\u0300'..'\u036f' | '\u203f'..'\u2040' => true,
+        _ => false
+    }
+}
+
+/// Returns true if `c` is whitespace as defined by the XML `S` production.
+#[inline]
+pub fn is_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || c == '\n'
+}
+
+/// Returns true if `name` matches the XML `Name` production.
+pub fn is_name(name: &str) -> bool {
+    name.len() > 0 && is_name_start_char(name.char_at(0)) && name.chars().all(is_name_char)
+}
+
+// Applies `mode` to the text of a character node, `None` meaning the node is dropped
+pub fn apply_whitespace(mode: WhitespaceMode, text: ~str) -> Option<~str> {
+    match mode {
+        KeepWhitespace => Some(text),
+        DropWhitespace if text.chars().all(is_space) => None,
+        DropWhitespace => Some(text),
+        TrimWhitespace => {
+            let start = match text.find(|c: char| !is_space(c)) {
+                Some(start) => start,
+                None => return None
+            };
+            let last = text.rfind(|c: char| !is_space(c)).unwrap();
+            Some(text.slice(start, text.char_range_at(last).next).to_owned())
+        }
+    }
 }
 
 // General types
+#[deriving(Clone,Eq)]
+/// How whitespace in character data is handed to consumers
+pub enum WhitespaceMode {
+    /// Report all character data unchanged
+    KeepWhitespace,
+    /// Drop character data consisting only of whitespace
+    DropWhitespace,
+    /// Strip leading and trailing whitespace, dropping character data left empty
+    TrimWhitespace
+}
+
 #[deriving(Clone,Eq)]
 /// An Enum describing a XML Node
 pub enum XML {
@@ -86,31 +327,69 @@ pub enum XML {
     /// A XML Comment
     CommentNode(~str),
     /// Processing Information
-    PINode(~str)
+    PINode(PI)
 }
 
-#[deriving(Clone,Eq)]
-/// A struct representing an XML element
+#[deriving(Clone)]
+/// A struct representing an XML element.
+/// Elements are equal if their names, attributes and children are, regardless
+/// of where or how they were read.
 pub struct Element {
     /// The element's name
-    name: ~str,
-    /// The element's namespace
+    name: Atom,
+    /// The element's namespace. Prefixes are chosen when the element is written.
     ns: Option<~str>,
-    /// The element's default namespace
-    default_ns: Option<~str>,
-    /// The prefixes set for known namespaces
-    prefixes: HashMap<~str, ~str>,
+    /// The prefix the element was read with. It is reused when the element is
+    /// written if it is still bound to the element's namespace, or not bound at all.
+    prefix: Option<~str>,
     /// The element's `Attribute`s
     attributes: ~[Attribute],
     /// The element's child `XML` nodes
     children: ~[XML],
+    /// Where the element was read from, if recorded by the `ElementBuilder`.
+    /// Boxed, as most elements are built without it.
+    span: Option<~Span>,
+    /// The text the element was read from, if recorded in lossless mode
+    trivia: Option<~Trivia>
+}
+
+impl Eq for Element {
+    fn eq(&self, other: &Element) -> bool {
+        self.name == other.name && self.ns == other.ns &&
+        self.attributes == other.attributes && self.children == other.children
+    }
+}
+
+#[deriving(Clone,Eq)]
+/// The source text of an `Element`, as recorded by `parse_lossless`.
+/// `write_lossless` writes the recorded text for every part of the element
+/// which is unchanged.
+pub struct Trivia {
+    /// The start tag as written. For an element written as `<a/>` this is the
+    /// whole element.
+    start_tag: ~str,
+    /// The end tag as written, `None` for an element written as `<a/>`
+    end_tag: Option<~str>,
+    /// The name read from the start tag
+    name: Atom,
+    /// The namespace read from the start tag
+    ns: Option<~str>,
+    /// The attributes read from the start tag
+    attributes: ~[Attribute],
+    /// The child nodes other than elements, each with the text it was read from
+    children: ~[(XML, ~str)],
+    /// The text before the root element, e.g. the XML declaration and DOCTYPE.
+    /// Empty for all other elements.
+    before: ~str,
+    /// The text after the root element. Empty for all other elements.
+    after: ~str
 }
 
 #[deriving(Clone,Eq)]
 /// A struct representing an XML attribute
 pub struct Attribute {
     /// The attribute's name
-    name: ~str,
+    name: Atom,
     /// The attribute's namespace
     ns: Option<~str>,
     /// The attribute's value
@@ -120,8 +399,10 @@ pub struct Attribute {
 #[deriving(Eq)]
 /// Events returned by the `Parser`
 pub enum Event {
+    /// Event indicating the XML declaration was found
+    XmlDecl(XmlDecl),
     /// Event indicating processing information was found
-    PI(~str),
+    PI(PI),
     /// Event indicating a start tag was found
     StartTag(StartTag),
     /// Event indicating a end tag was found
@@ -134,11 +415,34 @@ pub enum Event {
     Comment(~str)
 }
 
+#[deriving(Clone,Eq)]
+/// Structure describing the XML declaration at the start of a document
+pub struct XmlDecl {
+    /// The XML version, usually "1.0"
+    version: ~str,
+    /// The declared encoding, if any
+    encoding: Option<~str>,
+    /// The declared standalone status, if any
+    standalone: Option<bool>
+}
+
+#[deriving(Clone,Eq)]
+/// Structure describing a processing instruction
+pub struct PI {
+    /// The name of the application the instruction is meant for
+    target: ~str,
+    /// The whitespace between the target and the data, kept so the instruction
+    /// is written back unchanged
+    space: ~str,
+    /// The rest of the instruction, after the whitespace following the target
+    data: ~str
+}
+
 #[deriving(Eq)]
 /// Structure describint an opening tag
 pub struct StartTag {
     /// The tag's name
-    name: ~str,
+    name: Atom,
     /// The tag's namespace
     ns: Option<~str>,
     /// The tag's prefix
@@ -151,13 +455,53 @@ pub struct StartTag {
 /// Structure describint n closing tag
 pub struct EndTag {
     /// The tag's name
-    name: ~str,
+    name: Atom,
     /// The tag's namespace
     ns: Option<~str>,
     /// The tag's prefix
     prefix: Option<~str>
 }
 
+#[deriving(Clone,Eq)]
+/// A position in the parsed text
+pub struct Position {
+    /// The byte offset from the start of the text
+    offset: uint,
+    /// The line number, starting at 1
+    line: uint,
+    /// The column number, starting at 1
+    col: uint
+}
+
+#[deriving(Clone,Eq)]
+/// The region of the parsed text an `Event` or `Element` was read from
+pub struct Span {
+    /// The position of the first character
+    start: Position,
+    /// The position just past the last character
+    end: Position
+}
+
+#[deriving(Clone,Eq)]
+/// The kind of an `Error`, telling malformed documents apart from documents
+/// exceeding one of the limits of a `ParserConfig`
+pub enum ErrorKind {
+    /// The document is not well-formed
+    SyntaxError,
+    /// Elements are nested deeper than `max_depth`
+    DepthLimitExceeded,
+    /// An element has more attributes than `max_attributes`
+    AttributeLimitExceeded,
+    /// A name is longer than `max_name_length`
+    NameLengthLimitExceeded,
+    /// Character data or markup text is longer than `max_text_size`
+    TextSizeLimitExceeded,
+    /// The document is larger than `max_document_size`
+    DocumentSizeLimitExceeded,
+    /// More references were expanded than `max_entity_expansions`
+    EntityExpansionLimitExceeded
+}
+
 #[deriving(Eq)]
 /// If an error occurs while parsing some XML, this is the structure which is
 /// returned
@@ -167,7 +511,60 @@ pub struct Error {
     /// The column number at which the error occurred
     col: uint,
     /// A message describing the type of the error
-    msg: ~str
+    msg: ~str,
+    /// Whether the document is malformed or exceeded a limit
+    kind: ErrorKind
+}
+
+// Renders line `line` of `source` with `len` carets below it, starting at column `col`.
+// A `len` of 0 underlines up to the end of the line. Line 0, which no text was
+// read from, renders as an empty string.
+fn render_snippet(source: &str, line: uint, col: uint, len: uint) -> ~str {
+    if line == 0 {
+        return ~"";
+    }
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let col = cmp::max(col, 1);
+    let gutter = line.to_str();
+
+    let mut result = format!("{} | {}\n", gutter, text);
+    result.push_str(" ".repeat(gutter.len()));
+    result.push_str(" | ");
+    // Keep tabs, so the carets line up with the text above
+    for c in text.chars().take(col - 1) {
+        result.push_char(if c == '\t' { '\t' } else { ' ' });
+    }
+    let len = if len > 0 {
+        len
+    } else {
+        cmp::max(text.char_len() as int - (col - 1) as int, 1) as uint
+    };
+    result.push_str("^".repeat(len));
+    result
+}
+
+impl Span {
+    /// Renders the line of `source` the span starts on, underlining the span with carets.
+    /// Spans covering several lines are underlined up to the end of their first line.
+    /// `source` must be the text the span was recorded from. A span without a
+    /// position, starting on line 0, renders as an empty string.
+    pub fn snippet(&self, source: &str) -> ~str {
+        let len = if self.end.line == self.start.line && self.end.col > self.start.col {
+            self.end.col - self.start.col
+        } else {
+            0
+        };
+        render_snippet(source, self.start.line, self.start.col, len)
+    }
+}
+
+impl Error {
+    /// Renders the line of `source` the error occurred on, with a caret below
+    /// the offending character. Errors without a position, on line 0, render
+    /// as an empty string.
+    pub fn snippet(&self, source: &str) -> ~str {
+        render_snippet(source, self.line, self.col, 1)
+    }
 }
 
 impl ToStr for XML {
@@ -184,76 +581,127 @@ impl ToStr for Element {
     }
 }
 
+// The options nodes are displayed with. Characters not allowed in XML are replaced
+// by U+FFFD, so writing UTF-8 does not fail. `serialize` handles them as an error.
+fn display_options() -> SerializeOptions {
+    let mut opts = SerializeOptions::compact();
+    opts.escape.replace_invalid = true;
+    opts
+}
+
 impl fmt::Default for XML {
     fn fmt(value: &XML, f: &mut fmt::Formatter) {
-        match *value {
-            Element(ref elem) => fmt::Default::fmt(elem, f),
-            CharacterNode(ref data) => write!(f.buf, "{}", escape(*data)),
-            CDATANode(ref data) => write!(f.buf, "<![CDATA[{}]]>", *data),
-            CommentNode(ref data) => write!(f.buf, "<!--{}-->", *data),
-            PINode(ref data) => write!(f.buf, "<?{}?>", *data)
+        let result = match *value {
+            Element(ref elem) => write_element(f.buf, elem, &display_options()),
+            ref node => write_node(f.buf, node, &display_options())
+        };
+        match result {
+            Ok(()) => (),
+            Err(_) => ()
         }
     }
 }
 
-fn fmt_elem(elem: &Element, parent: Option<&Element>, all_prefixes: &HashMap<~str, ~str>,
-            f: &mut fmt::Formatter) {
-    write!(f.buf, "<");
-
-    let mut all_prefixes = all_prefixes.clone();
-    all_prefixes.extend(&mut elem.prefixes.iter().map(|(k, v)| (k.clone(), v.clone()) ));
-
-    // Do we need a prefix?
-    if elem.ns != elem.default_ns {
-        let prefix = all_prefixes.find(elem.ns.get_ref()).expect("No namespace prefix bound");
-        write!(f.buf, "{}:", *prefix);
+impl fmt::Default for XmlDecl {
+    fn fmt(value: &XmlDecl, f: &mut fmt::Formatter) {
+        write!(f.buf, "<?xml version=\"{}\"", value.version);
+        match value.encoding {
+            Some(ref enc) => write!(f.buf, " encoding=\"{}\"", *enc),
+            None => ()
+        }
+        match value.standalone {
+            Some(true) => write!(f.buf, " standalone=\"yes\""),
+            Some(false) => write!(f.buf, " standalone=\"no\""),
+            None => ()
+        }
+        write!(f.buf, "?>");
     }
+}
 
-    write!(f.buf, "{}", elem.name);
+impl fmt::Default for PI {
+    fn fmt(value: &PI, f: &mut fmt::Formatter) {
+        let space = if value.space.is_empty() && !value.data.is_empty() {
+            " "
+        } else {
+            value.space.as_slice()
+        };
+        write!(f.buf, "<?{}{}{}?>", value.target, space, value.data);
+    }
+}
 
-    // Do we need to set the default namespace ?
-    if (parent.is_none() && elem.default_ns.is_some()) ||
-       (parent.is_some() && parent.unwrap().default_ns != elem.default_ns) {
-        match elem.default_ns {
-            None => write!(f.buf, " xmlns=''"),
-            Some(ref x) => write!(f.buf, " xmlns='{}'", *x)
+impl PI {
+    /// Returns a new processing instruction for `target`, with `data` separated
+    /// from it by a single space
+    pub fn new(target: &str, data: &str) -> PI {
+        PI {
+            target: target.to_owned(),
+            space: if data.is_empty() { ~"" } else { ~" " },
+            data: data.to_owned()
         }
     }
 
-    for attr in elem.attributes.iter() {
-        write!(f.buf, " ");
-        match attr.ns {
-            Some(ref ns) => {
-                let prefix = all_prefixes.find(ns).expect("No namespace prefix bound");
-                write!(f.buf, "{}:", *prefix);
+    /// Parses the data as a list of pseudo-attributes, as used by `xml-stylesheet`
+    /// and similar instructions: `name="value"` pairs separated by whitespace.
+    /// References in the values are replaced, a reference to an unknown entity is
+    /// an error.
+    pub fn pseudo_attributes(&self) -> Result<~[(~str, ~str)], ~str> {
+        let mut attrs: ~[(~str, ~str)] = ~[];
+        let mut rest = self.data.as_slice();
+
+        loop {
+            let trimmed = rest.trim_left();
+            if trimmed.len() == 0 {
+                break;
+            }
+            if attrs.len() > 0 && trimmed.len() == rest.len() {
+                return Err(~"Expected whitespace between pseudo-attributes");
             }
-            None => ()
-        };
-        write!(f.buf, "{}='{}'", attr.name, escape(attr.value));
-    }
 
-    if elem.children.len() == 0 {
-        write!(f.buf, "/>");
-    } else {
-        write!(f.buf, ">");
-        for child in elem.children.iter() {
-            match *child {
-                Element(ref child) => fmt_elem(child, Some(elem), &all_prefixes, f),
-                ref o => fmt::Default::fmt(o, f)
+            let eq = match trimmed.find('=') {
+                Some(i) => i,
+                None => return Err(~"Malformed pseudo-attribute")
+            };
+            let name = trimmed.slice_to(eq).trim_right();
+            if !is_name(name) {
+                return Err(format!("Invalid pseudo-attribute name '{}'", name));
             }
+            if attrs.iter().any(|&(ref n, _)| n.as_slice() == name) {
+                return Err(format!("Duplicate pseudo-attribute '{}'", name));
+            }
+
+            let value = trimmed.slice_from(eq + 1).trim_left();
+            let quote = match value.chars().next() {
+                Some(q) if q == '"' || q == '\'' => q,
+                _ => return Err(~"Pseudo-attribute value not enclosed in ' or \"")
+            };
+            let value = value.slice_from(1);
+            let end = match value.find(quote) {
+                Some(i) => i,
+                None => return Err(~"Malformed pseudo-attribute")
+            };
+            attrs.push((name.to_owned(), check!(try_unescape(value.slice_to(end)))));
+            rest = value.slice_from(end + 1);
         }
-        write!(f.buf, "</");
-        if elem.ns != elem.default_ns {
-            let prefix = all_prefixes.find(elem.ns.get_ref()).expect("No namespace prefix bound");
-            write!(f.buf, "{}:", *prefix);
+        Ok(attrs)
+    }
+
+    /// Returns the value of the pseudo-attribute `name`.
+    /// Returns `None` if there is no such pseudo-attribute, or the data does not
+    /// consist of pseudo-attributes with known references.
+    pub fn pseudo_attribute(&self, name: &str) -> Option<~str> {
+        match self.pseudo_attributes() {
+            Ok(attrs) => attrs.move_iter().find(|&(ref n, _)| n.as_slice() == name).map(|(_, v)| v),
+            Err(_) => None
         }
-        write!(f.buf, "{}>", elem.name);
     }
 }
 
 impl fmt::Default for Element{
     fn fmt(value: &Element, f: &mut fmt::Formatter) {
-        fmt_elem(value, None, &HashMap::new(), f)
+        match write_element(f.buf, value, &display_options()) {
+            Ok(()) => (),
+            Err(_) => ()
+        }
     }
 }
 
@@ -284,7 +732,7 @@ impl Element {
       -> Option<&'a Attribute> {
         for i in range(0, self.attributes.len()) {
             let attr: &'a Attribute = &self.attributes[i];
-            if name == attr.name && ns == attr.ns {
+            if name == attr.name.as_slice() && ns == attr.ns {
                 return Some(attr);
             }
         }
@@ -304,7 +752,9 @@ impl Element {
         for i in range(0, self.children.len()) {
             let child: &'a XML = &self.children[i];
             match *child {
-                Element(ref elem) if name == elem.name && ns == elem.ns => return Some(&*elem),
+                Element(ref elem) if name == elem.name.as_slice() && ns == elem.ns => {
+                    return Some(&*elem)
+                }
                 _ => ()
             }
         }
@@ -324,10 +774,173 @@ impl Element {
         for i in range(0, self.children.len()) {
             let child: &'a XML = &self.children[i];
             match *child {
-                Element(ref elem) if name == elem.name && ns == elem.ns => res.push(&*elem),
+                Element(ref elem) if name == elem.name.as_slice() && ns == elem.ns => {
+                    res.push(&*elem)
+                }
                 _ => ()
             }
         }
         res
     }
+
+    // Editing
+
+    /// Returns a new `Element` without namespace, attributes or children
+    pub fn new(name: &str) -> Element {
+        Element {
+            name: Atom::new(name),
+            ns: None,
+            prefix: None,
+            attributes: ~[],
+            children: ~[],
+            span: None,
+            trivia: None
+        }
+    }
+
+    /// Places the element in the namespace `ns` and returns it
+    pub fn ns(self, ns: &str) -> Element {
+        let mut elem = self;
+        elem.ns = Some(ns.to_owned());
+        elem
+    }
+
+    /// Sets the attribute `name` to `value` and returns the element
+    pub fn attr(self, name: &str, value: &str) -> Element {
+        let mut elem = self;
+        elem.set_attribute(name, value);
+        elem
+    }
+
+    /// Replaces the children of the element with the character data `text` and
+    /// returns the element
+    pub fn text(self, text: &str) -> Element {
+        let mut elem = self;
+        elem.set_text(text);
+        elem
+    }
+
+    /// Appends `child` to the children of the element and returns the element
+    pub fn child(self, child: Element) -> Element {
+        let mut elem = self;
+        elem.append_child(Element(child));
+        elem
+    }
+
+    /// Appends `child` to the children of the element
+    pub fn append_child(&mut self, child: XML) {
+        self.children.push(child);
+    }
+
+    /// Inserts `child` at position `index` of the children, shifting later children.
+    /// Fails if `index` is greater than the number of children.
+    pub fn insert_child(&mut self, index: uint, child: XML) {
+        self.children.insert(index, child);
+    }
+
+    /// Removes and returns the child at position `index`.
+    /// Fails if `index` is out of bounds.
+    pub fn remove_child(&mut self, index: uint) -> XML {
+        self.children.remove(index)
+    }
+
+    /// Replaces the child at position `index` with `child`, returning the old child.
+    /// Fails if `index` is out of bounds.
+    pub fn replace_child(&mut self, index: uint, child: XML) -> XML {
+        util::replace(&mut self.children[index], child)
+    }
+
+    /// Removes and returns the first child matching `pred`.
+    /// When no child matches `None` is returned.
+    pub fn remove_child_where(&mut self, pred: |&XML| -> bool) -> Option<XML> {
+        match self.children.iter().position(pred) {
+            Some(i) => Some(self.children.remove(i)),
+            None => None
+        }
+    }
+
+    /// Removes and returns all children matching `pred`, keeping their order
+    pub fn remove_children_where(&mut self, pred: |&XML| -> bool) -> ~[XML] {
+        let children = util::replace(&mut self.children, ~[]);
+        let (removed, kept) = children.partition(pred);
+        self.children = kept;
+        removed
+    }
+
+    /// Replaces the first child matching `pred` with `child`, returning the old child.
+    /// When no child matches `child` is dropped and `None` is returned.
+    pub fn replace_child_where(&mut self, pred: |&XML| -> bool, child: XML) -> Option<XML> {
+        match self.children.iter().position(pred) {
+            Some(i) => Some(self.replace_child(i, child)),
+            None => None
+        }
+    }
+
+    /// Removes and returns the first child `Element` with the specified name and
+    /// namespace, so it can be inserted elsewhere. When no such child exists
+    /// `None` is returned.
+    pub fn detach_child_with_name_and_ns(&mut self, name: &str, ns: Option<~str>) -> Option<Element> {
+        let removed = self.remove_child_where(|c| match *c {
+            Element(ref elem) => name == elem.name.as_slice() && ns == elem.ns,
+            _ => false
+        });
+        match removed {
+            Some(Element(elem)) => Some(elem),
+            _ => None
+        }
+    }
+
+    /// Moves the child at position `from` to position `to`, shifting the children
+    /// in between. Fails if either index is out of bounds.
+    pub fn move_child(&mut self, from: uint, to: uint) {
+        let child = self.children.remove(from);
+        self.children.insert(to, child);
+    }
+
+    /// Replaces all children of the element with the character data `text`
+    pub fn set_text(&mut self, text: &str) {
+        self.children = if text.is_empty() { ~[] } else { ~[CharacterNode(text.to_owned())] };
+    }
+
+    /// Sets the attribute `name` without namespace to `value`, adding it if necessary
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        self.set_attribute_with_ns(name, None, value)
+    }
+
+    /// Sets the attribute `name` in namespace `ns` to `value`, adding it if necessary.
+    /// A prefix for `ns` is declared when the element is written, if none is in scope.
+    pub fn set_attribute_with_ns(&mut self, name: &str, ns: Option<~str>, value: &str) {
+        for attr in self.attributes.mut_iter() {
+            if name == attr.name.as_slice() && ns == attr.ns {
+                attr.value = value.to_owned();
+                return;
+            }
+        }
+        self.attributes.push(Attribute { name: Atom::new(name), ns: ns, value: value.to_owned() });
+    }
+
+    /// Removes and returns the attribute `name` without namespace.
+    /// When no such attribute exists `None` is returned.
+    pub fn remove_attribute(&mut self, name: &str) -> Option<Attribute> {
+        self.remove_attribute_with_ns(name, None)
+    }
+
+    /// Removes and returns the attribute `name` in namespace `ns`.
+    /// When no such attribute exists `None` is returned.
+    pub fn remove_attribute_with_ns(&mut self, name: &str, ns: Option<~str>) -> Option<Attribute> {
+        match self.attributes.iter().position(|a| name == a.name.as_slice() && ns == a.ns) {
+            Some(i) => Some(self.attributes.remove(i)),
+            None => None
+        }
+    }
+
+    /// Adds a declaration binding `prefix` to the namespace `ns` on this element.
+    /// The serializer uses it for `ns` instead of inventing a prefix.
+    pub fn declare_prefix(&mut self, prefix: &str, ns: ~str) {
+        self.attributes.push(Attribute {
+            name: Atom::new(prefix),
+            ns: Some(XMLNS_NS.to_owned()),
+            value: ns
+        });
+    }
 }
@@ -0,0 +1,198 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{Element, CharacterNode, CDATANode, CommentNode, PINode};
+use super::namespace::Scope;
+use std::cmp::Equal;
+use std::hashmap::HashMap;
+use std::str;
+
+#[deriving(Clone,Eq)]
+/// The canonicalization algorithm to apply
+pub enum C14NMethod {
+    /// Canonical XML 1.0, rendering every namespace in scope
+    InclusiveC14N,
+    /// Exclusive XML Canonicalization 1.0, rendering only the namespaces visibly
+    /// used by an element, plus those in the given InclusiveNamespaces prefix list.
+    /// The default namespace is listed as "#default".
+    ExclusiveC14N(~[~str])
+}
+
+#[deriving(Clone)]
+/// Options controlling how an `Element` is canonicalized
+pub struct C14NOptions {
+    /// The canonicalization algorithm
+    method: C14NMethod,
+    /// Whether comments are kept
+    with_comments: bool
+}
+
+impl C14NOptions {
+    /// Returns the options for Canonical XML 1.0 without comments
+    pub fn canonical() -> C14NOptions {
+        C14NOptions { method: InclusiveC14N, with_comments: false }
+    }
+
+    /// Returns the options for Exclusive XML Canonicalization without comments
+    pub fn exclusive() -> C14NOptions {
+        C14NOptions { method: ExclusiveC14N(~[]), with_comments: false }
+    }
+}
+
+impl Element {
+    /// Returns the canonical form of the element, as selected by `opts`.
+    /// Namespaces declared on ancestors of the element are not known to it,
+    /// so the element should be the root of a document. Elements keep the
+    /// prefix they were read with, also when other prefixes share its namespace.
+    pub fn canonicalize(&self, opts: &C14NOptions) -> ~str {
+        let mut result = ~"";
+        let mut rendered = HashMap::new();
+        rendered.insert(~"", ~"");
+        c14n_elem(&mut result, self, &mut Scope::new(), &rendered, opts);
+        result
+    }
+}
+
+// Escapes character data as required by C14N
+fn escape_text(input: &str) -> ~str {
+    let mut result = str::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '\r' => result.push_str("&#xD;"),
+            o => result.push_char(o)
+        }
+    }
+    result
+}
+
+// Escapes attribute values as required by C14N
+fn escape_attr(input: &str) -> ~str {
+    let mut result = str::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '"' => result.push_str("&quot;"),
+            '\t' => result.push_str("&#x9;"),
+            '\n' => result.push_str("&#xA;"),
+            '\r' => result.push_str("&#xD;"),
+            o => result.push_char(o)
+        }
+    }
+    result
+}
+
+// Returns the prefix of a qualified name, "" if there is none
+fn prefix_of<'a>(qname: &'a str) -> &'a str {
+    match qname.find(':') {
+        Some(i) => qname.slice_to(i),
+        None => ""
+    }
+}
+
+// `scope` names elements and attributes and tracks the namespaces in scope.
+// `rendered` holds the declarations written on output ancestors, with ""
+// standing for the default namespace.
+fn c14n_elem(out: &mut ~str, elem: &Element, scope: &mut Scope,
+             rendered: &HashMap<~str, ~str>, opts: &C14NOptions) {
+    let names = scope.enter(elem);
+    let mut in_scope = scope.in_scope();
+    if scope.namespace_for("") == Some("") {
+        in_scope.push((~"", ~""));
+    }
+
+    // Namespace nodes to render, as prefix and namespace
+    let candidates: ~[~str] = match opts.method {
+        InclusiveC14N => in_scope.iter().map(|&(ref p, _)| p.clone()).collect(),
+        ExclusiveC14N(ref inclusive) => {
+            let mut used = ~[prefix_of(names.qname).to_owned()];
+            for &(ref name, _) in names.attributes.iter() {
+                let prefix = prefix_of(*name);
+                if !prefix.is_empty() && prefix != "xml" {
+                    used.push(prefix.to_owned());
+                }
+            }
+            for prefix in inclusive.iter() {
+                let prefix = if prefix.as_slice() == "#default" { ~"" } else { prefix.clone() };
+                used.push(prefix);
+            }
+            used
+        }
+    };
+
+    let mut namespaces = ~[];
+    for prefix in candidates.move_iter() {
+        let ns = match in_scope.iter().find(|&&(ref p, _)| *p == prefix) {
+            Some(&(_, ref ns)) => ns.clone(),
+            None => continue
+        };
+        if rendered.find(&prefix) != Some(&ns) && !namespaces.iter().any(|&(ref p, _)| *p == prefix) {
+            namespaces.push((prefix, ns));
+        }
+    }
+    namespaces.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+
+    // Attributes are sorted by namespace, then local name
+    let mut attributes = names.attributes;
+    attributes.sort_by(|&(_, a), &(_, b)| {
+        let a_ns = a.ns.as_ref().map_default("", |ns| ns.as_slice());
+        let b_ns = b.ns.as_ref().map_default("", |ns| ns.as_slice());
+        match a_ns.cmp(&b_ns) {
+            Equal => a.name.cmp(&b.name),
+            order => order
+        }
+    });
+
+    let qname = names.qname;
+    out.push_char('<');
+    out.push_str(qname);
+
+    let mut rendered = rendered.clone();
+    for &(ref prefix, ref ns) in namespaces.iter() {
+        if prefix.len() == 0 {
+            out.push_str(format!(" xmlns=\"{}\"", escape_attr(*ns)));
+        } else {
+            out.push_str(format!(" xmlns:{}=\"{}\"", *prefix, escape_attr(*ns)));
+        }
+        rendered.insert(prefix.clone(), ns.clone());
+    }
+    for &(ref name, attr) in attributes.iter() {
+        out.push_str(format!(" {}=\"{}\"", *name, escape_attr(attr.value)));
+    }
+    out.push_char('>');
+
+    for child in elem.children.iter() {
+        match *child {
+            Element(ref child) => c14n_elem(out, child, scope, &rendered, opts),
+            CharacterNode(ref data) | CDATANode(ref data) => {
+                out.push_str(escape_text(*data));
+            }
+            CommentNode(ref data) => {
+                if opts.with_comments {
+                    out.push_str("<!--");
+                    out.push_str(*data);
+                    out.push_str("-->");
+                }
+            }
+            PINode(ref pi) => {
+                // The target is separated from non-empty data by a single space
+                out.push_str("<?");
+                out.push_str(pi.target);
+                if !pi.data.is_empty() {
+                    out.push_char(' ');
+                }
+                out.push_str(pi.data);
+                out.push_str("?>");
+            }
+        }
+    }
+
+    out.push_str(format!("</{}>", qname));
+    scope.pop();
+}
@@ -0,0 +1,236 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{Element, Attribute, CharacterNode};
+
+/// Types which can be read from an `Element`
+pub trait FromXml {
+    /// Reads a value from `elem`
+    fn from_xml(elem: &Element) -> Result<Self, ~str>;
+}
+
+/// Types which can be written as an `Element`
+pub trait ToXml {
+    /// Returns an element named `name` in the namespace `ns` holding the value
+    fn to_xml(&self, name: &str, ns: Option<~str>) -> Element;
+}
+
+/// Types stored as text, in an attribute or as the content of an element
+pub trait XmlText {
+    /// Parses a value from `text`
+    fn from_xml_text(text: &str) -> Result<Self, ~str>;
+    /// Returns the text form of the value
+    fn to_xml_text(&self) -> ~str;
+}
+
+/// Values stored in the child elements of a struct field, see `xml_struct!`.
+/// Children are in the namespace of their parent. A single value is read from
+/// the first child with the name and is required, an `Option` allows the child
+/// to be missing, and a vector holds every child with the name.
+pub trait XmlChildren {
+    /// Reads the value from the children of `parent` named `name`
+    fn from_children(parent: &Element, name: &str) -> Result<Self, ~str>;
+    /// Appends the value to `parent` as children named `name`
+    fn to_children(&self, parent: &mut Element, name: &str);
+}
+
+/// Values stored in an attribute without namespace, see `xml_struct!`.
+/// An `Option` allows the attribute to be missing.
+pub trait XmlAttribute {
+    /// Reads the value from the attribute `name` of `elem`
+    fn from_attribute(elem: &Element, name: &str) -> Result<Self, ~str>;
+    /// Sets the attribute `name` of `elem` to the value
+    fn to_attribute(&self, elem: &mut Element, name: &str);
+}
+
+/// Reads a required value from the first child of `parent` named `name`
+pub fn read_child<T: FromXml>(parent: &Element, name: &str) -> Result<T, ~str> {
+    match parent.child_with_name_and_ns(name, parent.ns.clone()) {
+        Some(child) => FromXml::from_xml(child),
+        None => Err(format!("Missing element '{}'", name))
+    }
+}
+
+/// Appends `value` to `parent` as a child named `name`
+pub fn write_child<T: ToXml>(value: &T, parent: &mut Element, name: &str) {
+    let child = value.to_xml(name, parent.ns.clone());
+    parent.append_child(Element(child));
+}
+
+/// Reads a struct field from the children of `parent`, as done by `xml_struct!`
+pub fn read_children<T: XmlChildren>(parent: &Element, name: &str) -> Result<T, ~str> {
+    XmlChildren::from_children(parent, name)
+}
+
+/// Writes a struct field as children of `parent`, as done by `xml_struct!`
+pub fn write_children<T: XmlChildren>(value: &T, parent: &mut Element, name: &str) {
+    value.to_children(parent, name)
+}
+
+/// Reads a struct field from an attribute of `elem`, as done by `xml_struct!`
+pub fn read_attribute<T: XmlAttribute>(elem: &Element, name: &str) -> Result<T, ~str> {
+    XmlAttribute::from_attribute(elem, name)
+}
+
+/// Writes a struct field as an attribute of `elem`, as done by `xml_struct!`
+pub fn write_attribute<T: XmlAttribute>(value: &T, elem: &mut Element, name: &str) {
+    value.to_attribute(elem, name)
+}
+
+/// Reads a struct field from the character data of `elem`, as done by `xml_struct!`
+pub fn read_text<T: XmlText>(elem: &Element) -> Result<T, ~str> {
+    XmlText::from_xml_text(elem.content_str())
+}
+
+/// Appends a struct field to `elem` as character data, as done by `xml_struct!`
+pub fn write_text<T: XmlText>(value: &T, elem: &mut Element) {
+    let text = value.to_xml_text();
+    if !text.is_empty() {
+        elem.append_child(CharacterNode(text));
+    }
+}
+
+impl XmlText for ~str {
+    fn from_xml_text(text: &str) -> Result<~str, ~str> {
+        Ok(text.to_owned())
+    }
+
+    fn to_xml_text(&self) -> ~str {
+        self.clone()
+    }
+}
+
+// Accepts the lexical forms of xsd:boolean
+impl XmlText for bool {
+    fn from_xml_text(text: &str) -> Result<bool, ~str> {
+        match text.trim() {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => Err(format!("Invalid boolean '{}'", text))
+        }
+    }
+
+    fn to_xml_text(&self) -> ~str {
+        self.to_str()
+    }
+}
+
+// Implements `XmlText` for numeric types through `FromStr` and `ToStr`
+macro_rules! number_text_impls(
+    ($($t:ty),+) => ($(
+        impl XmlText for $t {
+            fn from_xml_text(text: &str) -> Result<$t, ~str> {
+                match from_str(text.trim()) {
+                    Some(n) => Ok(n),
+                    None => Err(format!("Invalid number '{}'", text))
+                }
+            }
+
+            fn to_xml_text(&self) -> ~str {
+                self.to_str()
+            }
+        }
+    )+)
+)
+
+number_text_impls!(int, uint, i8, i16, i32, i64, u8, u16, u32, u64, f32, f64)
+
+// Implements the element and attribute traits for types stored as text
+macro_rules! text_impls(
+    ($($t:ty),+) => ($(
+        impl FromXml for $t {
+            fn from_xml(elem: &Element) -> Result<$t, ~str> {
+                read_text(elem)
+            }
+        }
+
+        impl ToXml for $t {
+            fn to_xml(&self, name: &str, ns: Option<~str>) -> Element {
+                let mut elem = Element::new(name);
+                elem.ns = ns;
+                write_text(self, &mut elem);
+                elem
+            }
+        }
+
+        impl XmlChildren for $t {
+            fn from_children(parent: &Element, name: &str) -> Result<$t, ~str> {
+                read_child(parent, name)
+            }
+
+            fn to_children(&self, parent: &mut Element, name: &str) {
+                write_child(self, parent, name)
+            }
+        }
+
+        impl XmlAttribute for $t {
+            fn from_attribute(elem: &Element, name: &str) -> Result<$t, ~str> {
+                match elem.attribute_with_name(name) {
+                    Some(attr) => XmlText::from_xml_text(attr.value),
+                    None => Err(format!("Missing attribute '{}'", name))
+                }
+            }
+
+            fn to_attribute(&self, elem: &mut Element, name: &str) {
+                elem.set_attribute(name, self.to_xml_text())
+            }
+        }
+    )+)
+)
+
+text_impls!(~str, bool, int, uint, i8, i16, i32, i64, u8, u16, u32, u64, f32, f64)
+
+impl<T: FromXml + ToXml> XmlChildren for Option<T> {
+    fn from_children(parent: &Element, name: &str) -> Result<Option<T>, ~str> {
+        match parent.child_with_name_and_ns(name, parent.ns.clone()) {
+            Some(child) => FromXml::from_xml(child).map(|v| Some(v)),
+            None => Ok(None)
+        }
+    }
+
+    fn to_children(&self, parent: &mut Element, name: &str) {
+        match *self {
+            Some(ref value) => write_child(value, parent, name),
+            None => ()
+        }
+    }
+}
+
+impl<T: FromXml + ToXml> XmlChildren for ~[T] {
+    fn from_children(parent: &Element, name: &str) -> Result<~[T], ~str> {
+        let mut result = ~[];
+        for child in parent.children_with_name_and_ns(name, parent.ns.clone()).move_iter() {
+            match FromXml::from_xml(child) {
+                Ok(v) => result.push(v),
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(result)
+    }
+
+    fn to_children(&self, parent: &mut Element, name: &str) {
+        for value in self.iter() {
+            write_child(value, parent, name);
+        }
+    }
+}
+
+impl<T: XmlText> XmlAttribute for Option<T> {
+    fn from_attribute(elem: &Element, name: &str) -> Result<Option<T>, ~str> {
+        let attr: Option<&Attribute> = elem.attribute_with_name(name);
+        match attr {
+            Some(attr) => XmlText::from_xml_text(attr.value).map(|v| Some(v)),
+            None => Ok(None)
+        }
+    }
+
+    fn to_attribute(&self, elem: &mut Element, name: &str) {
+        match *self {
+            Some(ref value) => elem.set_attribute(name, value.to_xml_text()),
+            None => ()
+        }
+    }
+}
@@ -0,0 +1,501 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{XML, Element, CharacterNode, CDATANode, CommentNode, PINode};
+use super::namespace::is_declaration;
+use super::base::is_space;
+use std::fmt;
+use std::hash::Hash;
+use std::hashmap::HashMap;
+use std::vec;
+
+#[deriving(Clone,Eq)]
+/// How the children of an element are told apart when matching siblings
+pub enum DiffKey {
+    /// By the value of the named attribute without namespace
+    KeyAttribute(~str),
+    /// By the content of the first child element with this name, in the namespace
+    /// of the element
+    KeyChild(~str)
+}
+
+#[deriving(Clone)]
+/// Options controlling how two `Element`s are compared
+pub struct DiffOptions {
+    /// The keys of elements, by element name. Elements without a key are matched by
+    /// name and position among the siblings with the same name.
+    keys: ~[(~str, DiffKey)]
+}
+
+impl DiffOptions {
+    /// Returns options without keys
+    pub fn new() -> DiffOptions {
+        DiffOptions { keys: ~[] }
+    }
+
+    /// Returns options with the keys of common Salesforce metadata, such as `field`
+    /// for `fieldPermissions`.
+    pub fn metadata() -> DiffOptions {
+        let keys = [("applicationVisibilities", "application"), ("classAccesses", "apexClass"),
+                    ("fieldPermissions", "field"), ("layoutAssignments", "layout"),
+                    ("objectPermissions", "object"), ("pageAccesses", "apexPage"),
+                    ("recordTypeVisibilities", "recordType"), ("tabVisibilities", "tab"),
+                    ("userPermissions", "name")];
+        DiffOptions { keys: keys.iter().map(|&(e, k)| (e.to_owned(), KeyChild(k.to_owned()))).collect() }
+    }
+}
+
+#[deriving(Clone,Eq)]
+/// One step of the path to a node, below the root element
+pub struct Step {
+    /// The position of the node among its siblings
+    index: uint,
+    /// A readable name for the node, e.g. "fieldPermissions[field='Account.Phone']"
+    label: ~str
+}
+
+#[deriving(Clone,Eq)]
+/// A single change in an `EditScript`. Paths are valid at the point the edit is
+/// applied, after all earlier edits.
+pub enum Edit {
+    /// Inserts a node at the path, shifting later siblings
+    Insert(~[Step], XML),
+    /// Deletes the node at the path
+    Delete(~[Step]),
+    /// Changes the content of the character data, CDATA or comment, or the data of the
+    /// processing instruction at the path from the first value to the second
+    UpdateText(~[Step], ~str, ~str),
+    /// Changes an attribute of the element at the path, given by name and namespace,
+    /// from the first value to the second. `None` means the attribute is not present.
+    UpdateAttribute(~[Step], ~str, Option<~str>, Option<~str>, Option<~str>),
+    /// Moves the node at the path to another position among its siblings
+    Move(~[Step], uint)
+}
+
+#[deriving(Clone,Eq)]
+/// The changes turning one `Element` into another, as returned by `Element::diff`
+pub struct EditScript {
+    /// The name of the root element, which starts every path
+    root: ~str,
+    /// The edits, in the order they are applied
+    edits: ~[Edit]
+}
+
+impl EditScript {
+    /// Returns true if the compared elements are equal
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Applies the edits to `root`, which should be equal to the element the script
+    /// was computed from. Fails with `Err` if a path does not exist in `root`.
+    pub fn apply(&self, root: &mut Element) -> Result<(), ~str> {
+        for edit in self.edits.iter() {
+            check!(apply_edit(root, edit));
+        }
+        Ok(())
+    }
+}
+
+impl Element {
+    /// Returns the edits turning this element into `other`, matching children as
+    /// configured by `opts`. Fails with `Err` if the root elements differ in name
+    /// or namespace.
+    pub fn diff(&self, other: &Element, opts: &DiffOptions) -> Result<EditScript, ~str> {
+        if self.name != other.name || self.ns != other.ns {
+            return Err(~"Root elements differ");
+        }
+        let mut edits = ~[];
+        diff_element(self, other, ~[], opts, &mut edits);
+        Ok(EditScript { root: self.name.to_str(), edits: edits })
+    }
+}
+
+// What a child node is matched by
+#[deriving(Clone,Eq,IterBytes)]
+enum Kind {
+    // Name, namespace and the key label and value if a key is configured
+    ElementKind(~str, Option<~str>, Option<(~str, ~str)>),
+    TextKind,
+    // Character data consisting only of whitespace, which is matched in order
+    SpaceKind,
+    CommentKind,
+    // Target
+    PIKind(~str)
+}
+
+// The identity of a child node: its kind and how many earlier siblings share it
+#[deriving(Clone,Eq,IterBytes)]
+struct NodeKey {
+    kind: Kind,
+    occurrence: uint
+}
+
+// The keys and labels of a list of children, indexed by key
+struct Keys {
+    keys: ~[NodeKey],
+    labels: ~[~str],
+    // The position of the child with each key, leaving out whitespace
+    index: HashMap<NodeKey, uint>
+}
+
+fn kind_of(node: &XML, opts: &DiffOptions) -> Kind {
+    match *node {
+        Element(ref elem) => {
+            let key = match opts.keys.iter().find(|&&(ref name, _)| name.as_slice() == elem.name.as_slice()) {
+                Some(&(_, KeyAttribute(ref attr))) => elem.attribute_with_name(*attr).map(|a| {
+                    (format!("@{}", *attr), a.value.clone())
+                }),
+                Some(&(_, KeyChild(ref child))) => elem.child_with_name_and_ns(*child, elem.ns.clone()).map(|c| {
+                    (child.clone(), c.content_str())
+                }),
+                None => None
+            };
+            ElementKind(elem.name.to_str(), elem.ns.clone(), key)
+        }
+        CharacterNode(ref data) if data.chars().all(is_space) => SpaceKind,
+        CharacterNode(_) | CDATANode(_) => TextKind,
+        CommentNode(_) => CommentKind,
+        PINode(ref pi) => PIKind(pi.target.clone())
+    }
+}
+
+// Returns the name of nodes of `kind` in a path
+fn kind_label(kind: &Kind) -> ~str {
+    match *kind {
+        ElementKind(ref name, _, Some((ref key, ref value))) => format!("{}[{}='{}']", *name, *key, *value),
+        ElementKind(ref name, _, None) => name.clone(),
+        TextKind | SpaceKind => ~"text()",
+        CommentKind => ~"comment()",
+        PIKind(ref target) => format!("processing-instruction('{}')", *target)
+    }
+}
+
+// Counts the occurrences of each value returned by `f` for `items`, returning the
+// number of earlier items with the same value for each item, and the totals
+fn count_occurrences<T, K: Hash + Eq>(items: &[T], f: |&T| -> K) -> (~[uint], HashMap<K, uint>) {
+    let mut counts = HashMap::new();
+    let mut result = ~[];
+    for item in items.iter() {
+        let k = f(item);
+        let n = counts.find(&k).map_default(0, |n| *n);
+        result.push(n);
+        counts.insert(k, n + 1);
+    }
+    (result, counts)
+}
+
+// Labels children in the way of XPath, numbering them if siblings share their name
+fn node_keys(children: &[XML], opts: &DiffOptions) -> Keys {
+    let kinds: ~[Kind] = children.iter().map(|c| kind_of(c, opts)).collect();
+    let (occurrences, _) = count_occurrences(kinds, |k| k.clone());
+    let (positions, totals) = count_occurrences(kinds, |k| kind_label(k));
+
+    let mut keys = Keys { keys: ~[], labels: ~[], index: HashMap::new() };
+    for (i, kind) in kinds.move_iter().enumerate() {
+        let label = kind_label(&kind);
+        keys.labels.push(if *totals.get(&label) > 1 {
+            format!("{}[{}]", label, positions[i] + 1)
+        } else {
+            label
+        });
+        let key = NodeKey { kind: kind, occurrence: occurrences[i] };
+        if key.kind != SpaceKind {
+            keys.index.insert(key.clone(), i);
+        }
+        keys.keys.push(key);
+    }
+    keys
+}
+
+// The old children which have not been placed yet. They follow the children
+// placed so far in their original order, so their positions follow from the
+// number of them before each.
+struct Remaining {
+    left: ~[bool],
+    // A Fenwick tree over `left`
+    tree: ~[uint],
+    // The first child left
+    first: uint
+}
+
+impl Remaining {
+    fn new(left: ~[bool]) -> Remaining {
+        let n = left.len();
+        let mut r = Remaining { left: ~[], tree: vec::from_elem(n + 1, 0u), first: n };
+        for (i, &l) in left.iter().enumerate() {
+            if l {
+                r.update(i, |count| count + 1);
+                if r.first == n {
+                    r.first = i;
+                }
+            }
+        }
+        r.left = left;
+        r
+    }
+
+    fn update(&mut self, i: uint, f: |uint| -> uint) {
+        let mut k = i + 1;
+        while k < self.tree.len() {
+            self.tree[k] = f(self.tree[k]);
+            k += k & (!k + 1);
+        }
+    }
+
+    // Returns the number of children left before the old child `i`
+    fn before(&self, i: uint) -> uint {
+        let mut n = 0;
+        let mut k = i;
+        while k > 0 {
+            n += self.tree[k];
+            k &= k - 1;
+        }
+        n
+    }
+
+    fn take(&mut self, i: uint) {
+        self.left[i] = false;
+        self.update(i, |count| count - 1);
+        while self.first < self.left.len() && !self.left[self.first] {
+            self.first += 1;
+        }
+    }
+}
+
+fn child_path(path: &[Step], index: uint, label: ~str) -> ~[Step] {
+    let mut path = path.to_owned();
+    path.push(Step { index: index, label: label });
+    path
+}
+
+fn node_text<'a>(node: &'a XML) -> &'a str {
+    match *node {
+        CharacterNode(ref data) | CDATANode(ref data) | CommentNode(ref data) => data.as_slice(),
+        PINode(ref pi) => pi.data.as_slice(),
+        Element(_) => ""
+    }
+}
+
+fn diff_node(old: &XML, new: &XML, path: ~[Step], opts: &DiffOptions, edits: &mut ~[Edit]) {
+    match (old, new) {
+        (&Element(ref a), &Element(ref b)) => diff_element(a, b, path, opts, edits),
+        _ => {
+            let (a, b) = (node_text(old), node_text(new));
+            if a != b {
+                edits.push(UpdateText(path, a.to_owned(), b.to_owned()));
+            }
+        }
+    }
+}
+
+fn diff_element(old: &Element, new: &Element, path: ~[Step], opts: &DiffOptions, edits: &mut ~[Edit]) {
+    // Namespace declarations only choose prefixes, so they are not compared
+    for attr in old.attributes.iter().filter(|a| !is_declaration(*a)) {
+        if new.attribute_with_name_and_ns(attr.name.as_slice(), attr.ns.clone()).is_none() {
+            edits.push(UpdateAttribute(path.clone(), attr.name.to_str(), attr.ns.clone(),
+                                       Some(attr.value.clone()), None));
+        }
+    }
+    for attr in new.attributes.iter().filter(|a| !is_declaration(*a)) {
+        let old_value = old.attribute_with_name_and_ns(attr.name.as_slice(), attr.ns.clone()).map(|a| a.value.clone());
+        if old_value.as_ref() != Some(&attr.value) {
+            edits.push(UpdateAttribute(path.clone(), attr.name.to_str(), attr.ns.clone(),
+                                       old_value, Some(attr.value.clone())));
+        }
+    }
+
+    let old_keys = node_keys(old.children, opts);
+    let new_keys = node_keys(new.children, opts);
+
+    // Deletions come last to first, so the paths of earlier siblings stay valid.
+    // Whitespace is kept until all other children are placed.
+    let mut kept = ~[];
+    for (i, key) in old_keys.keys.iter().enumerate().invert() {
+        let keep = key.kind == SpaceKind || new_keys.index.contains_key(key);
+        if !keep {
+            edits.push(Delete(child_path(path, i, old_keys.labels[i].clone())));
+        }
+        kept.push(keep);
+    }
+    kept.reverse();
+
+    // Old whitespace is matched in order and never moves: the old children in
+    // front of it are moved behind it instead, so reordered elements leave the
+    // indentation between them in place
+    let spaces: ~[uint] = range(0, old_keys.keys.len()).filter(|&j| old_keys.keys[j].kind == SpaceKind).collect();
+    let mut next_space = 0;
+    let mut remaining = Remaining::new(kept);
+    for (i, key) in new_keys.keys.iter().enumerate() {
+        let new_path = child_path(path, i, new_keys.labels[i].clone());
+        let old_index = if key.kind != SpaceKind {
+            old_keys.index.find(key).map(|j| *j)
+        } else if next_space < spaces.len() {
+            let k = spaces[next_space];
+            next_space += 1;
+            let ahead: ~[uint] = range(remaining.first, k).filter(|&j| remaining.left[j]).collect();
+            for &j in ahead.iter() {
+                edits.push(Move(child_path(path, i, old_keys.labels[j].clone()), i + ahead.len()));
+            }
+            remaining.take(k);
+            diff_node(&old.children[k], &new.children[i], new_path, opts, edits);
+            continue;
+        } else {
+            None
+        };
+        match old_index {
+            Some(j) => {
+                let pos = i + remaining.before(j);
+                if pos != i {
+                    edits.push(Move(child_path(path, pos, old_keys.labels[j].clone()), i));
+                }
+                remaining.take(j);
+                diff_node(&old.children[j], &new.children[i], new_path, opts, edits);
+            }
+            None => edits.push(Insert(new_path, new.children[i].clone()))
+        }
+    }
+
+    // The whitespace left follows all new children
+    let n = new_keys.keys.len();
+    for j in range(0, old_keys.keys.len()).invert() {
+        if remaining.left[j] {
+            let pos = n + remaining.before(j);
+            edits.push(Delete(child_path(path, pos, old_keys.labels[j].clone())));
+        }
+    }
+}
+
+// Returns the element at `path` below `elem`
+fn element_at<'a>(elem: &'a mut Element, path: &[Step]) -> Result<&'a mut Element, ~str> {
+    if path.is_empty() {
+        return Ok(elem);
+    }
+    let i = path[0].index;
+    if i >= elem.children.len() {
+        return Err(~"Path does not exist");
+    }
+    match elem.children[i] {
+        Element(ref mut child) => element_at(child, path.slice_from(1)),
+        _ => Err(~"Path does not lead to an element")
+    }
+}
+
+// Returns the parent of the node at `path` and the node's position
+fn parent_of<'a>(root: &'a mut Element, path: &[Step]) -> Result<(&'a mut Element, uint), ~str> {
+    if path.is_empty() {
+        return Err(~"The root element can not be inserted, deleted or moved");
+    }
+    let l = path.len();
+    let parent = check!(element_at(root, path.slice_to(l - 1)));
+    Ok((parent, path[l-1].index))
+}
+
+fn apply_edit(root: &mut Element, edit: &Edit) -> Result<(), ~str> {
+    match *edit {
+        Insert(ref path, ref node) => {
+            let (parent, i) = check!(parent_of(root, *path));
+            if i > parent.children.len() {
+                return Err(~"Path does not exist");
+            }
+            parent.insert_child(i, node.clone());
+        }
+        Delete(ref path) => {
+            let (parent, i) = check!(parent_of(root, *path));
+            if i >= parent.children.len() {
+                return Err(~"Path does not exist");
+            }
+            parent.remove_child(i);
+        }
+        UpdateText(ref path, _, ref text) => {
+            let (parent, i) = check!(parent_of(root, *path));
+            if i >= parent.children.len() {
+                return Err(~"Path does not exist");
+            }
+            match parent.children[i] {
+                CharacterNode(ref mut data) | CDATANode(ref mut data) |
+                CommentNode(ref mut data) => *data = text.clone(),
+                PINode(ref mut pi) => pi.data = text.clone(),
+                Element(_) => return Err(~"Path does not lead to character data")
+            }
+        }
+        UpdateAttribute(ref path, ref name, ref ns, _, ref value) => {
+            let elem = check!(element_at(root, *path));
+            match *value {
+                Some(ref value) => elem.set_attribute_with_ns(*name, ns.clone(), *value),
+                None => { elem.remove_attribute_with_ns(*name, ns.clone()); }
+            }
+        }
+        Move(ref path, to) => {
+            let (parent, i) = check!(parent_of(root, *path));
+            if i >= parent.children.len() || to >= parent.children.len() {
+                return Err(~"Path does not exist");
+            }
+            parent.move_child(i, to);
+        }
+    }
+    Ok(())
+}
+
+// Writes the path of a node, starting with the root element
+fn write_path(f: &mut fmt::Formatter, root: &str, path: &[Step]) {
+    write!(f.buf, "/{}", root);
+    for step in path.iter() {
+        write!(f.buf, "/{}", step.label);
+    }
+}
+
+fn write_attribute_name(f: &mut fmt::Formatter, name: &str, ns: &Option<~str>) {
+    match *ns {
+        Some(ref ns) => write!(f.buf, "/@\\{{}\\}{}", *ns, name),
+        None => write!(f.buf, "/@{}", name)
+    }
+}
+
+// Renders the script as a patch, one line per edit
+impl fmt::Default for EditScript {
+    fn fmt(value: &EditScript, f: &mut fmt::Formatter) {
+        for edit in value.edits.iter() {
+            match *edit {
+                Insert(ref path, ref node) => {
+                    write!(f.buf, "+ ");
+                    write_path(f, value.root, *path);
+                    write!(f.buf, ": {}\n", *node);
+                }
+                Delete(ref path) => {
+                    write!(f.buf, "- ");
+                    write_path(f, value.root, *path);
+                    write!(f.buf, "\n");
+                }
+                UpdateText(ref path, ref old, ref new) => {
+                    write!(f.buf, "~ ");
+                    write_path(f, value.root, *path);
+                    write!(f.buf, ": \"{}\" -> \"{}\"\n", *old, *new);
+                }
+                UpdateAttribute(ref path, ref name, ref ns, ref old, ref new) => {
+                    write!(f.buf, "{} ", match (old, new) {
+                        (&None, _) => "+",
+                        (_, &None) => "-",
+                        _ => "~"
+                    });
+                    write_path(f, value.root, *path);
+                    write_attribute_name(f, *name, ns);
+                    match (old, new) {
+                        (&Some(ref old), &Some(ref new)) => write!(f.buf, ": \"{}\" -> \"{}\"", *old, *new),
+                        (&None, &Some(ref new)) => write!(f.buf, ": \"{}\"", *new),
+                        _ => ()
+                    }
+                    write!(f.buf, "\n");
+                }
+                Move(ref path, to) => {
+                    write!(f.buf, "> ");
+                    write_path(f, value.root, *path);
+                    write!(f.buf, ": to position {}\n", to + 1);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use std::ascii::StrAsciiExt;
+use std::char;
+use std::str;
+
+#[deriving(Clone,Eq)]
+/// Character encodings understood by `Parser::parse_bytes`
+pub enum Encoding {
+    /// UTF-8, the default for XML documents
+    UTF8,
+    /// UTF-16, little endian
+    UTF16LE,
+    /// UTF-16, big endian
+    UTF16BE,
+    /// US-ASCII, rejecting bytes above 0x7F
+    ASCII,
+    /// ISO-8859-1 (Latin-1)
+    ISO88591,
+    /// Windows-1252, the Latin-1 superset used by Windows editors
+    Windows1252
+}
+
+// Code points for the bytes 0x80 to 0x9F in Windows-1252.
+// Bytes left undefined by the code page map to the C1 control of the same value.
+static WINDOWS_1252: [u32, ..32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021,
+    0x02C6, 0x2030, 0x0160, 0x2039, 0x0152, 0x008D, 0x017D, 0x008F,
+    0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178
+];
+
+impl Encoding {
+    /// Looks up an encoding by the name used in an XML declaration.
+    /// Names are matched case-insensitively. Unsupported names return `None`.
+    /// "UTF-16" leaves the byte order to the byte order mark and returns `UTF16BE`,
+    /// the byte order assumed without one.
+    pub fn from_label(label: &str) -> Option<Encoding> {
+        let label = label.trim().to_ascii_lower();
+        match label.as_slice() {
+            "utf-8" | "utf8" => Some(UTF8),
+            "utf-16" | "utf16" | "utf-16be" => Some(UTF16BE),
+            "utf-16le" => Some(UTF16LE),
+            "us-ascii" | "ascii" => Some(ASCII),
+            "iso-8859-1" | "iso8859-1" | "latin1" | "l1" => Some(ISO88591),
+            "windows-1252" | "cp1252" => Some(Windows1252),
+            _ => None
+        }
+    }
+
+    /// Returns the canonical name of the encoding.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            UTF8 => "UTF-8",
+            UTF16LE => "UTF-16LE",
+            UTF16BE => "UTF-16BE",
+            ASCII => "US-ASCII",
+            ISO88591 => "ISO-8859-1",
+            Windows1252 => "windows-1252"
+        }
+    }
+
+    /// Returns true if `c` can be represented in the encoding.
+    pub fn can_encode(&self, c: char) -> bool {
+        match *self {
+            UTF8 | UTF16LE | UTF16BE => true,
+            ASCII => (c as u32) < 0x80,
+            ISO88591 => (c as u32) < 0x100,
+            Windows1252 => {
+                (c as u32) < 0x80 || ((c as u32) >= 0xA0 && (c as u32) < 0x100) ||
+                WINDOWS_1252.iter().any(|&code| code == c as u32)
+            }
+        }
+    }
+
+    fn is_utf16(&self) -> bool {
+        match *self {
+            UTF16LE | UTF16BE => true,
+            _ => false
+        }
+    }
+
+    // Whether a document in this encoding may declare `label`
+    fn accepts_label(&self, label: &str) -> bool {
+        match Encoding::from_label(label) {
+            Some(enc) if enc == *self => true,
+            Some(enc) if enc.is_utf16() => {
+                self.is_utf16() && label.trim().to_ascii_lower().replace("-", "") == ~"utf16"
+            }
+            _ => false
+        }
+    }
+
+    /// Decodes `data` into a string. `data` must not include a byte order mark.
+    pub fn decode(&self, data: &[u8]) -> Result<~str, ~str> {
+        match *self {
+            UTF8 => match str::from_utf8_opt(data) {
+                Some(s) => Ok(s.to_owned()),
+                None => Err(~"Invalid UTF-8 sequence")
+            },
+            UTF16LE => decode_utf16(data, |lo, hi| (hi as u16 << 8) | lo as u16),
+            UTF16BE => decode_utf16(data, |hi, lo| (hi as u16 << 8) | lo as u16),
+            ASCII => match data.iter().find(|&&b| b >= 0x80) {
+                Some(b) => Err(format!("Invalid US-ASCII byte 0x{:x}", *b as uint)),
+                None => Ok(str::from_utf8(data).to_owned())
+            },
+            ISO88591 => {
+                let mut result = str::with_capacity(data.len());
+                for &b in data.iter() {
+                    result.push_char(b as char);
+                }
+                Ok(result)
+            }
+            Windows1252 => {
+                let mut result = str::with_capacity(data.len());
+                for &b in data.iter() {
+                    if b >= 0x80 && b < 0xA0 {
+                        result.push_char(char::from_u32(WINDOWS_1252[(b - 0x80) as uint]).unwrap());
+                    } else {
+                        result.push_char(b as char);
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+fn decode_utf16(data: &[u8], unit: |u8, u8| -> u16) -> Result<~str, ~str> {
+    if data.len() % 2 != 0 {
+        return Err(~"Truncated UTF-16 sequence");
+    }
+
+    let mut result = str::with_capacity(data.len() / 2);
+    let mut high: Option<u16> = None;
+    let mut i = 0;
+    while i < data.len() {
+        let u = unit(data[i], data[i+1]);
+        i += 2;
+
+        match high.take() {
+            Some(h) => {
+                if u < 0xDC00 || u > 0xDFFF {
+                    return Err(~"Unpaired UTF-16 surrogate");
+                }
+                let c = 0x10000 + ((h as u32 - 0xD800) << 10) + (u as u32 - 0xDC00);
+                result.push_char(char::from_u32(c).unwrap());
+            }
+            None if u >= 0xD800 && u <= 0xDBFF => high = Some(u),
+            None if u >= 0xDC00 && u <= 0xDFFF => return Err(~"Unpaired UTF-16 surrogate"),
+            None => result.push_char(char::from_u32(u as u32).unwrap())
+        }
+    }
+
+    if high.is_some() {
+        return Err(~"Unpaired UTF-16 surrogate");
+    }
+    Ok(result)
+}
+
+/// Guesses the encoding of a document from its first bytes, as described in
+/// appendix F of the XML 1.0 specification.
+/// Returns the encoding and the length of the byte order mark, if any.
+/// `None` means the document is ASCII-compatible and its declaration decides.
+pub fn detect(data: &[u8]) -> (Option<Encoding>, uint) {
+    if data.len() >= 3 && data[0] == 0xEF && data[1] == 0xBB && data[2] == 0xBF {
+        (Some(UTF8), 3)
+    } else if data.len() >= 2 && data[0] == 0xFF && data[1] == 0xFE {
+        (Some(UTF16LE), 2)
+    } else if data.len() >= 2 && data[0] == 0xFE && data[1] == 0xFF {
+        (Some(UTF16BE), 2)
+    } else if data.len() >= 4 && data[0] == 0x3C && data[1] == 0 && data[2] == 0x3F && data[3] == 0 {
+        (Some(UTF16LE), 0)
+    } else if data.len() >= 4 && data[0] == 0 && data[1] == 0x3C && data[2] == 0 && data[3] == 0x3F {
+        (Some(UTF16BE), 0)
+    } else {
+        (None, 0)
+    }
+}
+
+// Reads the encoding pseudo-attribute from an ASCII-compatible XML declaration
+fn declared_encoding(data: &[u8]) -> Option<~str> {
+    if data.len() < 5 || data.slice_to(5) != bytes!("<?xml") {
+        return None;
+    }
+    let end = match data.iter().position(|&b| b == '>' as u8) {
+        Some(end) => end,
+        None => return None
+    };
+    let decl = match str::from_utf8_opt(data.slice_to(end)) {
+        Some(decl) => decl,
+        None => return None
+    };
+    let start = match decl.find_str("encoding") {
+        Some(start) => start + "encoding".len(),
+        None => return None
+    };
+    let rest = decl.slice_from(start).trim_left();
+    if !rest.starts_with("=") {
+        return None;
+    }
+    let rest = rest.slice_from(1).trim_left();
+    let quote = match rest.char_at(0) {
+        q @ '"' | q @ '\'' => q,
+        _ => return None
+    };
+    let rest = rest.slice_from(1);
+    rest.find(quote).map(|i| rest.slice_to(i).to_owned())
+}
+
+/// Decodes a complete document, honouring its byte order mark and the
+/// encoding named in its XML declaration.
+pub fn decode_document(data: &[u8]) -> Result<~str, ~str> {
+    let (detected, bom) = detect(data);
+    let declared = match detected {
+        // Only ASCII-compatible documents can be peeked at directly
+        Some(UTF8) | None => declared_encoding(data.slice_from(bom)),
+        Some(_) => None
+    };
+
+    let enc = match (detected, declared) {
+        (None, None) => UTF8,
+        (None, Some(ref label)) => match Encoding::from_label(*label) {
+            Some(enc) if !enc.is_utf16() => enc,
+            Some(_) => return Err(format!("Declared encoding '{}' does not match document", *label)),
+            None => return Err(format!("Unsupported encoding '{}'", *label))
+        },
+        (Some(UTF8), Some(ref label)) => match Encoding::from_label(*label) {
+            Some(UTF8) => UTF8,
+            _ => return Err(format!("Declared encoding '{}' does not match byte order mark", *label))
+        },
+        (Some(enc), _) => enc
+    };
+
+    let text = check!(enc.decode(data.slice_from(bom)));
+    if enc.is_utf16() {
+        // The declaration of a UTF-16 document can only be read once decoded
+        match declared_encoding(text.as_bytes()) {
+            Some(ref label) if !enc.accepts_label(*label) => {
+                return Err(format!("Declared encoding '{}' does not match document", *label));
+            }
+            _ => ()
+        }
+    }
+    Ok(text)
+}
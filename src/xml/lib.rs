@@ -1,36 +1,221 @@
 #[crate_id = "xml#0.1"];
 #[crate_type = "lib" ];
 #[forbid(non_camel_case_types)];
+#[feature(macro_rules)];
 #[warn(missing_doc)]
 
-pub use base::{escape, unescape, unrecognized_entity};
+extern mod extra;
+
+pub use base::{escape, unescape, try_unescape, unrecognized_entity};
+pub use base::{EscapeOptions, escape_text, escape_attribute};
+pub use atom::{Atom, Atoms};
 pub use base::{XML, Element, Attribute, CharacterNode, CDATANode, CommentNode, PINode};
-pub use base::{Event, PI, StartTag, EndTag, Characters, CDATA, Comment};
-pub use base::Error;
-pub use Parser::Parser;
-pub use ElementBuilder::ElementBuilder;
+pub use base::{Event, XmlDecl, PI, StartTag, EndTag, Characters, CDATA, Comment};
+pub use base::{Error, Position, Span, Trivia};
+pub use base::{ErrorKind, SyntaxError, DepthLimitExceeded, AttributeLimitExceeded, NameLengthLimitExceeded};
+pub use base::{TextSizeLimitExceeded, DocumentSizeLimitExceeded, EntityExpansionLimitExceeded};
+pub use base::{WhitespaceMode, KeepWhitespace, DropWhitespace, TrimWhitespace};
+pub use encoding::{Encoding, UTF8, UTF16LE, UTF16BE, ASCII, ISO88591, Windows1252};
+pub use serialize::{SerializeOptions, AttributeOrder, DocumentOrder, SortedOrder};
+pub use c14n::{C14NOptions, C14NMethod, InclusiveC14N, ExclusiveC14N};
+pub use xpath::{XPath, XPathContext, XPathValue, NodeSet, StringValue, NumberValue, BooleanValue};
+pub use xpath::{XPathNode, XPathRoot, XPathElement, XPathAttribute, XPathText, XPathComment};
+pub use xpath::{XPathPI, XPathNamespace};
+pub use traverse::{Descendants, BreadthFirst, Walk, Cursor};
+pub use traverse::{Visitor, VisitAction, VisitChildren, SkipChildren, StopVisit};
+pub use diff::{DiffOptions, DiffKey, KeyAttribute, KeyChild, EditScript, Step};
+pub use diff::{Edit, Insert, Delete, UpdateText, UpdateAttribute, Move};
+pub use convert::{FromXml, ToXml, XmlText, XmlChildren, XmlAttribute};
+pub use convert::{read_child, write_child, read_children, write_children};
+pub use convert::{read_attribute, write_attribute, read_text, write_text};
+pub use schema::{Schema, ValidationError, XSD_NS};
+pub use sax::{ContentHandler, QName, SaxDispatcher, BuildHandler, WriteHandler, parse_with_handler};
+pub use reader::{Reader, Text, Borrowed, Owned, AttributeRef};
+pub use reader::{EventRef, XmlDeclRef, PIRef, StartTagRef, EndTagRef, CharactersRef, CDATARef, CommentRef};
+pub use Parser::{Parser, ParserConfig};
+pub use ElementBuilder::{ElementBuilder, Built, Root, Subtree};
+pub use XmlWriter::XmlWriter;
 
 use std::from_str::FromStr;
+
+// Unwraps an `Ok` value, returning early on `Err`.
+// Defined before the modules, so all of them can use it.
+macro_rules! check(
+    ($e:expr) => (match $e { Ok(v) => v, Err(e) => return Err(e) })
+)
+
+#[macro_escape]
+mod macros;
+
 mod base;
+mod atom;
+mod namespace;
+mod encoding;
+mod serialize;
+mod c14n;
+mod xpath;
+mod traverse;
+mod diff;
+mod convert;
+mod schema;
+mod sax;
+mod reader;
 mod Parser;
 mod ElementBuilder;
+mod XmlWriter;
+
+// Hands one recovered parse result to `builder`, collecting errors
+fn collect_recovered(result: Result<(Event, Span), Error>,
+                     builder: &mut ElementBuilder::ElementBuilder,
+                     root: &mut Option<Element>, errors: &mut ~[Error]) {
+    match result {
+        Ok((event, span)) => match builder.push_event_at(event, span.clone()) {
+            Ok(Some(Root(elem))) => *root = Some(elem),
+            Ok(_) => (),
+            Err(msg) => errors.push(Error {
+                line: span.start.line,
+                col: span.start.col,
+                msg: msg,
+                kind: SyntaxError
+            })
+        },
+        Err(e) => errors.push(e)
+    }
+}
+
+/// Parses `data` with error recovery enabled, collecting every `Error` found.
+/// Returns the best-effort root `Element`, with spans recorded, together with
+/// all errors in document order.
+pub fn parse_recovering(data: &str) -> (Option<Element>, ~[Error]) {
+    let mut p = Parser::Parser::new();
+    let mut e = ElementBuilder::ElementBuilder::new();
+    let mut root = None;
+    let mut errors = ~[];
+
+    p.set_recover(true);
+    p.parse_str_spanned(data, |result| collect_recovered(result, &mut e, &mut root, &mut errors));
+    p.finish_spanned(|result| collect_recovered(result, &mut e, &mut root, &mut errors));
+    (root, errors)
+}
+
+/// Parses `data` keeping its formatting, for editing files in place.
+/// Every `Element` records the text it was read from as its `Trivia`, and the root
+/// also records the text before and after it. `write_lossless` reproduces `data`
+/// exactly, except for the parts which were changed since.
+pub fn parse_lossless(data: &str) -> Result<Element, Error> {
+    let mut p = Parser::Parser::new();
+    let mut e = ElementBuilder::ElementBuilder::new();
+    e.set_lossless(true);
+    let mut root = None;
+    let mut error = None;
+    let mut before = ~"";
+    // The end of the text handed to the builder so far
+    let mut last = 0;
+
+    p.parse_str_spanned(data, |result| {
+        match result {
+            _ if error.is_some() || root.is_some() => (),
+            Ok((event, span)) => {
+                let start = span.start.offset;
+                let end = span.end.offset;
+                if e.depth() == 0 {
+                    // Text before the root element is kept on its own
+                    match event {
+                        StartTag(_) => before.push_str(data.slice(last, start)),
+                        _ => {
+                            before.push_str(data.slice(last, end));
+                            last = end;
+                            return;
+                        }
+                    }
+                    last = start;
+                }
+                match e.push_event_source(event, data.slice(last, end)) {
+                    Ok(Some(Root(elem))) => root = Some(elem),
+                    Ok(_) => (),
+                    Err(msg) => error = Some(Error {
+                        line: span.start.line,
+                        col: span.start.col,
+                        msg: msg,
+                        kind: SyntaxError
+                    })
+                }
+                last = end;
+            }
+            Err(err) => error = Some(err)
+        }
+    });
+    p.finish(|result| {
+        match result {
+            Err(err) if error.is_none() => error = Some(err),
+            _ => ()
+        }
+    });
+
+    match error {
+        Some(err) => Err(err),
+        None => {
+            // The parser reports an error if there is no root element
+            let mut root = root.unwrap();
+            {
+                let trivia = root.trivia.get_mut_ref();
+                trivia.before = before;
+                trivia.after = data.slice_from(last).to_owned();
+            }
+            Ok(root)
+        }
+    }
+}
+
+/// Parses the document `data`, checking the limits in `config`. Documents from
+/// untrusted sources should be read with `ParserConfig::untrusted()` this way,
+/// as `from_str` does not limit anything. Returns the root `Element`, or the
+/// first `Error` found.
+pub fn parse_with_config(data: &str, config: ParserConfig) -> Result<Element, Error> {
+    let mut e = ElementBuilder::ElementBuilder::new();
+    let mut atoms = atom::Atoms::new();
+    let mut reader = reader::Reader::with_config(data, config);
+    let mut root = None;
+
+    loop {
+        match reader.next() {
+            Some(Ok(event)) => match e.push_event(event.into_event(&mut atoms)) {
+                Ok(Some(Root(elem))) => root = Some(elem),
+                Ok(_) => (),
+                Err(msg) => {
+                    let span = reader.span();
+                    return Err(Error {
+                        line: span.start.line,
+                        col: span.start.col,
+                        msg: msg,
+                        kind: SyntaxError
+                    });
+                }
+            },
+            Some(Err(err)) => return Err(err),
+            None => break
+        }
+    }
+    // The reader reports an error if there is no root element
+    Ok(root.unwrap())
+}
 
 impl FromStr for Element {
     #[inline]
     fn from_str(data: &str) -> Option<Element> {
-        let mut p = Parser::Parser::new();
         let mut e = ElementBuilder::ElementBuilder::new();
+        let mut atoms = atom::Atoms::new();
         let mut result = None;
 
-        p.parse_str(data, |event| {
+        for event in reader::Reader::new(data) {
             match event {
-                Ok(event) => match e.push_event(event) {
-                    Ok(Some(elem)) => result = Some(elem),
+                Ok(event) => match e.push_event(event.into_event(&mut atoms)) {
+                    Ok(Some(Root(elem))) => result = Some(elem),
                     _ => ()
                 },
                 _ => ()
             }
-        });
+        }
         result
     }
 }
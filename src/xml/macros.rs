@@ -0,0 +1,110 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+// Macros mapping structs to XML with the `FromXml` and `ToXml` traits.
+//
+// The macros are exported from the crate, so users load them with the crate:
+//
+//     #[feature(phase)];
+//
+//     #[phase(syntax, link)]
+//     extern mod xml;
+//
+// `xml_struct!` implements `FromXml`, `ToXml` and `XmlChildren` for a struct.
+// Every field is listed with how it is stored, in the order it is written:
+//
+//     xml_struct!(FieldPermission {
+//         child editable,
+//         child field,
+//         child readable
+//     })
+//
+// `child`     the field is stored in child elements named like the field, in the
+//             namespace of the element. The field type implements `XmlChildren`,
+//             e.g. a `bool`, an `Option<~str>` or a vector of mapped structs.
+// `default`   like `child`, but a missing child is read as the field's `Default`,
+//             e.g. `false` or an empty string. A child that can not be read is
+//             still an error.
+// `attribute` the field is stored in the attribute named like the field and
+//             implements `XmlAttribute`.
+// `text`      the field is the character data of the element and implements `XmlText`.
+// `skip`      the field is not stored, it is set to its `Default` when reading.
+
+// Reads or writes a single field of a struct mapped by `xml_struct!`. Exported
+// because `xml_struct!` expands to it in the crate using it.
+#[macro_export]
+macro_rules! xml_field(
+    (read child $field:ident, $elem:expr) => (
+        ::xml::read_children($elem, stringify!($field))
+    );
+    (read default $field:ident, $elem:expr) => (
+        match ::xml::read_children($elem, stringify!($field)) {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => Ok(::std::default::Default::default()),
+            Err(e) => Err(e)
+        }
+    );
+    (read attribute $field:ident, $elem:expr) => (
+        ::xml::read_attribute($elem, stringify!($field))
+    );
+    (read text $field:ident, $elem:expr) => (
+        ::xml::read_text($elem)
+    );
+    (read skip $field:ident, $elem:expr) => (
+        Ok(::std::default::Default::default())
+    );
+    (write child $field:ident, $value:expr, $elem:expr) => (
+        ::xml::write_children($value, $elem, stringify!($field))
+    );
+    (write default $field:ident, $value:expr, $elem:expr) => (
+        ::xml::write_children($value, $elem, stringify!($field))
+    );
+    (write attribute $field:ident, $value:expr, $elem:expr) => (
+        ::xml::write_attribute($value, $elem, stringify!($field))
+    );
+    (write text $field:ident, $value:expr, $elem:expr) => (
+        ::xml::write_text($value, $elem)
+    );
+    (write skip $field:ident, $value:expr, $elem:expr) => (
+        ()
+    )
+)
+
+// Implements `FromXml`, `ToXml` and `XmlChildren` for the struct `$name`
+#[macro_export]
+macro_rules! xml_struct(
+    ($name:ident { $($kind:ident $field:ident),+ }) => (
+        impl ::xml::FromXml for $name {
+            fn from_xml(elem: &::xml::Element) -> Result<$name, ~str> {
+                Ok($name {
+                    $($field: match xml_field!(read $kind $field, elem) {
+                        Ok(v) => v,
+                        Err(e) => return Err(e)
+                    }),+
+                })
+            }
+        }
+
+        impl ::xml::ToXml for $name {
+            fn to_xml(&self, name: &str, ns: Option<~str>) -> ::xml::Element {
+                let mut elem = ::xml::Element::new(name);
+                elem.ns = ns;
+                $(xml_field!(write $kind $field, &self.$field, &mut elem);)+
+                elem
+            }
+        }
+
+        impl ::xml::XmlChildren for $name {
+            fn from_children(parent: &::xml::Element, name: &str) -> Result<$name, ~str> {
+                ::xml::read_child(parent, name)
+            }
+
+            fn to_children(&self, parent: &mut ::xml::Element, name: &str) {
+                ::xml::write_child(self, parent, name)
+            }
+        }
+    )
+)
@@ -0,0 +1,214 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{Element, Attribute};
+
+/// The namespace bound to the `xml` prefix
+pub static XML_NS: &'static str = "http://www.w3.org/XML/1998/namespace";
+/// The namespace of namespace declarations
+pub static XMLNS_NS: &'static str = "http://www.w3.org/2000/xmlns/";
+
+/// If `attr` is a namespace declaration returns the declared prefix and namespace.
+/// The default namespace is declared with an empty prefix, an empty namespace
+/// undeclares it.
+pub fn declaration<'a>(attr: &'a Attribute) -> Option<(&'a str, &'a str)> {
+    match attr.ns {
+        Some(ref ns) if ns.as_slice() == XMLNS_NS => Some((attr.name.as_slice(), attr.value.as_slice())),
+        None if attr.name.as_slice() == "xmlns" => Some(("", attr.value.as_slice())),
+        _ => None
+    }
+}
+
+/// Returns true if `attr` is a namespace declaration
+#[inline]
+pub fn is_declaration(attr: &Attribute) -> bool {
+    declaration(attr).is_some()
+}
+
+/// The prefixes bound while writing a document.
+/// The default namespace is bound to the empty prefix, an empty namespace meaning none.
+pub struct Scope {
+    priv bindings: ~[(~str, ~str)],
+    // The number of bindings when each open element was entered
+    priv marks: ~[uint],
+    priv generated: uint
+}
+
+/// The names to write for an `Element` and its attributes, as returned by `Scope::enter`
+pub struct Names<'a> {
+    /// The qualified name of the element
+    qname: ~str,
+    /// The namespace declarations to write, as prefix and namespace
+    declarations: ~[(~str, ~str)],
+    /// The attributes other than namespace declarations, with their qualified names
+    attributes: ~[(~str, &'a Attribute)]
+}
+
+impl Scope {
+    /// Returns a new `Scope`, only binding the `xml` prefix
+    pub fn new() -> Scope {
+        Scope {
+            bindings: ~[(~"xml", XML_NS.to_owned()), (~"", ~"")],
+            marks: ~[],
+            generated: 0
+        }
+    }
+
+    /// Returns the namespace bound to `prefix`
+    pub fn namespace_for<'a>(&'a self, prefix: &str) -> Option<&'a str> {
+        self.bindings.rev_iter().find(|&&(ref p, _)| p.as_slice() == prefix).map(|&(_, ref ns)| ns.as_slice())
+    }
+
+    /// Returns a non-empty prefix bound to `ns`, which is not shadowed by a later binding
+    pub fn prefix_for(&self, ns: &str) -> Option<~str> {
+        for &(ref p, ref uri) in self.bindings.rev_iter() {
+            if !p.is_empty() && uri.as_slice() == ns && self.namespace_for(*p) == Some(ns) {
+                return Some(p.clone());
+            }
+        }
+        None
+    }
+
+    /// Returns the bindings in scope, innermost first, as prefix and namespace.
+    /// The `xml` prefix and an empty default namespace are left out.
+    pub fn in_scope(&self) -> ~[(~str, ~str)] {
+        let mut result: ~[(~str, ~str)] = ~[];
+        for &(ref p, ref ns) in self.bindings.rev_iter() {
+            if !result.iter().any(|&(ref q, _)| q == p) {
+                result.push((p.clone(), ns.clone()));
+            }
+        }
+        result.move_iter().filter(|&(ref p, ref ns)| p.as_slice() != "xml" && !ns.is_empty()).collect()
+    }
+
+    /// Binds `prefix` to `ns` until the current element is left
+    pub fn bind(&mut self, prefix: &str, ns: &str) {
+        self.bindings.push((prefix.to_owned(), ns.to_owned()));
+    }
+
+    /// Returns a prefix of the form "ns0" which is not bound
+    pub fn generate_prefix(&mut self) -> ~str {
+        loop {
+            let p = format!("ns{}", self.generated);
+            self.generated += 1;
+            if self.namespace_for(p.as_slice()).is_none() {
+                return p;
+            }
+        }
+    }
+
+    /// Starts a new element; bindings made until the matching `pop` are dropped then
+    pub fn push(&mut self) {
+        self.marks.push(self.bindings.len());
+    }
+
+    /// Drops the bindings made since the matching `push` or `enter`
+    pub fn pop(&mut self) {
+        let mark = self.marks.pop();
+        self.bindings.truncate(mark);
+    }
+
+    // Returns a prefix for `ns`, binding a new one if none is in scope
+    fn prefix_or_bind(&mut self, ns: &str, declarations: &mut ~[(~str, ~str)]) -> ~str {
+        if ns == XML_NS {
+            return ~"xml";
+        }
+        match self.prefix_for(ns) {
+            Some(p) => p,
+            None => {
+                let p = self.generate_prefix();
+                self.bind(p.as_slice(), ns);
+                declarations.push((p.clone(), ns.to_owned()));
+                p
+            }
+        }
+    }
+
+    // Returns `hint` if it can be used as the prefix for `ns`: when it is bound to `ns`
+    // already, or not bound at all, in which case it is bound now
+    fn hinted_prefix(&mut self, hint: &str, ns: &str, declarations: &mut ~[(~str, ~str)]) -> Option<~str> {
+        if hint.is_empty() || hint == "xmlns" || (hint == "xml") != (ns == XML_NS) {
+            return None;
+        }
+        let bound = self.namespace_for(hint).map(|bound| bound == ns);
+        match bound {
+            Some(true) => Some(hint.to_owned()),
+            Some(false) => None,
+            None => {
+                self.bind(hint, ns);
+                declarations.push((hint.to_owned(), ns.to_owned()));
+                Some(hint.to_owned())
+            }
+        }
+    }
+
+    /// Enters `elem`, binding the namespaces it declares and inventing prefixes as needed.
+    /// The prefix the element was read with is kept where possible.
+    /// Declarations which are already in scope are dropped.
+    /// `pop` must be called once the element and its children have been written.
+    pub fn enter<'a>(&mut self, elem: &'a Element) -> Names<'a> {
+        self.push();
+        let mut declarations = ~[];
+
+        for attr in elem.attributes.iter() {
+            match declaration(attr) {
+                Some((prefix, ns)) if self.namespace_for(prefix) != Some(ns) => {
+                    self.bind(prefix, ns);
+                    declarations.push((prefix.to_owned(), ns.to_owned()));
+                }
+                _ => ()
+            }
+        }
+
+        let default_declared = declarations.iter().any(|&(ref p, _)| p.is_empty());
+        let qname = match elem.ns {
+            None => {
+                if self.namespace_for("") != Some("") {
+                    // A default namespace declared on an element outside any namespace is void
+                    declarations.retain(|&(ref p, _)| !p.is_empty());
+                    self.bind("", "");
+                    declarations.push((~"", ~""));
+                }
+                elem.name.to_str()
+            }
+            Some(ref ns) => {
+                let hinted = match elem.prefix {
+                    Some(ref p) => self.hinted_prefix(*p, *ns, &mut declarations),
+                    None => None
+                };
+                if hinted.is_some() {
+                    format!("{}:{}", hinted.unwrap(), elem.name)
+                } else if self.namespace_for("") == Some(ns.as_slice()) {
+                    elem.name.to_str()
+                } else if !default_declared && self.prefix_for(*ns).is_none() {
+                    self.bind("", *ns);
+                    declarations.push((~"", ns.clone()));
+                    elem.name.to_str()
+                } else {
+                    let p = self.prefix_or_bind(*ns, &mut declarations);
+                    format!("{}:{}", p, elem.name)
+                }
+            }
+        };
+
+        let mut attributes = ~[];
+        for attr in elem.attributes.iter() {
+            if is_declaration(attr) {
+                continue;
+            }
+            let name = match attr.ns {
+                None => attr.name.to_str(),
+                Some(ref ns) => {
+                    let p = self.prefix_or_bind(*ns, &mut declarations);
+                    format!("{}:{}", p, attr.name)
+                }
+            };
+            attributes.push((name, attr));
+        }
+
+        Names { qname: qname, declarations: declarations, attributes: attributes }
+    }
+}
@@ -0,0 +1,1298 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{try_unescape, Event, XmlDecl, PI, StartTag, EndTag, Characters, CDATA, Comment};
+use super::{Attribute, Error, Position, Span};
+use super::{ErrorKind, SyntaxError, DepthLimitExceeded, AttributeLimitExceeded, NameLengthLimitExceeded};
+use super::{TextSizeLimitExceeded, DocumentSizeLimitExceeded, EntityExpansionLimitExceeded};
+use super::atom::Atoms;
+use super::base::{is_xml_char, is_space, is_name, is_name_start_char, char_reference};
+use super::namespace::{XML_NS, XMLNS_NS};
+use super::Parser::ParserConfig;
+use std::ascii::StrAsciiExt;
+use std::cmp;
+
+#[deriving(Clone,Eq)]
+/// Text read by a `Reader`, borrowed from the document unless it had to be unescaped
+/// or normalized
+pub enum Text<'a> {
+    /// Text as found in the document
+    Borrowed(&'a str),
+    /// Text with its references replaced or its whitespace normalized
+    Owned(~str)
+}
+
+impl<'a> Text<'a> {
+    /// Returns the text as a string slice
+    pub fn as_slice<'b>(&'b self) -> &'b str {
+        match *self {
+            Borrowed(text) => text,
+            Owned(ref text) => text.as_slice()
+        }
+    }
+
+    /// Returns the text as an owned string, copying it if it was borrowed
+    pub fn into_owned(self) -> ~str {
+        match self {
+            Borrowed(text) => text.to_owned(),
+            Owned(text) => text
+        }
+    }
+}
+
+#[deriving(Clone,Eq)]
+/// An attribute read by a `Reader`
+pub struct AttributeRef<'a> {
+    /// The attribute's name
+    name: &'a str,
+    /// The attribute's namespace
+    ns: Option<Text<'a>>,
+    /// The attribute's value
+    value: Text<'a>
+}
+
+#[deriving(Eq)]
+/// A start tag read by a `Reader`
+pub struct StartTagRef<'a> {
+    /// The tag's name
+    name: &'a str,
+    /// The tag's namespace
+    ns: Option<Text<'a>>,
+    /// The tag's prefix
+    prefix: Option<&'a str>,
+    /// Attributes included in the tag
+    attributes: ~[AttributeRef<'a>]
+}
+
+#[deriving(Eq)]
+/// An end tag read by a `Reader`
+pub struct EndTagRef<'a> {
+    /// The tag's name
+    name: &'a str,
+    /// The tag's namespace
+    ns: Option<Text<'a>>,
+    /// The tag's prefix
+    prefix: Option<&'a str>
+}
+
+#[deriving(Clone,Eq)]
+/// A processing instruction read by a `Reader`
+pub struct PIRef<'a> {
+    /// The instruction's target
+    target: &'a str,
+    /// The whitespace between the target and the data
+    space: Text<'a>,
+    /// The rest of the instruction
+    data: Text<'a>
+}
+
+#[deriving(Eq)]
+/// Events returned by a `Reader`, borrowing their data from the document
+pub enum EventRef<'a> {
+    /// The XML declaration
+    XmlDeclRef(XmlDecl),
+    /// A processing instruction
+    PIRef(PIRef<'a>),
+    /// A start tag
+    StartTagRef(StartTagRef<'a>),
+    /// An end tag
+    EndTagRef(EndTagRef<'a>),
+    /// Character data
+    CharactersRef(Text<'a>),
+    /// A CDATA section
+    CDATARef(Text<'a>),
+    /// A comment
+    CommentRef(Text<'a>)
+}
+
+fn owned_ns<'a>(ns: Option<Text<'a>>) -> Option<~str> {
+    ns.map(|ns| ns.into_owned())
+}
+
+impl<'a> EventRef<'a> {
+    /// Converts the event into an `Event` owning its data.
+    /// The names of elements and attributes are interned in `atoms`.
+    pub fn into_event(self, atoms: &mut Atoms) -> Event {
+        match self {
+            XmlDeclRef(decl) => XmlDecl(decl),
+            PIRef(PIRef { target, space, data }) => PI(PI {
+                target: target.to_owned(),
+                space: space.into_owned(),
+                data: data.into_owned()
+            }),
+            StartTagRef(StartTagRef { name, ns, prefix, attributes }) => StartTag(StartTag {
+                name: atoms.intern(name),
+                ns: owned_ns(ns),
+                prefix: prefix.map(|p| p.to_owned()),
+                attributes: attributes.move_iter().map(|a| Attribute {
+                    name: atoms.intern(a.name),
+                    ns: owned_ns(a.ns),
+                    value: a.value.into_owned()
+                }).collect()
+            }),
+            EndTagRef(EndTagRef { name, ns, prefix }) => EndTag(EndTag {
+                name: atoms.intern(name),
+                ns: owned_ns(ns),
+                prefix: prefix.map(|p| p.to_owned())
+            }),
+            CharactersRef(text) => Characters(text.into_owned()),
+            CDATARef(text) => CDATA(text.into_owned()),
+            CommentRef(text) => Comment(text.into_owned())
+        }
+    }
+}
+
+/**
+ * A pull parser reading a document held in memory.
+ * It scans the bytes of the document directly and returns events borrowing names
+ * and text from it, only allocating for text which had to be unescaped or
+ * normalized. `Parser` reads the text passed to it with a `Reader` as well, so
+ * documents are checked and normalized the same way by both. Reading stops at the
+ * first `Error`, whitespace handling and error recovery are offered by `Parser`.
+ *
+ * ~~~
+ * for event in Reader::new("<a href='http://rust-lang.org'>Rust</a>") {
+ *     match event {
+ *        [...]
+ *     }
+ * }
+ * ~~~
+ */
+pub struct Reader<'a> {
+    priv data: &'a str,
+    priv pos: uint,
+    // The prefixes bound by open elements, innermost last
+    priv bindings: ~[(&'a str, Text<'a>)],
+    // The number of bindings when each open element was entered
+    priv scopes: ~[uint],
+    // The qualified names of the open elements
+    priv elements: ~[&'a str],
+    priv root_seen: bool,
+    priv config: ParserConfig,
+    // The number of references replaced so far
+    priv expansions: uint,
+    priv recover: bool,
+    // Set if more text follows `data`, so markup it ends in is read later
+    priv partial: bool,
+    // Set if `data` was cut off at `max_document_size`
+    priv oversized: bool,
+    // Set after broken markup, which is skipped up to the next '<'
+    priv skipping: bool,
+    // The markup `data` starts in, if it was left incomplete by the last piece, and
+    // the number of its bytes searched for its end so far
+    priv awaiting: Option<(&'static str, uint)>,
+    // Events and errors to report before reading on, with the offsets of their spans
+    priv queue: ~[(Result<EventRef<'a>, Error>, uint, uint)],
+    // The offsets of the span of the last event reported
+    priv last: (uint, uint),
+    // The position `data` starts at, and whether it follows a '\r'
+    priv start: Position,
+    priv start_cr: bool,
+    // Lines and columns are counted up to the offset `counted`
+    priv counted: uint,
+    priv line: uint,
+    priv col: uint,
+    priv after_cr: bool,
+    priv failed: bool,
+    priv done: bool
+}
+
+/// The state of a `Reader` between two pieces of a document, owning everything it
+/// needs to carry on with the next piece
+pub struct ReaderState {
+    priv bindings: ~[(~str, ~str)],
+    priv scopes: ~[uint],
+    priv elements: ~[~str],
+    priv root_seen: bool,
+    priv expansions: uint,
+    // The position of the text not read yet, and whether it follows a '\r'
+    priv position: Position,
+    priv after_cr: bool,
+    priv skipping: bool,
+    // The markup left incomplete at the position, and how much of it was searched
+    priv awaiting: Option<(&'static str, uint)>,
+    priv failed: bool
+}
+
+impl ReaderState {
+    /// Returns the state at the start of a document
+    pub fn new() -> ReaderState {
+        ReaderState {
+            bindings: ~[(~"xml", XML_NS.to_owned()), (~"xmlns", XMLNS_NS.to_owned())],
+            scopes: ~[],
+            elements: ~[],
+            root_seen: false,
+            expansions: 0,
+            position: Position { offset: 0, line: 1, col: 1 },
+            after_cr: false,
+            skipping: false,
+            awaiting: None,
+            failed: false
+        }
+    }
+
+    /// Returns the position of the text not read yet
+    pub fn position<'a>(&'a self) -> &'a Position {
+        &self.position
+    }
+
+    /// Returns whether reading stopped at an `Error`
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Stops reading, after an `Error` found outside of a `Reader`
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+}
+
+// Why reading stopped before the end of a token
+enum Stop {
+    // An error at a byte offset
+    Invalid(uint, ErrorKind, ~str),
+    // The end of the text inside the named markup, which is only an error once
+    // no more text follows
+    Incomplete(&'static str)
+}
+
+// A well-formedness error at `offset`
+fn invalid<T>(offset: uint, msg: ~str) -> Result<T, Stop> {
+    Err(Invalid(offset, SyntaxError, msg))
+}
+
+// Checks `len` bytes starting at `offset` against the limit `limit`
+fn check_length(limit: Option<uint>, offset: uint, len: uint, kind: ErrorKind,
+                what: &str) -> Result<(), Stop> {
+    match limit {
+        Some(max) if len > max => {
+            Err(Invalid(offset + max, kind, format!("{} longer than {} bytes", what, max)))
+        }
+        _ => Ok(())
+    }
+}
+
+// Splits a qualified name into prefix and local name
+fn split_qname<'a>(qname: &'a str) -> (Option<&'a str>, &'a str) {
+    match qname.find(':') {
+        None => (None, qname),
+        Some(i) => (Some(qname.slice_to(i)), qname.slice_from(i+1))
+    }
+}
+
+// Splits the content of a processing instruction into its target, the whitespace
+// following it and its data. Targets matching "xml" in any case are reserved,
+// only "xml" itself is accepted for the XML declaration.
+pub fn split_pi<'a>(content: &'a str) -> Result<(&'a str, &'a str, &'a str), ~str> {
+    let end = content.find(|c: char| is_space(c)).unwrap_or(content.len());
+    let target = content.slice_to(end);
+    if !is_name(target) || target.find(':').is_some() {
+        return Err(format!("Invalid processing instruction target '{}'", target));
+    }
+    if target != "xml" && target.eq_ignore_ascii_case("xml") {
+        return Err(format!("Reserved processing instruction target '{}'", target));
+    }
+
+    let rest = content.slice_from(end);
+    let data = rest.trim_left();
+    Ok((target, rest.slice_to(rest.len() - data.len()), data))
+}
+
+// Parses the pseudo-attributes of an XML declaration, `data` starting with "xml"
+pub fn parse_xml_decl(data: &str) -> Result<XmlDecl, ~str> {
+    static NAMES: [&'static str, ..3] = ["version", "encoding", "standalone"];
+    let mut decl = XmlDecl { version: ~"", encoding: None, standalone: None };
+    let mut next = 0;
+    let mut rest = data.slice_from(3);
+
+    loop {
+        let trimmed = rest.trim_left();
+        if trimmed.len() == 0 {
+            break;
+        }
+        if trimmed.len() == rest.len() {
+            return Err(~"Expected whitespace in XML declaration");
+        }
+
+        let eq = match trimmed.find('=') {
+            Some(i) => i,
+            None => return Err(~"Malformed XML declaration")
+        };
+        let name = trimmed.slice_to(eq).trim_right();
+        let value = trimmed.slice_from(eq + 1).trim_left();
+        if value.len() == 0 {
+            return Err(~"Malformed XML declaration");
+        }
+        let quote = match value.char_at(0) {
+            q @ '"' | q @ '\'' => q,
+            _ => return Err(~"XML declaration value not enclosed in ' or \"")
+        };
+        let value = value.slice_from(1);
+        let end = match value.find(quote) {
+            Some(i) => i,
+            None => return Err(~"Malformed XML declaration")
+        };
+        rest = value.slice_from(end + 1);
+        let value = value.slice_to(end);
+
+        // The pseudo-attributes must appear in the order version, encoding, standalone
+        let pos = match NAMES.iter().position(|n| *n == name) {
+            Some(pos) if pos >= next && (pos == 0 || next > 0) => pos,
+            _ => return Err(format!("Unexpected '{}' in XML declaration", name))
+        };
+        next = pos + 1;
+
+        match pos {
+            0 => {
+                let valid = value.starts_with("1.") && value.len() > 2 &&
+                            value.slice_from(2).chars().all(|c| c.is_digit());
+                if !valid {
+                    return Err(format!("Invalid XML version '{}'", value));
+                }
+                decl.version = value.to_owned();
+            }
+            1 => {
+                let valid = value.len() > 0 && value.char_at(0).is_alphabetic() &&
+                            value.chars().all(|c| c.is_ascii() && (c.is_alphanumeric() ||
+                                              c == '.' || c == '_' || c == '-'));
+                if !valid {
+                    return Err(format!("Invalid encoding name '{}'", value));
+                }
+                decl.encoding = Some(value.to_owned());
+            }
+            _ => {
+                decl.standalone = match value {
+                    "yes" => Some(true),
+                    "no" => Some(false),
+                    _ => return Err(format!("Invalid standalone value '{}'", value))
+                };
+            }
+        }
+    }
+
+    if decl.version.len() == 0 {
+        return Err(~"XML declaration lacks version");
+    }
+    Ok(decl)
+}
+
+// Checks that `qname` is a name with at most one colon separating prefix and local part
+pub fn check_qname(qname: &str) -> Result<(), ~str> {
+    let valid = match qname.find(':') {
+        None => is_name(qname),
+        Some(i) => {
+            let local = qname.slice_from(i+1);
+            is_name(qname.slice_to(i)) && is_name(local) && local.find(':').is_none()
+        }
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid name '{}'", qname))
+    }
+}
+
+// Checks that every '&' in `text` starts a character reference or a reference to
+// one of the predefined entities, as no others can be declared without a DTD
+pub fn check_references(text: &str) -> Result<(), ~str> {
+    static PREDEFINED: [&'static str, ..5] = ["lt", "gt", "amp", "apos", "quot"];
+    let mut rest = text;
+    loop {
+        let start = match rest.find('&') {
+            Some(i) => i,
+            None => return Ok(())
+        };
+        rest = rest.slice_from(start+1);
+        let end = match rest.find(';') {
+            Some(i) => i,
+            None => return Err(~"Unterminated reference, '&' must be escaped as '&amp;'")
+        };
+        let reference = rest.slice_to(end);
+        if reference.starts_with("#") {
+            if char_reference(reference.slice_from(1)).is_none() {
+                return Err(format!("Invalid reference '&{};'", reference));
+            }
+        } else if !PREDEFINED.iter().any(|e| *e == reference) {
+            if !is_name(reference) || reference.find(':').is_some() {
+                return Err(format!("Invalid reference '&{};'", reference));
+            }
+            return Err(format!("Unknown entity '&{};'", reference));
+        }
+        rest = rest.slice_from(end+1);
+    }
+}
+
+// Validates a namespace declaration made by the attribute `prefix:name="value"`
+pub fn check_ns_declaration(prefix: Option<&str>, name: &str, value: &str) -> Result<(), ~str> {
+    let declared = match prefix {
+        None if name == "xmlns" => None,
+        Some("xmlns") => Some(name),
+        _ => return Ok(())
+    };
+
+    match declared {
+        Some("xmlns") => Err(~"The 'xmlns' prefix must not be declared"),
+        Some("xml") if value != XML_NS => Err(~"The 'xml' prefix must not be rebound"),
+        Some("xml") => Ok(()),
+        _ if value == XML_NS || value == XMLNS_NS => {
+            Err(format!("Namespace '{}' must not be bound", value))
+        }
+        Some(p) if value.len() == 0 => Err(format!("Prefix '{}' must not be undeclared", p)),
+        _ => Ok(())
+    }
+}
+
+// Checks that `text` only holds characters allowed in XML, without decoding ASCII
+fn check_chars(text: &str) -> Result<(), ~str> {
+    let ascii = text.as_bytes().iter().all(|&b| {
+        b >= 0x20 && b < 0x80 || b == 0x09 || b == 0x0a || b == 0x0d
+    });
+    if ascii {
+        return Ok(());
+    }
+    match text.chars().find(|&c| !is_xml_char(c)) {
+        Some(c) => Err(format!("Invalid character 0x{:x}", c as uint)),
+        None => Ok(())
+    }
+}
+
+// Replaces "\r\n" and lone "\r" by "\n"
+fn normalize_newlines(text: &str) -> ~str {
+    text.replace("\r\n", "\n").replace("\r", "\n")
+}
+
+// Returns `text` with its line breaks normalized, borrowing it if there are no '\r'
+fn normalize_text<'a>(text: &'a str) -> Text<'a> {
+    if text.contains_char('\r') {
+        Owned(normalize_newlines(text))
+    } else {
+        Borrowed(text)
+    }
+}
+
+// Returns an attribute value with whitespace other than spaces replaced by spaces,
+// borrowing it if there is none. Characters written as references are kept.
+fn normalize_attribute<'a>(raw: &'a str) -> Text<'a> {
+    if !raw.as_bytes().iter().any(|&b| b == 0x09 || b == 0x0a || b == 0x0d) {
+        return Borrowed(raw);
+    }
+    Owned(normalize_newlines(raw).chars().map(|c| {
+        match c {
+            '\t' | '\n' => ' ',
+            _ => c
+        }
+    }).collect())
+}
+
+#[inline]
+fn is_space_byte(b: u8) -> bool {
+    b == 0x20 || b == 0x09 || b == 0x0a || b == 0x0d
+}
+
+impl<'a> Reader<'a> {
+    /// Returns a new `Reader` for the document `data`
+    pub fn new(data: &'a str) -> Reader<'a> {
+        Reader::with_config(data, ParserConfig::new())
+    }
+
+    /// Returns a new `Reader` for the document `data`, checking the limits in
+    /// `config` like `Parser` does
+    pub fn with_config(data: &'a str, config: ParserConfig) -> Reader<'a> {
+        let bindings = ~[("xml", Borrowed(XML_NS)), ("xmlns", Borrowed(XMLNS_NS))];
+        let start = Position { offset: 0, line: 1, col: 1 };
+        Reader::create(data, bindings, start, false, config)
+    }
+
+    /// Returns a `Reader` carrying on from `state` with `data`, the text following
+    /// what was read before. If `partial` is set more text follows `data`, and
+    /// markup it ends in is left for the next piece.
+    /// When recovering, each `Error` is reported and reading carries on as
+    /// described for `Parser::set_recover`.
+    pub fn resume(data: &'a str, state: &'a ReaderState, config: ParserConfig,
+                  recover: bool, partial: bool) -> Reader<'a> {
+        let bindings = state.bindings.iter().map(|&(ref prefix, ref ns)| {
+            (prefix.as_slice(), Borrowed(ns.as_slice()))
+        }).collect();
+        let mut reader = Reader::create(data, bindings, state.position.clone(),
+                                        state.after_cr, config);
+        reader.scopes = state.scopes.clone();
+        reader.elements = state.elements.iter().map(|e| e.as_slice()).collect();
+        reader.root_seen = state.root_seen;
+        reader.expansions = state.expansions;
+        reader.skipping = state.skipping;
+        reader.awaiting = state.awaiting;
+        reader.failed = state.failed;
+        reader.done = state.failed;
+        reader.recover = recover;
+        reader.partial = partial;
+        reader
+    }
+
+    fn create(data: &'a str, bindings: ~[(&'a str, Text<'a>)], start: Position, start_cr: bool,
+              config: ParserConfig) -> Reader<'a> {
+        // Only the part of `data` within the document size limit is read
+        let mut end = data.len();
+        match config.max_document_size {
+            Some(max) if start.offset + end > max => {
+                end = if max > start.offset { max - start.offset } else { 0 };
+                while !data.is_char_boundary(end) {
+                    end -= 1;
+                }
+            }
+            _ => ()
+        }
+        let line = start.line;
+        let col = start.col - 1;
+        Reader {
+            data: data.slice_to(end),
+            pos: 0,
+            bindings: bindings,
+            scopes: ~[],
+            elements: ~[],
+            root_seen: false,
+            config: config,
+            expansions: 0,
+            recover: false,
+            partial: false,
+            oversized: end < data.len(),
+            skipping: false,
+            awaiting: None,
+            queue: ~[],
+            last: (0, 0),
+            start: start,
+            start_cr: start_cr,
+            counted: 0,
+            line: line,
+            col: col,
+            after_cr: start_cr,
+            failed: false,
+            done: false
+        }
+    }
+
+    /// Returns the state to carry on from with the text following `data`, and the
+    /// number of bytes of `data` which were read. Markup left incomplete by a
+    /// partial `data` was not read.
+    pub fn suspend(&mut self) -> (ReaderState, uint) {
+        let pos = self.pos;
+        let position = self.position(pos);
+        let state = ReaderState {
+            bindings: self.bindings.iter().map(|&(prefix, ref ns)| {
+                (prefix.to_owned(), ns.as_slice().to_owned())
+            }).collect(),
+            scopes: self.scopes.clone(),
+            elements: self.elements.iter().map(|&e| e.to_owned()).collect(),
+            root_seen: self.root_seen,
+            expansions: self.expansions,
+            position: position,
+            after_cr: self.after_cr,
+            skipping: self.skipping,
+            awaiting: self.awaiting,
+            failed: self.failed
+        };
+        (state, pos)
+    }
+
+    /// Returns the `Span` the last event was read from.
+    /// The end tag of an empty-element tag shares its span.
+    pub fn span(&mut self) -> Span {
+        let (start, end) = self.last;
+        let start = self.position(start);
+        Span { start: start, end: self.position(end) }
+    }
+
+    // Returns the position of the byte offset `offset`, counting on from the last
+    // position asked for. "\r\n" and a lone "\r" count as a single line break.
+    fn position(&mut self, offset: uint) -> Position {
+        let offset = cmp::min(offset, self.data.len());
+        if offset < self.counted {
+            self.counted = 0;
+            self.line = self.start.line;
+            self.col = self.start.col - 1;
+            self.after_cr = self.start_cr;
+        }
+        let data = self.data;
+        for &b in data.as_bytes().slice(self.counted, offset).iter() {
+            match b {
+                // The '\n' of "\r\n" was counted with the '\r'
+                0x0a if self.after_cr => (),
+                0x0a | 0x0d => {
+                    self.line += 1;
+                    self.col = 0;
+                }
+                // Continuation bytes of UTF-8 sequences are not characters of their own
+                _ if b & 0xc0 == 0x80 => (),
+                _ => self.col += 1
+            }
+            self.after_cr = b == 0x0d;
+        }
+        self.counted = offset;
+        Position { offset: self.start.offset + offset, line: self.line, col: self.col + 1 }
+    }
+
+    // Builds an `Error` for the character at byte offset `offset`
+    fn error_at(&mut self, offset: uint, kind: ErrorKind, msg: ~str) -> Error {
+        let position = self.position(offset);
+        Error { line: position.line, col: position.col, msg: msg, kind: kind }
+    }
+
+    // The offset of the first occurrence of `pattern` at or after `from`
+    fn find_from(&self, from: uint, pattern: &str) -> Option<uint> {
+        if from > self.data.len() {
+            return None;
+        }
+        self.data.slice_from(from).find_str(pattern).map(|i| from + i)
+    }
+
+    fn byte_at(&self, offset: uint) -> Option<u8> {
+        if offset < self.data.len() {
+            Some(self.data[offset])
+        } else {
+            None
+        }
+    }
+
+    fn skip_space(&self, mut offset: uint) -> uint {
+        while self.byte_at(offset).map_default(false, is_space_byte) {
+            offset += 1;
+        }
+        offset
+    }
+
+    // The end of the name starting at `offset`, stopping at whitespace or any of `stops`
+    fn name_end(&self, mut offset: uint, stops: &[u8]) -> uint {
+        let bytes = self.data.as_bytes();
+        while offset < bytes.len() && !is_space_byte(bytes[offset]) &&
+              !stops.contains(&bytes[offset]) {
+            offset += 1;
+        }
+        offset
+    }
+
+    // Checks the name from `start` to `end` against `max_name_length`
+    fn check_name(&self, start: uint, end: uint) -> Result<(), Stop> {
+        check_length(self.config.max_name_length, start, end - start,
+                     NameLengthLimitExceeded, "Name")
+    }
+
+    // Waits for the rest of the text starting at `offset` inside `markup`, as long
+    // as the text read so far is within `max_text_size`
+    fn incomplete<T>(&self, offset: uint, markup: &'static str) -> Result<T, Stop> {
+        let offset = cmp::min(offset, self.data.len());
+        check!(check_length(self.config.max_text_size, offset, self.data.len() - offset,
+                            TextSizeLimitExceeded, "Text"));
+        Err(Incomplete(markup))
+    }
+
+    // Whether the markup left incomplete by the last piece at `self.pos` can still
+    // not be complete, as the end it waits for was not passed in yet. Only the text
+    // which was not searched before is searched, so markup passed in many small
+    // pieces is read once.
+    fn still_incomplete(&mut self) -> bool {
+        let (markup, searched) = match self.awaiting.take() {
+            Some(awaiting) => awaiting,
+            None => return false
+        };
+        if !self.partial || self.oversized {
+            return false;
+        }
+        let end = match markup {
+            "character data" => "<",
+            "comment" => "-->",
+            "processing instruction" => "?>",
+            "CDATA section" => "]]>",
+            _ => ">"
+        };
+        // The end may have been split between the pieces
+        let from = self.pos + searched - cmp::min(searched, end.len() - 1);
+        if self.find_from(from, end).is_some() {
+            return false;
+        }
+        // Text over the limit is reported by reading it
+        let len = self.data.len() - self.pos;
+        if check_length(self.config.max_text_size, self.pos, len, TextSizeLimitExceeded, "Text").is_err() {
+            return false;
+        }
+        self.awaiting = Some((markup, len));
+        true
+    }
+
+    // Returns `text`, found at `offset`, with its line breaks or, for attribute
+    // values, its whitespace normalized and its references replaced. References are
+    // counted against `max_entity_expansions`.
+    fn expand(&mut self, text: &'a str, offset: uint, attribute: bool) -> Result<Text<'a>, Stop> {
+        let normalized = if attribute { normalize_attribute(text) } else { normalize_text(text) };
+        if !text.contains_char('&') {
+            return Ok(normalized);
+        }
+        match check_references(text) {
+            Ok(()) => (),
+            Err(msg) => return invalid(offset, msg)
+        }
+        self.expansions += text.as_bytes().iter().count(|&b| b == '&' as u8);
+        match self.config.max_entity_expansions {
+            Some(max) if self.expansions > max => {
+                let msg = format!("More than {} references expanded", max);
+                return Err(Invalid(offset, EntityExpansionLimitExceeded, msg));
+            }
+            _ => ()
+        }
+        match try_unescape(normalized.as_slice()) {
+            Ok(text) => Ok(Owned(text)),
+            Err(msg) => invalid(offset, msg)
+        }
+    }
+
+    fn namespace_for_prefix(&self, prefix: &str) -> Option<Text<'a>> {
+        match self.bindings.rev_iter().find(|&&(p, _)| p == prefix) {
+            Some(&(_, ref ns)) if ns.as_slice().len() > 0 => Some(ns.clone()),
+            _ => None
+        }
+    }
+
+    fn resolve_prefix(&self, prefix: Option<&str>) -> Result<Option<Text<'a>>, ~str> {
+        match prefix {
+            None => Ok(self.namespace_for_prefix("")),
+            Some(pre) => match self.namespace_for_prefix(pre) {
+                Some(ns) => Ok(Some(ns)),
+                None => Err(format!("Unbound prefix: '{}'", pre))
+            }
+        }
+    }
+
+    // Returns the end tag of the innermost open element, dropping its bindings
+    fn close_innermost(&mut self) -> EventRef<'a> {
+        let (prefix, name) = split_qname(self.elements.pop());
+        // The prefix was resolved when the element was opened
+        let ns = match self.resolve_prefix(prefix) {
+            Ok(ns) => ns,
+            Err(_) => None
+        };
+        match self.scopes.pop_opt() {
+            Some(mark) => self.bindings.truncate(mark),
+            None => ()
+        }
+        EndTagRef(EndTagRef { name: name, ns: ns, prefix: prefix })
+    }
+
+    // Queues an error found at `offset`
+    fn report(&mut self, offset: uint, kind: ErrorKind, msg: ~str) {
+        let err = self.error_at(offset, kind, msg);
+        self.queue.push((Err(err), offset, offset));
+    }
+
+    // Checks the end of the document. When recovering, the elements still open
+    // are closed.
+    fn end(&mut self) {
+        self.done = true;
+        let end = self.data.len();
+        if self.oversized {
+            let msg = format!("Document larger than {} bytes", self.config.max_document_size.unwrap());
+            self.report(end, DocumentSizeLimitExceeded, msg);
+            self.failed = true;
+            return;
+        }
+        while !self.elements.is_empty() {
+            let msg = format!("Unclosed element '{}'", *self.elements.last());
+            self.report(end, SyntaxError, msg);
+            if !self.recover {
+                self.failed = true;
+                return;
+            }
+            let event = self.close_innermost();
+            self.queue.push((Ok(event), end, end));
+        }
+        if !self.root_seen {
+            self.report(end, SyntaxError, ~"Document lacks a root element");
+            self.failed = true;
+        }
+    }
+
+    // Reads the character data starting at `self.pos`, up to the next '<'.
+    // Text after the root element is only checked, as the document ends there.
+    fn read_text(&mut self) -> Result<Option<EventRef<'a>>, Stop> {
+        let start = self.pos;
+        let end = match self.find_from(start, "<") {
+            Some(end) => end,
+            None if self.partial || self.oversized => return self.incomplete(start, "character data"),
+            None => self.data.len()
+        };
+        let text = self.data.slice(start, end);
+        self.pos = end;
+
+        check!(check_length(self.config.max_text_size, start, text.len(),
+                            TextSizeLimitExceeded, "Text"));
+        match check_chars(text) {
+            Ok(()) => (),
+            Err(msg) => return invalid(start, msg)
+        }
+        if self.elements.is_empty() {
+            if !text.chars().all(is_space) {
+                return invalid(start, ~"Character data outside of root element");
+            }
+            if end == self.data.len() {
+                return Ok(None);
+            }
+        }
+        match text.find_str("]]>") {
+            Some(i) => return invalid(start + i + 2, ~"']]>' is not allowed in character data"),
+            None => ()
+        }
+        if end == self.data.len() && !self.recover {
+            // The open elements are reported by `end`
+            return Ok(None);
+        }
+        let text = check!(self.expand(text, start, false));
+        Ok(Some(CharactersRef(text)))
+    }
+
+    // Reads the markup starting with '<' at `self.pos`.
+    // Returns `None` for markup which is not reported, like a DOCTYPE.
+    fn read_markup(&mut self) -> Result<Option<EventRef<'a>>, Stop> {
+        let start = self.pos;
+        let rest = self.data.slice_from(start);
+        if rest.starts_with("<?") {
+            self.read_pi(start)
+        } else if rest.starts_with("<!--") {
+            self.read_comment(start)
+        } else if rest == "<!-" {
+            Err(Incomplete("comment"))
+        } else if rest.starts_with("<!-") {
+            invalid(start + 3, ~"Expected 2nd '-' to start comment")
+        } else if rest.starts_with("<![") {
+            self.read_cdata(start)
+        } else if rest.starts_with("<!D") {
+            self.read_doctype(start)
+        } else if rest == "<!" || rest == "<" {
+            Err(Incomplete("tag"))
+        } else if rest.starts_with("<!") {
+            invalid(start + 2, ~"Malformed XML")
+        } else if rest.starts_with("</") {
+            self.read_end_tag(start)
+        } else {
+            self.read_start_tag(start)
+        }
+    }
+
+    fn read_pi(&mut self, start: uint) -> Result<Option<EventRef<'a>>, Stop> {
+        let end = match self.find_from(start + 2, "?>") {
+            Some(end) => end,
+            None => return self.incomplete(start + 2, "processing instruction")
+        };
+        let content = self.data.slice(start + 2, end);
+        check!(check_length(self.config.max_text_size, start + 2, content.len(),
+                            TextSizeLimitExceeded, "Text"));
+        self.pos = end + 2;
+        match check_chars(content) {
+            Ok(()) => (),
+            Err(msg) => return invalid(start, msg)
+        }
+
+        let (target, space, data) = match split_pi(content) {
+            Ok(parts) => parts,
+            Err(msg) => return invalid(end + 1, msg)
+        };
+        if target == "xml" {
+            // Only a PI at the very start of the document is an XML declaration
+            if self.start.offset + start != 0 {
+                return invalid(end + 1, ~"XML declaration only allowed at the start of the document");
+            }
+            return match parse_xml_decl(content) {
+                Ok(decl) => Ok(Some(XmlDeclRef(decl))),
+                Err(msg) => invalid(end + 1, msg)
+            };
+        }
+        Ok(Some(PIRef(PIRef {
+            target: target,
+            space: normalize_text(space),
+            data: normalize_text(data)
+        })))
+    }
+
+    fn read_comment(&mut self, start: uint) -> Result<Option<EventRef<'a>>, Stop> {
+        let end = match self.find_from(start + 4, "--") {
+            Some(end) => end,
+            None => return self.incomplete(start + 4, "comment")
+        };
+        match self.byte_at(end + 2) {
+            Some(b) if b == '>' as u8 => (),
+            Some(_) => return invalid(end + 2, ~"Not more than one adjacent '-' allowed in a comment"),
+            None => return self.incomplete(start + 4, "comment")
+        }
+        let content = self.data.slice(start + 4, end);
+        check!(check_length(self.config.max_text_size, start + 4, content.len(),
+                            TextSizeLimitExceeded, "Text"));
+        self.pos = end + 3;
+        match check_chars(content) {
+            Ok(()) => Ok(Some(CommentRef(normalize_text(content)))),
+            Err(msg) => invalid(start, msg)
+        }
+    }
+
+    fn read_cdata(&mut self, start: uint) -> Result<Option<EventRef<'a>>, Stop> {
+        if self.elements.is_empty() {
+            return invalid(start + 2, ~"CDATA section outside of root element");
+        }
+        let rest = self.data.slice_from(start);
+        if "<![CDATA[".starts_with(rest) {
+            return Err(Incomplete("CDATA section"));
+        }
+        if !rest.starts_with("<![CDATA[") {
+            return invalid(start + 3, ~"Invalid CDATA opening sequence");
+        }
+        let end = match self.find_from(start + 9, "]]>") {
+            Some(end) => end,
+            None => return self.incomplete(start + 9, "CDATA section")
+        };
+        let content = self.data.slice(start + 9, end);
+        check!(check_length(self.config.max_text_size, start + 9, content.len(),
+                            TextSizeLimitExceeded, "Text"));
+        self.pos = end + 3;
+        match check_chars(content) {
+            Ok(()) => Ok(Some(CDATARef(normalize_text(content)))),
+            Err(msg) => invalid(start, msg)
+        }
+    }
+
+    // Skips a DOCTYPE, including its internal subset
+    fn read_doctype(&mut self, start: uint) -> Result<Option<EventRef<'a>>, Stop> {
+        if self.root_seen {
+            return invalid(start + 2, ~"DOCTYPE after root element");
+        }
+        let rest = self.data.slice_from(start);
+        if "<!DOCTYPE".starts_with(rest) {
+            return Err(Incomplete("DOCTYPE"));
+        }
+        if !rest.starts_with("<!DOCTYPE") ||
+           !self.byte_at(start + 9).map_default(false, is_space_byte) {
+            return invalid(start + 2, ~"Invalid DOCTYPE");
+        }
+
+        let mut delim = None;
+        let mut in_subset = false;
+        let mut offset = start + 10;
+        loop {
+            let b = match self.byte_at(offset) {
+                Some(b) => b as char,
+                None => return Err(Incomplete("DOCTYPE"))
+            };
+            offset += 1;
+            match delim {
+                Some(d) if b == d => delim = None,
+                Some(_) => (),
+                None => match b {
+                    '"' | '\'' => delim = Some(b),
+                    '[' if !in_subset => in_subset = true,
+                    ']' if in_subset => in_subset = false,
+                    '>' if !in_subset => break,
+                    _ => ()
+                }
+            }
+        }
+        self.pos = offset;
+        Ok(None)
+    }
+
+    fn read_end_tag(&mut self, start: uint) -> Result<Option<EventRef<'a>>, Stop> {
+        let name_end = self.name_end(start + 2, &['>' as u8]);
+        check!(self.check_name(start + 2, name_end));
+        if name_end == self.data.len() {
+            return Err(Incomplete("tag"));
+        }
+        let qname = self.data.slice(start + 2, name_end);
+        match check_qname(qname) {
+            Ok(()) => (),
+            Err(msg) => return invalid(name_end, msg)
+        }
+        let close = self.skip_space(name_end);
+        if close == self.data.len() {
+            return Err(Incomplete("tag"));
+        }
+
+        // When recovering, the elements left open inside the one closed are closed too
+        let depth = match self.elements.rev_iter().position(|open| *open == qname) {
+            Some(depth) if depth == 0 || self.recover => depth,
+            _ if self.elements.is_empty() => {
+                return invalid(name_end, format!("Unexpected end tag '</{}>'", qname));
+            }
+            _ => {
+                let msg = format!("Expected '</{}>', found '</{}>'", *self.elements.last(), qname);
+                return invalid(name_end, msg);
+            }
+        };
+        if self.data[close] != '>' as u8 {
+            return invalid(close, ~"Expected '>' to close tag, or LWS");
+        }
+        self.pos = close + 1;
+        if depth == 0 {
+            return Ok(Some(self.close_innermost()));
+        }
+
+        let msg = format!("Expected '</{}>', found '</{}>'", *self.elements.last(), qname);
+        self.report(name_end, SyntaxError, msg);
+        for _ in range(0, depth + 1) {
+            let event = self.close_innermost();
+            self.queue.push((Ok(event), start, self.pos));
+        }
+        Ok(None)
+    }
+
+    fn read_start_tag(&mut self, start: uint) -> Result<Option<EventRef<'a>>, Stop> {
+        // Nothing is kept from a tag which is broken, or read again once complete
+        let mark = self.bindings.len();
+        let expansions = self.expansions;
+        let result = self.read_start_tag_from(start, mark);
+        if result.is_err() {
+            self.bindings.truncate(mark);
+            self.expansions = expansions;
+        }
+        result
+    }
+
+    fn read_start_tag_from(&mut self, start: uint, mark: uint) -> Result<Option<EventRef<'a>>, Stop> {
+        let first = self.data.char_at(start + 1);
+        if !is_name_start_char(first) {
+            return invalid(start + 1, format!("Unexpected '{}' after '<'", first));
+        }
+        let name_end = self.name_end(start + 1, &['/' as u8, '>' as u8]);
+        check!(self.check_name(start + 1, name_end));
+        if name_end == self.data.len() {
+            return Err(Incomplete("tag"));
+        }
+        let qname = self.data.slice(start + 1, name_end);
+        match check_qname(qname) {
+            Ok(()) => (),
+            Err(msg) => return invalid(name_end, msg)
+        }
+
+        // Attribute prefixes are resolved once all declarations of the tag are read
+        let mut attributes: ~[AttributeRef<'a>] = ~[];
+        let mut prefixes: ~[Option<&'a str>] = ~[];
+        let mut offset = name_end;
+        let mut empty = false;
+        loop {
+            let after_space = self.skip_space(offset);
+            let spaced = after_space > offset;
+            offset = after_space;
+            match self.byte_at(offset) {
+                None => return Err(Incomplete("tag")),
+                Some(b) if b == '>' as u8 => break,
+                Some(b) if b == '/' as u8 => {
+                    match self.byte_at(offset + 1) {
+                        Some(b) if b == '>' as u8 => (),
+                        Some(_) => return invalid(offset + 1, ~"Expected '>' to close tag"),
+                        None => return Err(Incomplete("tag"))
+                    }
+                    offset += 1;
+                    empty = true;
+                    break;
+                }
+                Some(_) if !spaced => return invalid(offset, ~"Expected whitespace between attributes"),
+                Some(_) => ()
+            }
+
+            let c = self.data.char_at(offset);
+            if !is_name_start_char(c) {
+                return invalid(offset, format!("Unexpected '{}' in tag", c));
+            }
+            let attr_end = self.name_end(offset, &['=' as u8, '/' as u8, '>' as u8]);
+            check!(self.check_name(offset, attr_end));
+            let attr_qname = self.data.slice(offset, attr_end);
+            let eq = self.skip_space(attr_end);
+            match self.byte_at(eq) {
+                Some(b) if b == '=' as u8 => (),
+                Some(b) if b == '/' as u8 || b == '>' as u8 => {
+                    return invalid(eq, format!("Attribute '{}' lacks a value", attr_qname));
+                }
+                Some(_) => return invalid(eq, ~"Space occured in attribute name"),
+                None => return Err(Incomplete("tag"))
+            }
+            match check_qname(attr_qname) {
+                Ok(()) => (),
+                Err(msg) => return invalid(eq, msg)
+            }
+            match self.config.max_attributes {
+                Some(max) if attributes.len() >= max => {
+                    let msg = format!("Element '{}' has more than {} attributes", qname, max);
+                    return Err(Invalid(offset, AttributeLimitExceeded, msg));
+                }
+                _ => ()
+            }
+
+            let quote_at = self.skip_space(eq + 1);
+            let quote = match self.byte_at(quote_at) {
+                Some(q) if q == '"' as u8 || q == '\'' as u8 => q as char,
+                Some(_) => return invalid(quote_at, ~"Attribute value not enclosed in ' or \""),
+                None => return Err(Incomplete("tag"))
+            };
+            let value_end = match self.data.slice_from(quote_at + 1).find(quote) {
+                Some(i) => quote_at + 1 + i,
+                None => return self.incomplete(quote_at + 1, "tag")
+            };
+            let raw = self.data.slice(quote_at + 1, value_end);
+            check!(check_length(self.config.max_text_size, quote_at + 1, raw.len(),
+                                TextSizeLimitExceeded, "Text"));
+            match raw.find('<') {
+                Some(i) => return invalid(quote_at + 1 + i, ~"'<' is not allowed in attribute values"),
+                None => ()
+            }
+            match check_chars(raw) {
+                Ok(()) => (),
+                Err(msg) => return invalid(quote_at + 1, msg)
+            }
+            let value = check!(self.expand(raw, value_end, true));
+            offset = value_end + 1;
+
+            let (prefix, name) = split_qname(attr_qname);
+            let duplicate = range(0, attributes.len()).any(|i| {
+                attributes[i].name == name && prefixes[i] == prefix
+            });
+            if duplicate {
+                return invalid(value_end, format!("Duplicate attribute '{}'", attr_qname));
+            }
+            match check_ns_declaration(prefix, name, value.as_slice()) {
+                Ok(()) => (),
+                Err(msg) => return invalid(value_end, msg)
+            }
+            match prefix {
+                None if name == "xmlns" => self.bindings.push(("", value.clone())),
+                Some("xmlns") => self.bindings.push((name, value.clone())),
+                _ => ()
+            }
+            attributes.push(AttributeRef { name: name, ns: None, value: value });
+            prefixes.push(prefix);
+        }
+        let end = offset;
+
+        let (prefix, name) = split_qname(qname);
+        let ns = match self.resolve_prefix(prefix) {
+            Ok(ns) => ns,
+            Err(msg) => return invalid(end, msg)
+        };
+        for (attr, prefix) in attributes.mut_iter().zip(prefixes.iter()) {
+            if prefix.is_some() {
+                attr.ns = match self.resolve_prefix(*prefix) {
+                    Ok(ns) => ns,
+                    Err(msg) => return invalid(end, msg)
+                };
+            }
+        }
+
+        // Attributes with different prefixes may still share a namespace
+        for i in range(0, attributes.len()) {
+            for j in range(i+1, attributes.len()) {
+                if attributes[i].name == attributes[j].name && attributes[i].ns == attributes[j].ns {
+                    return invalid(end, format!("Duplicate attribute '{}'", attributes[j].name));
+                }
+            }
+        }
+
+        if self.elements.is_empty() && self.root_seen {
+            return invalid(end, format!("Element '{}' found after the root element", qname));
+        }
+        match self.config.max_depth {
+            Some(max) if self.elements.len() >= max => {
+                let msg = format!("Elements nested deeper than {} levels", max);
+                return Err(Invalid(start, DepthLimitExceeded, msg));
+            }
+            _ => ()
+        }
+        self.root_seen = true;
+        self.elements.push(qname);
+        self.scopes.push(mark);
+        self.pos = end + 1;
+
+        let tag = StartTagRef(StartTagRef {
+            name: name,
+            ns: ns,
+            prefix: prefix,
+            attributes: attributes
+        });
+        if empty {
+            // The end tag of an empty-element tag is reported right after it
+            let event = self.close_innermost();
+            self.queue.push((Ok(event), start, self.pos));
+        }
+        Ok(Some(tag))
+    }
+}
+
+impl<'a> Iterator<Result<EventRef<'a>, Error>> for Reader<'a> {
+    /// Returns the next event, or the first `Error` found in the document
+    fn next(&mut self) -> Option<Result<EventRef<'a>, Error>> {
+        loop {
+            if !self.queue.is_empty() {
+                let (result, start, end) = self.queue.shift();
+                self.last = (start, end);
+                return Some(result);
+            }
+            if self.done {
+                return None;
+            }
+            if self.skipping {
+                match self.find_from(self.pos, "<") {
+                    Some(next) => {
+                        self.pos = next;
+                        self.skipping = false;
+                    }
+                    None => self.pos = self.data.len()
+                }
+            }
+            if self.pos >= self.data.len() {
+                if self.partial && !self.oversized {
+                    return None;
+                }
+                self.end();
+                continue;
+            }
+
+            if self.still_incomplete() {
+                return None;
+            }
+            let start = self.pos;
+            let result = if self.data[start] == '<' as u8 {
+                self.read_markup()
+            } else {
+                self.read_text()
+            };
+            match result {
+                Ok(Some(event)) => {
+                    self.last = (start, self.pos);
+                    return Some(Ok(event));
+                }
+                Ok(None) => (),
+                Err(Incomplete(_)) if self.oversized => self.end(),
+                // The markup is read again once the rest of it was passed in
+                Err(Incomplete(markup)) if self.partial => {
+                    self.awaiting = Some((markup, self.data.len() - start));
+                    return None;
+                }
+                Err(Incomplete(markup)) => {
+                    let end = self.data.len();
+                    self.report(end, SyntaxError, format!("Unexpected end of document inside {}", markup));
+                    if self.recover {
+                        self.pos = end;
+                    } else {
+                        self.failed = true;
+                        self.done = true;
+                    }
+                }
+                Err(Invalid(offset, kind, msg)) => {
+                    // Exceeding a limit is never recovered from
+                    let fatal = !self.recover || kind != SyntaxError;
+                    self.report(offset, kind, msg);
+                    if fatal {
+                        self.failed = true;
+                        self.done = true;
+                    } else if self.pos == start {
+                        self.pos = cmp::min(offset + 1, self.data.len());
+                        self.skipping = true;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{Event, XmlDecl, PI, StartTag, EndTag, Characters, CDATA, Comment};
+use super::{Element, Attribute, Error};
+use super::namespace::{declaration, XMLNS_NS};
+use super::atom::Atom;
+use super::Parser::Parser;
+use super::ElementBuilder::{ElementBuilder, Root};
+use super::XmlWriter::XmlWriter;
+
+#[deriving(Clone,Eq)]
+/// The name of an element, with its namespace resolved
+pub struct QName {
+    /// The local name
+    name: Atom,
+    /// The namespace, if any
+    ns: Option<~str>,
+    /// The prefix the name was written with, if any
+    prefix: Option<~str>
+}
+
+/// Callbacks for the content of a document, as an alternative to handling `Event`s
+/// in a closure. All methods do nothing by default.
+pub trait ContentHandler {
+    /// Called before any other method
+    fn start_document(&mut self) {
+    }
+
+    /// Called once the document has ended
+    fn end_document(&mut self) {
+    }
+
+    /// Called for the XML declaration
+    fn xml_declaration(&mut self, _decl: &XmlDecl) {
+    }
+
+    /// Called before the `start_element` of the element declaring `prefix`.
+    /// The default namespace is declared with an empty prefix.
+    fn start_prefix_mapping(&mut self, _prefix: &str, _ns: &str) {
+    }
+
+    /// Called after the `end_element` of the element which declared `prefix`
+    fn end_prefix_mapping(&mut self, _prefix: &str) {
+    }
+
+    /// Called for a start tag. Namespace declarations are reported through
+    /// `start_prefix_mapping` instead of `attributes`.
+    fn start_element(&mut self, _name: &QName, _attributes: &[Attribute]) {
+    }
+
+    /// Called for an end tag
+    fn end_element(&mut self, _name: &QName) {
+    }
+
+    /// Called for character data
+    fn characters(&mut self, _text: &str) {
+    }
+
+    /// Called for a CDATA section
+    fn cdata(&mut self, _text: &str) {
+    }
+
+    /// Called for a comment
+    fn comment(&mut self, _text: &str) {
+    }
+
+    /// Called for a processing instruction
+    fn processing_instruction(&mut self, _pi: &PI) {
+    }
+
+    /// Called for each error found while parsing
+    fn error(&mut self, _err: &Error) {
+    }
+}
+
+/// Translates the results of a `Parser` into calls on a `ContentHandler`
+pub struct SaxDispatcher<'a, H> {
+    priv handler: &'a mut H,
+    priv started: bool,
+    // The prefixes declared by each open element
+    priv mappings: ~[~[~str]]
+}
+
+impl<'a, H: ContentHandler> SaxDispatcher<'a, H> {
+    /// Returns a new `SaxDispatcher` calling `handler`
+    pub fn new(handler: &'a mut H) -> SaxDispatcher<'a, H> {
+        SaxDispatcher { handler: handler, started: false, mappings: ~[] }
+    }
+
+    fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            self.handler.start_document();
+        }
+    }
+
+    /// Hands one result of `Parser::parse_str` or `Parser::finish` to the handler
+    pub fn dispatch(&mut self, result: Result<Event, Error>) {
+        self.start();
+        let event = match result {
+            Ok(event) => event,
+            Err(err) => {
+                self.handler.error(&err);
+                return;
+            }
+        };
+        match event {
+            XmlDecl(decl) => self.handler.xml_declaration(&decl),
+            PI(pi) => self.handler.processing_instruction(&pi),
+            StartTag(StartTag { name, ns, prefix, attributes }) => {
+                let mut declared = ~[];
+                let mut plain = ~[];
+                for attr in attributes.move_iter() {
+                    match declaration(&attr) {
+                        Some((prefix, ns)) => {
+                            self.handler.start_prefix_mapping(prefix, ns);
+                            declared.push(prefix.to_owned());
+                        }
+                        None => plain.push(attr.clone())
+                    }
+                }
+                self.mappings.push(declared);
+                self.handler.start_element(&QName { name: name, ns: ns, prefix: prefix }, plain);
+            }
+            EndTag(EndTag { name, ns, prefix }) => {
+                self.handler.end_element(&QName { name: name, ns: ns, prefix: prefix });
+                match self.mappings.pop_opt() {
+                    Some(declared) => {
+                        for prefix in declared.rev_iter() {
+                            self.handler.end_prefix_mapping(*prefix);
+                        }
+                    }
+                    None => ()
+                }
+            }
+            Characters(text) => self.handler.characters(text),
+            CDATA(text) => self.handler.cdata(text),
+            Comment(text) => self.handler.comment(text)
+        }
+    }
+
+    /// Ends the document, calling `end_document`
+    pub fn end(&mut self) {
+        self.start();
+        self.handler.end_document();
+    }
+}
+
+/// Parses `data` as a complete document, calling `handler`
+pub fn parse_with_handler<H: ContentHandler>(data: &str, handler: &mut H) {
+    let mut p = Parser::new();
+    let mut d = SaxDispatcher::new(handler);
+    p.parse_str(data, |result| d.dispatch(result));
+    p.finish(|result| d.dispatch(result));
+    d.end();
+}
+
+// Turns prefix mappings back into namespace declaration attributes
+fn declaration_attribute(prefix: &str, ns: &str) -> Attribute {
+    if prefix.is_empty() {
+        Attribute { name: Atom::new("xmlns"), ns: None, value: ns.to_owned() }
+    } else {
+        Attribute { name: Atom::new(prefix), ns: Some(XMLNS_NS.to_owned()), value: ns.to_owned() }
+    }
+}
+
+// Rebuilds the `StartTag` event of a `start_element` call
+fn start_tag(name: &QName, declared: ~[Attribute], attributes: &[Attribute]) -> Event {
+    let mut all = declared;
+    all.push_all(attributes);
+    StartTag(StartTag { name: name.name.clone(), ns: name.ns.clone(), prefix: name.prefix.clone(),
+                        attributes: all })
+}
+
+fn end_tag(name: &QName) -> Event {
+    EndTag(EndTag { name: name.name.clone(), ns: name.ns.clone(), prefix: name.prefix.clone() })
+}
+
+/// A `ContentHandler` building an `Element` with an `ElementBuilder`
+pub struct BuildHandler {
+    priv builder: ElementBuilder,
+    priv declared: ~[Attribute],
+    priv root: Option<Element>,
+    priv error: Option<~str>
+}
+
+impl BuildHandler {
+    /// Returns a new `BuildHandler` using a default `ElementBuilder`
+    pub fn new() -> BuildHandler {
+        BuildHandler::with_builder(ElementBuilder::new())
+    }
+
+    /// Returns a new `BuildHandler` using `builder`, which may be configured
+    pub fn with_builder(builder: ElementBuilder) -> BuildHandler {
+        BuildHandler { builder: builder, declared: ~[], root: None, error: None }
+    }
+
+    /// Returns the built root element, or the first error
+    pub fn unwrap(self) -> Result<Element, ~str> {
+        match (self.error, self.root) {
+            (Some(msg), _) => Err(msg),
+            (None, Some(root)) => Ok(root),
+            (None, None) => Err(~"No root element")
+        }
+    }
+
+    fn push(&mut self, event: Event) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.builder.push_event(event) {
+            Ok(Some(Root(root))) => self.root = Some(root),
+            Ok(_) => (),
+            Err(msg) => self.error = Some(msg)
+        }
+    }
+}
+
+impl ContentHandler for BuildHandler {
+    fn start_prefix_mapping(&mut self, prefix: &str, ns: &str) {
+        self.declared.push(declaration_attribute(prefix, ns));
+    }
+
+    fn start_element(&mut self, name: &QName, attributes: &[Attribute]) {
+        let declared = ::std::util::replace(&mut self.declared, ~[]);
+        self.push(start_tag(name, declared, attributes));
+    }
+
+    fn end_element(&mut self, name: &QName) {
+        self.push(end_tag(name));
+    }
+
+    fn characters(&mut self, text: &str) {
+        self.push(Characters(text.to_owned()));
+    }
+
+    fn cdata(&mut self, text: &str) {
+        self.push(CDATA(text.to_owned()));
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.push(Comment(text.to_owned()));
+    }
+
+    fn processing_instruction(&mut self, pi: &PI) {
+        self.push(PI(pi.clone()));
+    }
+
+    fn error(&mut self, err: &Error) {
+        if self.error.is_none() {
+            self.error = Some(format!("{}:{}: {}", err.line, err.col, err.msg));
+        }
+    }
+}
+
+/// A `ContentHandler` writing the document with an `XmlWriter`
+pub struct WriteHandler<W> {
+    priv writer: XmlWriter<W>,
+    priv declared: ~[Attribute],
+    priv error: Option<~str>
+}
+
+impl<W: Writer> WriteHandler<W> {
+    /// Returns a new `WriteHandler` using `writer`
+    pub fn new(writer: XmlWriter<W>) -> WriteHandler<W> {
+        WriteHandler { writer: writer, declared: ~[], error: None }
+    }
+
+    /// Returns the underlying `Writer`, or the first error
+    pub fn unwrap(self) -> Result<W, ~str> {
+        match self.error {
+            Some(msg) => Err(msg),
+            None => Ok(self.writer.unwrap())
+        }
+    }
+
+    fn write(&mut self, event: Event) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.writer.write_event(event) {
+            Ok(()) => (),
+            Err(msg) => self.error = Some(msg)
+        }
+    }
+}
+
+impl<W: Writer> ContentHandler for WriteHandler<W> {
+    fn end_document(&mut self) {
+        if self.error.is_none() {
+            match self.writer.finish() {
+                Ok(()) => (),
+                Err(msg) => self.error = Some(msg)
+            }
+        }
+    }
+
+    fn xml_declaration(&mut self, decl: &XmlDecl) {
+        self.write(XmlDecl(decl.clone()));
+    }
+
+    fn start_prefix_mapping(&mut self, prefix: &str, ns: &str) {
+        self.declared.push(declaration_attribute(prefix, ns));
+    }
+
+    fn start_element(&mut self, name: &QName, attributes: &[Attribute]) {
+        let declared = ::std::util::replace(&mut self.declared, ~[]);
+        self.write(start_tag(name, declared, attributes));
+    }
+
+    fn end_element(&mut self, name: &QName) {
+        self.write(end_tag(name));
+    }
+
+    fn characters(&mut self, text: &str) {
+        self.write(Characters(text.to_owned()));
+    }
+
+    fn cdata(&mut self, text: &str) {
+        self.write(CDATA(text.to_owned()));
+    }
+
+    fn comment(&mut self, text: &str) {
+        self.write(Comment(text.to_owned()));
+    }
+
+    fn processing_instruction(&mut self, pi: &PI) {
+        self.write(PI(pi.clone()));
+    }
+
+    fn error(&mut self, err: &Error) {
+        if self.error.is_none() {
+            self.error = Some(format!("{}:{}: {}", err.line, err.col, err.msg));
+        }
+    }
+}
@@ -0,0 +1,683 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{XML, Element, CharacterNode, CDATANode, Span, XmlText};
+use super::base::is_space;
+use super::namespace::Scope;
+use std::fmt;
+
+/// The namespace of XML Schema
+pub static XSD_NS: &'static str = "http://www.w3.org/2001/XMLSchema";
+
+// A name in a namespace, as used for schema components
+#[deriving(Clone,Eq)]
+struct QName {
+    ns: Option<~str>,
+    name: ~str
+}
+
+impl QName {
+    fn matches(&self, elem: &Element) -> bool {
+        self.name.as_slice() == elem.name.as_slice() && self.ns == elem.ns
+    }
+
+    fn is_builtin(&self) -> bool {
+        self.ns.as_ref().map_default(false, |ns| ns.as_slice() == XSD_NS)
+    }
+}
+
+// The type of an element declaration
+#[deriving(Clone)]
+enum ElementType {
+    // A named type, which may be built in
+    NamedType(QName),
+    // An anonymous type declared inside the element
+    LocalType(TypeDef),
+    // A reference to a global element declaration
+    ElementRef(QName)
+}
+
+#[deriving(Clone)]
+struct ElementDecl {
+    name: QName,
+    typ: ElementType,
+    min_occurs: uint,
+    // `None` for "unbounded"
+    max_occurs: Option<uint>
+}
+
+#[deriving(Clone)]
+enum TypeDef {
+    SimpleDef(SimpleType),
+    ComplexDef(ComplexType)
+}
+
+#[deriving(Clone)]
+struct SimpleType {
+    base: QName,
+    // The allowed values, all values being allowed if empty
+    enumeration: ~[~str]
+}
+
+#[deriving(Clone)]
+struct ComplexType {
+    // The type extended through complexContent, if any
+    base: Option<QName>,
+    sequence: ~[ElementDecl],
+    mixed: bool
+}
+
+/// A schema read from XSD, for validating `Element`s.
+///
+/// Only a subset of XML Schema is supported: global and local element declarations
+/// and references, complex types with sequences and complexContent extensions,
+/// minOccurs and maxOccurs, and simple types restricting a built-in type, optionally
+/// with enumerations. Attributes are not validated. The built-in types `boolean`,
+/// `int`, `long`, `integer`, `double`, `date` and `dateTime` are checked, all other
+/// built-in types accept any text. Elements declared without a type are of type
+/// `anyType`, accepting any content.
+pub struct Schema {
+    priv elements: ~[ElementDecl],
+    priv types: ~[(QName, TypeDef)]
+}
+
+#[deriving(Clone,Eq)]
+/// A violation of a `Schema` found in a document
+pub struct ValidationError {
+    /// The path to the offending element, e.g. "/Profile/objectPermissions[2]"
+    path: ~str,
+    /// A message describing the violation
+    msg: ~str,
+    /// Where the element was read from, if recorded by the `ElementBuilder`
+    span: Option<Span>
+}
+
+impl fmt::Default for ValidationError {
+    fn fmt(value: &ValidationError, f: &mut fmt::Formatter) {
+        match value.span {
+            Some(ref span) => write!(f.buf, "{}:{}: ", span.start.line, span.start.col),
+            None => ()
+        }
+        write!(f.buf, "{}: {}", value.path, value.msg)
+    }
+}
+
+// Reads schema components, resolving prefixed names with the declarations in scope
+struct SchemaParser {
+    scope: Scope,
+    target: Option<~str>,
+    // Whether local elements are in the target namespace
+    qualified: bool
+}
+
+fn is_xsd(elem: &Element, name: &str) -> bool {
+    elem.name.as_slice() == name && elem.ns.as_ref().map_default(false, |ns| ns.as_slice() == XSD_NS)
+}
+
+// Returns the child elements in the XML Schema namespace, skipping annotations
+fn xsd_children<'a>(elem: &'a Element) -> ~[&'a Element] {
+    let mut result = ~[];
+    for child in elem.children.iter() {
+        match *child {
+            Element(ref e) if e.ns.as_ref().map_default(false, |ns| ns.as_slice() == XSD_NS)
+                              && e.name.as_slice() != "annotation" => result.push(e),
+            _ => ()
+        }
+    }
+    result
+}
+
+fn parse_occurs(elem: &Element, name: &str) -> Result<Option<uint>, ~str> {
+    match elem.attribute_with_name(name) {
+        None => Ok(Some(1)),
+        Some(attr) if attr.value.as_slice() == "unbounded" => Ok(None),
+        Some(attr) => match from_str(attr.value.trim()) {
+            Some(n) => Ok(Some(n)),
+            None => Err(format!("Invalid {} '{}'", name, attr.value))
+        }
+    }
+}
+
+impl SchemaParser {
+    // Resolves a prefixed name like "xsd:boolean"
+    fn resolve(&self, qname: &str) -> Result<QName, ~str> {
+        let (prefix, name) = match qname.find(':') {
+            Some(i) => (qname.slice_to(i), qname.slice_from(i + 1)),
+            None => ("", qname)
+        };
+        match self.scope.namespace_for(prefix) {
+            Some("") => Ok(QName { ns: None, name: name.to_owned() }),
+            Some(ns) => Ok(QName { ns: Some(ns.to_owned()), name: name.to_owned() }),
+            None => Err(format!("Unbound prefix '{}'", prefix))
+        }
+    }
+
+    fn schema(&mut self, elem: &Element, schema: &mut Schema) -> Result<(), ~str> {
+        self.target = elem.attribute_with_name("targetNamespace").map(|a| a.value.clone());
+        self.qualified = elem.attribute_with_name("elementFormDefault")
+                             .map_default(false, |a| a.value.as_slice() == "qualified");
+
+        for child in xsd_children(elem).move_iter() {
+            self.scope.enter(child);
+            let name = child.attribute_with_name("name").map(|a| {
+                QName { ns: self.target.clone(), name: a.value.clone() }
+            });
+            match (child.name.as_slice(), name) {
+                ("element", _) => {
+                    let decl = check!(self.element(child, true));
+                    schema.elements.push(decl);
+                }
+                ("complexType", Some(name)) => {
+                    let def = check!(self.complex_type(child));
+                    schema.types.push((name, ComplexDef(def)));
+                }
+                ("simpleType", Some(name)) => {
+                    let def = check!(self.simple_type(child));
+                    schema.types.push((name, SimpleDef(def)));
+                }
+                ("complexType", None) | ("simpleType", None) => {
+                    return Err(~"Global types must be named");
+                }
+                _ => ()
+            }
+            self.scope.pop();
+        }
+        Ok(())
+    }
+
+    // Reads an element declaration, whose namespace declarations are in scope
+    fn element(&mut self, elem: &Element, global: bool) -> Result<ElementDecl, ~str> {
+        let min_occurs = match check!(parse_occurs(elem, "minOccurs")) {
+            Some(n) => n,
+            None => return Err(~"minOccurs can not be unbounded")
+        };
+        let max_occurs = check!(parse_occurs(elem, "maxOccurs"));
+
+        match elem.attribute_with_name("ref") {
+            Some(attr) => {
+                let target = check!(self.resolve(attr.value));
+                return Ok(ElementDecl {
+                    name: target.clone(),
+                    typ: ElementRef(target),
+                    min_occurs: min_occurs,
+                    max_occurs: max_occurs
+                });
+            }
+            None => ()
+        }
+
+        let name = match elem.attribute_with_name("name") {
+            Some(attr) => attr.value.clone(),
+            None => return Err(~"Element declarations need a name or ref")
+        };
+        let qualified = match elem.attribute_with_name("form") {
+            Some(attr) => attr.value.as_slice() == "qualified",
+            None => self.qualified
+        };
+        let ns = if global || qualified { self.target.clone() } else { None };
+
+        let typ = match elem.attribute_with_name("type") {
+            Some(attr) => NamedType(check!(self.resolve(attr.value))),
+            None => {
+                let mut typ = NamedType(QName { ns: Some(XSD_NS.to_owned()), name: ~"anyType" });
+                for child in xsd_children(elem).move_iter() {
+                    self.scope.enter(child);
+                    if is_xsd(child, "complexType") {
+                        typ = LocalType(ComplexDef(check!(self.complex_type(child))));
+                    } else if is_xsd(child, "simpleType") {
+                        typ = LocalType(SimpleDef(check!(self.simple_type(child))));
+                    }
+                    self.scope.pop();
+                }
+                typ
+            }
+        };
+
+        Ok(ElementDecl {
+            name: QName { ns: ns, name: name },
+            typ: typ,
+            min_occurs: min_occurs,
+            max_occurs: max_occurs
+        })
+    }
+
+    // Reads the element declarations of a sequence, flattening nested sequences
+    fn sequence(&mut self, elem: &Element, decls: &mut ~[ElementDecl]) -> Result<(), ~str> {
+        for child in xsd_children(elem).move_iter() {
+            self.scope.enter(child);
+            match child.name.as_slice() {
+                "element" => decls.push(check!(self.element(child, false))),
+                "sequence" => check!(self.sequence(child, decls)),
+                other => return Err(format!("Unsupported schema construct '{}'", other))
+            }
+            self.scope.pop();
+        }
+        Ok(())
+    }
+
+    fn complex_type(&mut self, elem: &Element) -> Result<ComplexType, ~str> {
+        let mut def = ComplexType {
+            base: None,
+            sequence: ~[],
+            mixed: elem.attribute_with_name("mixed").map_default(false, |a| a.value.as_slice() == "true")
+        };
+
+        for child in xsd_children(elem).move_iter() {
+            self.scope.enter(child);
+            match child.name.as_slice() {
+                "sequence" => check!(self.sequence(child, &mut def.sequence)),
+                "complexContent" => {
+                    for ext in xsd_children(child).move_iter() {
+                        self.scope.enter(ext);
+                        if !is_xsd(ext, "extension") {
+                            return Err(format!("Unsupported schema construct '{}'", ext.name));
+                        }
+                        match ext.attribute_with_name("base") {
+                            Some(attr) => def.base = Some(check!(self.resolve(attr.value))),
+                            None => return Err(~"Extensions need a base type")
+                        }
+                        for seq in xsd_children(ext).move_iter() {
+                            self.scope.enter(seq);
+                            if is_xsd(seq, "sequence") {
+                                check!(self.sequence(seq, &mut def.sequence));
+                            }
+                            self.scope.pop();
+                        }
+                        self.scope.pop();
+                    }
+                }
+                "attribute" | "attributeGroup" | "anyAttribute" => (),
+                other => return Err(format!("Unsupported schema construct '{}'", other))
+            }
+            self.scope.pop();
+        }
+        Ok(def)
+    }
+
+    fn simple_type(&mut self, elem: &Element) -> Result<SimpleType, ~str> {
+        let restriction = match xsd_children(elem).move_iter().next() {
+            Some(child) if is_xsd(child, "restriction") => child,
+            Some(child) => return Err(format!("Unsupported schema construct '{}'", child.name)),
+            None => return Err(~"Simple types need a restriction")
+        };
+        self.scope.enter(restriction);
+        let base = match restriction.attribute_with_name("base") {
+            Some(attr) => check!(self.resolve(attr.value)),
+            None => return Err(~"Restrictions need a base type")
+        };
+        let mut enumeration = ~[];
+        for facet in xsd_children(restriction).move_iter() {
+            if is_xsd(facet, "enumeration") {
+                match facet.attribute_with_name("value") {
+                    Some(attr) => enumeration.push(attr.value.clone()),
+                    None => return Err(~"Enumerations need a value")
+                }
+            }
+        }
+        self.scope.pop();
+        Ok(SimpleType { base: base, enumeration: enumeration })
+    }
+}
+
+// Calls `parser.schema` for every xs:schema element in the subtree of `elem`
+fn find_schemas(elem: &Element, parser: &mut SchemaParser, schema: &mut Schema) -> Result<(), ~str> {
+    parser.scope.enter(elem);
+    if is_xsd(elem, "schema") {
+        check!(parser.schema(elem, schema));
+    } else {
+        for child in elem.children.iter() {
+            match *child {
+                Element(ref child) => check!(find_schemas(child, parser, schema)),
+                _ => ()
+            }
+        }
+    }
+    parser.scope.pop();
+    Ok(())
+}
+
+// Checks the lexical form of a date, "YYYY-MM-DD", with an optional leading '-'
+fn is_date(text: &str) -> bool {
+    let text = if text.starts_with("-") { text.slice_from(1) } else { text };
+    let parts: ~[&str] = text.split('-').collect();
+    parts.len() == 3 && parts[0].len() >= 4 && parts[1].len() == 2 && parts[2].len() == 2 &&
+        parts.iter().all(|p| p.chars().all(|c| c.is_digit())) &&
+        in_range(parts[1], 1, 12) && in_range(parts[2], 1, 31)
+}
+
+// Checks the lexical form of a time, "hh:mm:ss" with optional fractional seconds
+fn is_time(text: &str) -> bool {
+    let parts: ~[&str] = text.split(':').collect();
+    if parts.len() != 3 {
+        return false;
+    }
+    let (seconds, fraction) = match parts[2].find('.') {
+        Some(i) => (parts[2].slice_to(i), Some(parts[2].slice_from(i + 1))),
+        None => (parts[2], None)
+    };
+    parts[0].len() == 2 && parts[1].len() == 2 && seconds.len() == 2 &&
+        in_range(parts[0], 0, 24) && in_range(parts[1], 0, 59) && in_range(seconds, 0, 59) &&
+        fraction.map_default(true, |f| f.len() > 0 && f.chars().all(|c| c.is_digit()))
+}
+
+// Checks a time zone, "Z" or "+hh:mm" / "-hh:mm"
+fn is_timezone(text: &str) -> bool {
+    if text == "Z" {
+        return true;
+    }
+    text.len() == 6 && (text.starts_with("+") || text.starts_with("-")) &&
+        text.char_at(3) == ':' && in_range(text.slice(1, 3), 0, 14) && in_range(text.slice(4, 6), 0, 59)
+}
+
+fn in_range(digits: &str, min: uint, max: uint) -> bool {
+    match from_str::<uint>(digits) {
+        Some(n) => digits.chars().all(|c| c.is_digit()) && n >= min && n <= max,
+        None => false
+    }
+}
+
+// Splits a trailing time zone off a date or dateTime
+fn split_timezone<'a>(text: &'a str) -> (&'a str, &'a str) {
+    if text.ends_with("Z") {
+        return (text.slice_to(text.len() - 1), "Z");
+    }
+    if text.len() > 6 {
+        let i = text.len() - 6;
+        if (text.char_at(i) == '+' || text.char_at(i) == '-') && text.char_at(i + 3) == ':' {
+            return (text.slice_to(i), text.slice_from(i));
+        }
+    }
+    (text, "")
+}
+
+fn is_date_time(text: &str) -> bool {
+    let (text, zone) = split_timezone(text);
+    match text.find('T') {
+        Some(i) => is_date(text.slice_to(i)) && is_time(text.slice_from(i + 1)) &&
+                   (zone.is_empty() || is_timezone(zone)),
+        None => false
+    }
+}
+
+// Checks `text` against the built-in type `name`
+fn check_builtin(name: &str, text: &str) -> bool {
+    let text = text.trim();
+    match name {
+        "boolean" => {
+            let value: Result<bool, ~str> = XmlText::from_xml_text(text);
+            value.is_ok()
+        }
+        "int" => {
+            let value: Result<i32, ~str> = XmlText::from_xml_text(text);
+            value.is_ok()
+        }
+        "long" => {
+            let value: Result<i64, ~str> = XmlText::from_xml_text(text);
+            value.is_ok()
+        }
+        "integer" => {
+            let digits = if text.starts_with("-") || text.starts_with("+") { text.slice_from(1) } else { text };
+            digits.len() > 0 && digits.chars().all(|c| c.is_digit())
+        }
+        "double" => {
+            let value: Result<f64, ~str> = XmlText::from_xml_text(text);
+            value.is_ok() || text == "INF" || text == "-INF" || text == "NaN"
+        }
+        "date" => {
+            let (date, zone) = split_timezone(text);
+            is_date(date) && (zone.is_empty() || is_timezone(zone))
+        }
+        "dateTime" => is_date_time(text),
+        _ => true
+    }
+}
+
+// Validates documents, collecting errors
+struct Validator<'a> {
+    schema: &'a Schema,
+    errors: ~[ValidationError]
+}
+
+// A type found for an element
+enum Resolved<'a> {
+    // `anyType`, allowing any attributes, children and character data
+    AnyType,
+    Builtin(~str),
+    Simple(&'a SimpleType),
+    Complex(&'a ComplexType)
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, elem: &Element, path: &str, msg: ~str) {
+        self.errors.push(ValidationError { path: path.to_owned(), msg: msg, span: elem.span.as_ref().map(|s| (**s).clone()) });
+    }
+
+    fn lookup_type(&self, name: &QName) -> Result<Resolved<'a>, ~str> {
+        if name.is_builtin() {
+            if name.name.as_slice() == "anyType" {
+                return Ok(AnyType);
+            }
+            return Ok(Builtin(name.name.clone()));
+        }
+        match self.schema.types.iter().find(|&&(ref n, _)| n == name) {
+            Some(&(_, SimpleDef(ref def))) => Ok(Simple(def)),
+            Some(&(_, ComplexDef(ref def))) => Ok(Complex(def)),
+            None => Err(format!("Unknown type '{}'", name.name))
+        }
+    }
+
+    // Returns the global declaration `name`
+    fn lookup_element(&self, name: &QName) -> Result<&'a ElementDecl, ~str> {
+        match self.schema.elements.iter().find(|d| d.name == *name) {
+            Some(decl) => Ok(decl),
+            None => Err(format!("Unknown element '{}'", name.name))
+        }
+    }
+
+    fn resolve(&self, decl: &'a ElementDecl) -> Result<Resolved<'a>, ~str> {
+        match decl.typ {
+            NamedType(ref name) => self.lookup_type(name),
+            LocalType(SimpleDef(ref def)) => Ok(Simple(def)),
+            LocalType(ComplexDef(ref def)) => Ok(Complex(def)),
+            ElementRef(ref name) => {
+                let target = check!(self.lookup_element(name));
+                match target.typ {
+                    ElementRef(_) => Err(~"Element references must refer to declarations"),
+                    _ => self.resolve(target)
+                }
+            }
+        }
+    }
+
+    // Returns the sequence of `def`, preceded by those of the types it extends
+    fn sequence(&self, def: &'a ComplexType) -> Result<~[&'a ElementDecl], ~str> {
+        let mut chain = ~[def];
+        let mut current = def;
+        loop {
+            match current.base {
+                Some(ref base) if !base.is_builtin() => {
+                    match check!(self.lookup_type(base)) {
+                        Complex(parent) => {
+                            if chain.len() > self.schema.types.len() {
+                                return Err(~"Circular type definition");
+                            }
+                            chain.push(parent);
+                            current = parent;
+                        }
+                        _ => return Err(format!("Type '{}' is not a complex type", base.name))
+                    }
+                }
+                _ => break
+            }
+        }
+        let mut result = ~[];
+        for def in chain.rev_iter() {
+            for decl in def.sequence.iter() {
+                result.push(decl);
+            }
+        }
+        Ok(result)
+    }
+
+    fn element(&mut self, elem: &Element, typ: Resolved<'a>, path: &str) {
+        match typ {
+            AnyType => (),
+            Builtin(name) => self.simple_content(elem, path, |text| {
+                if check_builtin(name, text) { None } else { Some(format!("Invalid {} '{}'", name, text.trim())) }
+            }),
+            Simple(def) => {
+                let schema = self.schema;
+                self.simple_content(elem, path, |text| check_simple(schema, def, text))
+            }
+            Complex(def) => self.complex_content(elem, def, path)
+        }
+    }
+
+    // Checks an element with only character data against `check`
+    fn simple_content(&mut self, elem: &Element, path: &str, check: |&str| -> Option<~str>) {
+        if elem.children.iter().any(|c| match *c { Element(_) => true, _ => false }) {
+            self.error(elem, path, format!("Element '{}' must not contain elements", elem.name));
+            return;
+        }
+        match check(elem.content_str()) {
+            Some(msg) => self.error(elem, path, msg),
+            None => ()
+        }
+    }
+
+    fn complex_content(&mut self, elem: &Element, def: &'a ComplexType, path: &str) {
+        if !def.mixed && elem.children.iter().any(|c| has_text(c)) {
+            self.error(elem, path, format!("Element '{}' must not contain character data", elem.name));
+        }
+
+        let sequence = match self.sequence(def) {
+            Ok(sequence) => sequence,
+            Err(msg) => {
+                self.error(elem, path, msg);
+                return;
+            }
+        };
+        let children: ~[&Element] = elem.children.iter().filter_map(|c| match *c {
+            Element(ref e) => Some(e),
+            _ => None
+        }).collect();
+
+        let mut pos = 0;
+        for decl in sequence.move_iter() {
+            let mut count = 0;
+            while pos < children.len() && decl.name.matches(children[pos]) &&
+                  decl.max_occurs.map_default(true, |max| count < max) {
+                let child_path = child_path(path, children, pos);
+                match self.resolve(decl) {
+                    Ok(typ) => self.element(children[pos], typ, child_path),
+                    Err(msg) => self.error(children[pos], child_path, msg)
+                }
+                pos += 1;
+                count += 1;
+            }
+            if count < decl.min_occurs {
+                let msg = if decl.min_occurs == 1 {
+                    format!("Missing element '{}'", decl.name.name)
+                } else {
+                    format!("Expected at least {} '{}' elements, found {}", decl.min_occurs, decl.name.name, count)
+                };
+                self.error(elem, path, msg);
+            }
+        }
+        for i in range(pos, children.len()) {
+            let child_path = child_path(path, children, i);
+            self.error(children[i], child_path, format!("Unexpected element '{}'", children[i].name));
+        }
+    }
+}
+
+fn has_text(node: &XML) -> bool {
+    match *node {
+        CharacterNode(ref data) => !data.chars().all(is_space),
+        CDATANode(_) => true,
+        _ => false
+    }
+}
+
+// Checks `text` against a simple type and the types it restricts
+fn check_simple(schema: &Schema, def: &SimpleType, text: &str) -> Option<~str> {
+    if !def.enumeration.is_empty() && !def.enumeration.iter().any(|v| v.as_slice() == text.trim()) {
+        return Some(format!("Value '{}' is not allowed", text.trim()));
+    }
+    let mut base = &def.base;
+    for _ in range(0, schema.types.len() + 1) {
+        if base.is_builtin() {
+            return if check_builtin(base.name, text) {
+                None
+            } else {
+                Some(format!("Invalid {} '{}'", base.name, text.trim()))
+            };
+        }
+        match schema.types.iter().find(|&&(ref n, _)| n == base) {
+            Some(&(_, SimpleDef(ref parent))) => {
+                if !parent.enumeration.is_empty() && !parent.enumeration.iter().any(|v| v.as_slice() == text.trim()) {
+                    return Some(format!("Value '{}' is not allowed", text.trim()));
+                }
+                base = &parent.base;
+            }
+            _ => return Some(format!("Unknown simple type '{}'", base.name))
+        }
+    }
+    Some(~"Circular type definition")
+}
+
+// Returns the path of `children[i]`, numbering it if siblings share its name
+fn child_path(path: &str, children: &[&Element], i: uint) -> ~str {
+    let name = children[i].name.as_slice();
+    let same = children.iter().count(|c| c.name.as_slice() == name);
+    if same > 1 {
+        let n = children.slice_to(i).iter().count(|c| c.name.as_slice() == name) + 1;
+        format!("{}/{}[{}]", path, name, n)
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+impl Schema {
+    /// Reads the schema from `root`, which is either an `xs:schema` element or a
+    /// document containing them, like a WSDL file. All schemas found are combined.
+    pub fn from_element(root: &Element) -> Result<Schema, ~str> {
+        let mut schema = Schema { elements: ~[], types: ~[] };
+        let mut parser = SchemaParser { scope: Scope::new(), target: None, qualified: false };
+        check!(find_schemas(root, &mut parser, &mut schema));
+        Ok(schema)
+    }
+
+    /// Validates `root` against the global element declaration with its name.
+    /// Returns all errors found, in document order.
+    pub fn validate(&self, root: &Element) -> ~[ValidationError] {
+        let mut v = Validator { schema: self, errors: ~[] };
+        let path = format!("/{}", root.name);
+        let decl = self.elements.iter().find(|d| d.name.matches(root));
+        match decl {
+            Some(decl) => match v.resolve(decl) {
+                Ok(typ) => v.element(root, typ, path),
+                Err(msg) => v.error(root, path, msg)
+            },
+            None => v.error(root, path, format!("No declaration for element '{}'", root.name))
+        }
+        v.errors
+    }
+
+    /// Validates `root` against the type `name` in namespace `ns`, as used for
+    /// documents whose root element is not declared globally.
+    /// Returns all errors found, in document order.
+    pub fn validate_with_type(&self, root: &Element, name: &str, ns: Option<~str>) -> ~[ValidationError] {
+        let mut v = Validator { schema: self, errors: ~[] };
+        let path = format!("/{}", root.name);
+        let name = QName { ns: ns, name: name.to_owned() };
+        match v.lookup_type(&name) {
+            Ok(typ) => v.element(root, typ, path),
+            Err(msg) => v.error(root, path, msg)
+        }
+        v.errors
+    }
+}
@@ -0,0 +1,417 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{XML, Element, CharacterNode, CDATANode, CommentNode, PINode, PI, XmlDecl, Trivia};
+use super::base::{is_space, EscapeOptions, escape_text, escape_attribute, check_unescaped};
+use super::namespace::{Scope, Names};
+use std::cmp::Equal;
+use std::io::Decorator;
+use std::io::Writer;
+use std::io::mem::MemWriter;
+use std::str;
+use std::util;
+
+#[deriving(Clone,Eq)]
+/// The order attributes are written in
+pub enum AttributeOrder {
+    /// Write attributes in the order they are stored in
+    DocumentOrder,
+    /// Write namespace declarations first, then all other attributes sorted by
+    /// namespace and local name, those without a namespace coming first
+    SortedOrder
+}
+
+#[deriving(Clone)]
+/// Options controlling how an `Element` is serialized
+pub struct SerializeOptions {
+    /// The XML declaration to write before the element, if any
+    declaration: Option<XmlDecl>,
+    /// The string each nesting level is indented with. `None` writes no line breaks.
+    indent: Option<~str>,
+    /// The line break used when indenting, e.g. "\n" or "\r\n"
+    newline: ~str,
+    /// The character attribute values are quoted with, `'` or `"`
+    quote: char,
+    /// Whether empty elements are written as `<a/>` rather than `<a></a>`
+    self_closing: bool,
+    /// The order attributes are written in
+    attribute_order: AttributeOrder,
+    /// Whether a line break is written after the element
+    final_newline: bool,
+    /// How character data and attribute values are escaped
+    escape: EscapeOptions
+}
+
+impl SerializeOptions {
+    /// Returns the options used by `to_str`: everything on one line, single quotes,
+    /// no XML declaration and only necessary escaping. Unlike `to_str`, they do not
+    /// replace invalid characters.
+    pub fn compact() -> SerializeOptions {
+        SerializeOptions {
+            declaration: None,
+            indent: None,
+            newline: ~"\n",
+            quote: '\'',
+            self_closing: true,
+            attribute_order: DocumentOrder,
+            final_newline: false,
+            escape: EscapeOptions::minimal()
+        }
+    }
+
+    /// Returns options matching the files produced by Salesforce: a UTF-8 XML
+    /// declaration, four space indentation, double quotes, quotes escaped everywhere
+    /// and a final line break.
+    pub fn pretty() -> SerializeOptions {
+        SerializeOptions {
+            declaration: Some(XmlDecl { version: ~"1.0", encoding: Some(~"UTF-8"), standalone: None }),
+            indent: Some(~"    "),
+            newline: ~"\n",
+            quote: '"',
+            self_closing: true,
+            attribute_order: DocumentOrder,
+            final_newline: true,
+            escape: EscapeOptions::salesforce()
+        }
+    }
+}
+
+impl Element {
+    /// Returns a string representation of the element, formatted according to `opts`.
+    /// Returns an error if the element contains characters which can not be written.
+    pub fn serialize(&self, opts: &SerializeOptions) -> Result<~str, ~str> {
+        let mut w = MemWriter::new();
+        check!(write_element(&mut w as &mut Writer, self, opts));
+        Ok(str::from_utf8_owned(w.inner()))
+    }
+
+    /// Writes the element to `w`, formatted according to `opts`.
+    /// Returns an error if the element contains characters which can not be written.
+    pub fn write_to(&self, w: &mut Writer, opts: &SerializeOptions) -> Result<(), ~str> {
+        write_element(w, self, opts)
+    }
+
+    /// Returns a string representation of the element, reproducing the text it was
+    /// parsed from where it is unchanged. See `write_lossless`.
+    pub fn serialize_lossless(&self, opts: &SerializeOptions) -> Result<~str, ~str> {
+        let mut w = MemWriter::new();
+        check!(write_lossless(&mut w as &mut Writer, self, opts));
+        Ok(str::from_utf8_owned(w.inner()))
+    }
+
+    /// Writes the element to `w`, reproducing the text it was parsed from where it
+    /// is unchanged. See `write_lossless`.
+    pub fn write_lossless_to(&self, w: &mut Writer, opts: &SerializeOptions) -> Result<(), ~str> {
+        write_lossless(w, self, opts)
+    }
+}
+
+/// Writes `elem` to `w`, formatted according to `opts`.
+/// Returns an error for characters which are not allowed in XML, or which can not
+/// be represented in the encoding where they can not be escaped.
+pub fn write_element(w: &mut Writer, elem: &Element, opts: &SerializeOptions) -> Result<(), ~str> {
+    match opts.declaration {
+        Some(ref decl) => {
+            let q = opts.quote;
+            write!(w, "<?xml version={}{}{}", q, decl.version, q);
+            match decl.encoding {
+                Some(ref enc) => write!(w, " encoding={}{}{}", q, *enc, q),
+                None => ()
+            }
+            match decl.standalone {
+                Some(standalone) => {
+                    write!(w, " standalone={}{}{}", q, if standalone { "yes" } else { "no" }, q)
+                }
+                None => ()
+            }
+            write!(w, "?>");
+            if opts.indent.is_some() {
+                write!(w, "{}", opts.newline);
+            }
+        }
+        None => ()
+    }
+
+    check!(write_elem(w, elem, &mut Scope::new(), 0, opts));
+
+    if opts.final_newline {
+        write!(w, "{}", opts.newline);
+    }
+    Ok(())
+}
+
+/// Writes `elem` to `w`, reproducing the text recorded by `parse_lossless` for all
+/// parts of it which are unchanged, including the text before and after the root.
+/// Changed and added nodes are written according to `opts`. Where the children of
+/// an element were on lines of their own, added nodes are put on a line of their own
+/// too, indented like their siblings. Their content is written without indentation.
+/// An element without recorded text is written like `write_element` does.
+pub fn write_lossless(w: &mut Writer, elem: &Element, opts: &SerializeOptions) -> Result<(), ~str> {
+    match elem.trivia {
+        Some(ref trivia) => {
+            // New nodes are indented with the whitespace recorded around their siblings
+            let mut opts = opts.clone();
+            opts.indent = None;
+
+            write!(w, "{}", trivia.before);
+            check!(write_lossless_elem(w, elem, &mut Scope::new(), &opts));
+            write!(w, "{}", trivia.after);
+            Ok(())
+        }
+        None => write_element(w, elem, opts)
+    }
+}
+
+// Starts a new line, indented for `depth`
+fn write_indent(w: &mut Writer, depth: uint, opts: &SerializeOptions) {
+    match opts.indent {
+        Some(ref indent) => {
+            write!(w, "{}", opts.newline);
+            for _ in range(0, depth) {
+                write!(w, "{}", *indent);
+            }
+        }
+        None => ()
+    }
+}
+
+// Returns true if `elem` has character data children other than whitespace.
+// The children of such elements are written as they are, without indentation.
+fn has_mixed_content(elem: &Element) -> bool {
+    elem.children.iter().any(|child| {
+        match *child {
+            CharacterNode(ref data) => !data.chars().all(is_space),
+            CDATANode(_) => true,
+            _ => false
+        }
+    })
+}
+
+// Writes a start tag up to the closing '>', returning the qualified name
+fn write_start_tag(w: &mut Writer, names: Names, opts: &SerializeOptions) -> Result<~str, ~str> {
+    let qname = names.qname;
+    write!(w, "<{}", qname);
+
+    let q = opts.quote;
+    for &(ref prefix, ref ns) in names.declarations.iter() {
+        let ns = check!(escape_attribute(*ns, q, &opts.escape));
+        if prefix.is_empty() {
+            write!(w, " xmlns={}{}{}", q, ns, q);
+        } else {
+            write!(w, " xmlns:{}={}{}{}", *prefix, q, ns, q);
+        }
+    }
+
+    let mut attributes = names.attributes;
+    match opts.attribute_order {
+        DocumentOrder => (),
+        SortedOrder => attributes.sort_by(|&(_, a), &(_, b)| {
+            match a.ns.cmp(&b.ns) {
+                Equal => a.name.cmp(&b.name),
+                order => order
+            }
+        })
+    }
+
+    for &(ref name, attr) in attributes.iter() {
+        let value = check!(escape_attribute(attr.value, q, &opts.escape));
+        write!(w, " {}={}{}{}", *name, q, value, q);
+    }
+    Ok(qname)
+}
+
+// Writes a node other than an element
+pub fn write_node(w: &mut Writer, node: &XML, opts: &SerializeOptions) -> Result<(), ~str> {
+    match *node {
+        Element(_) => (),
+        CharacterNode(ref data) => write!(w, "{}", check!(escape_text(*data, &opts.escape))),
+        CDATANode(ref data) => write!(w, "<![CDATA[{}]]>", check!(check_unescaped(*data, &opts.escape))),
+        CommentNode(ref data) => write!(w, "<!--{}-->", check!(check_unescaped(*data, &opts.escape))),
+        PINode(ref pi) => {
+            let pi = PI {
+                target: check!(check_unescaped(pi.target, &opts.escape)),
+                space: pi.space.clone(),
+                data: check!(check_unescaped(pi.data, &opts.escape))
+            };
+            write!(w, "{}", pi);
+        }
+    }
+    Ok(())
+}
+
+fn write_elem(w: &mut Writer, elem: &Element, scope: &mut Scope, depth: uint,
+              opts: &SerializeOptions) -> Result<(), ~str> {
+    let names = scope.enter(elem);
+    let qname = check!(write_start_tag(w, names, opts));
+
+    // Whitespace between child elements is replaced by the configured indentation
+    let indented = opts.indent.is_some() && !has_mixed_content(elem);
+    let children: ~[&XML] = elem.children.iter().filter(|child| {
+        match **child {
+            CharacterNode(ref data) if indented => !data.chars().all(is_space),
+            _ => true
+        }
+    }).collect();
+
+    if children.len() == 0 {
+        if opts.self_closing {
+            write!(w, "/>");
+        } else {
+            write!(w, "></{}>", qname);
+        }
+        scope.pop();
+        return Ok(());
+    }
+
+    write!(w, ">");
+    for child in children.iter() {
+        if indented {
+            write_indent(w, depth + 1, opts);
+        }
+        match **child {
+            Element(ref child) => check!(write_elem(w, child, scope, depth + 1, opts)),
+            ref node => check!(write_node(w, node, opts))
+        }
+    }
+    if indented {
+        write_indent(w, depth, opts);
+    }
+    write!(w, "</{}>", qname);
+    scope.pop();
+    Ok(())
+}
+
+// The text recorded in `trivia` for `node`, looking at the recorded nodes from `next`
+fn recorded_source<'a>(trivia: &'a Trivia, next: uint, node: &XML) -> Option<&'a str> {
+    trivia.children.slice_from(next).iter().find(|&&(ref n, _)| n == node).map(|&(_, ref source)| {
+        source.as_slice()
+    })
+}
+
+// Returns the whitespace recorded before the first child of an element and before
+// its end tag, if its children were on lines of their own
+fn recorded_indent<'a>(trivia: &'a Trivia) -> Option<(&'a str, &'a str)> {
+    let lines: ~[&'a str] = trivia.children.iter().filter_map(|&(ref node, ref source)| {
+        match *node {
+            CharacterNode(ref data) if data.chars().all(is_space) && data.contains_char('\n') => {
+                Some(source.as_slice())
+            }
+            _ => None
+        }
+    }).collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some((lines[0], *lines.last()))
+    }
+}
+
+// Writes a child of an element recorded as `trivia`, reusing the text recorded for
+// it. `next` is the index of the first recorded node not matched yet.
+fn write_lossless_child(w: &mut Writer, child: &XML, trivia: &Trivia, next: &mut uint,
+                        scope: &mut Scope, opts: &SerializeOptions) -> Result<(), ~str> {
+    match *child {
+        Element(ref child) => write_lossless_elem(w, child, scope, opts),
+        ref node => match trivia.children.slice_from(*next).iter().position(|&(ref n, _)| n == node) {
+            Some(i) => {
+                let (_, ref source) = trivia.children[*next + i];
+                write!(w, "{}", *source);
+                *next += i + 1;
+                Ok(())
+            }
+            None => write_node(w, node, opts)
+        }
+    }
+}
+
+// Writes `elem`, reusing the text recorded for it where it is unchanged
+fn write_lossless_elem(w: &mut Writer, elem: &Element, scope: &mut Scope,
+                       opts: &SerializeOptions) -> Result<(), ~str> {
+    let trivia: &Trivia = match elem.trivia {
+        Some(ref trivia) => &**trivia,
+        None => return write_elem(w, elem, scope, 0, opts)
+    };
+
+    let names = scope.enter(elem);
+    // A start tag written as `<a/>` can only be kept while there are no children
+    let unchanged = trivia.name == elem.name && trivia.ns == elem.ns &&
+                    trivia.attributes == elem.attributes &&
+                    (trivia.end_tag.is_some() || elem.children.is_empty());
+    let qname = if unchanged {
+        write!(w, "{}", trivia.start_tag);
+        if trivia.end_tag.is_none() {
+            scope.pop();
+            return Ok(());
+        }
+        names.qname.clone()
+    } else {
+        let qname = check!(write_start_tag(w, names, opts));
+        if elem.children.is_empty() && opts.self_closing {
+            write!(w, "/>");
+            scope.pop();
+            return Ok(());
+        }
+        write!(w, ">");
+        qname
+    };
+
+    // Nodes are matched to the recorded ones in order, so inserting or removing
+    // a node does not change how the nodes after it are written
+    let mut next = 0;
+    let indent = if has_mixed_content(elem) { None } else { recorded_indent(trivia) };
+    // Whitespace not written yet, as a new node may have to go before it
+    let mut pending: Option<&XML> = None;
+    // Set after a new node, which the next node is put on a new line after
+    let mut separate = false;
+    for child in elem.children.iter() {
+        let first = match indent {
+            Some((first, _)) => first,
+            None => {
+                check!(write_lossless_child(w, child, trivia, &mut next, scope, opts));
+                continue;
+            }
+        };
+        let new = match *child {
+            CharacterNode(ref data) if data.chars().all(is_space) => {
+                match util::replace(&mut pending, Some(child)) {
+                    Some(space) => check!(write_lossless_child(w, space, trivia, &mut next, scope, opts)),
+                    None => ()
+                }
+                separate = false;
+                continue;
+            }
+            Element(ref child) => child.trivia.is_none(),
+            ref node => recorded_source(trivia, next, node).is_none()
+        };
+
+        match util::replace(&mut pending, None) {
+            // Whitespace before the end tag stays there
+            Some(space) if new && recorded_source(trivia, next, space) != Some(first) => {
+                write!(w, "{}", first);
+                pending = Some(space);
+            }
+            Some(space) => check!(write_lossless_child(w, space, trivia, &mut next, scope, opts)),
+            None if new || separate => write!(w, "{}", first),
+            None => ()
+        }
+        check!(write_lossless_child(w, child, trivia, &mut next, scope, opts));
+        separate = new;
+    }
+    match pending {
+        Some(space) => check!(write_lossless_child(w, space, trivia, &mut next, scope, opts)),
+        None => match indent {
+            Some((_, last)) if separate => write!(w, "{}", last),
+            _ => ()
+        }
+    }
+
+    match trivia.end_tag {
+        Some(ref end_tag) if unchanged => write!(w, "{}", *end_tag),
+        _ => write!(w, "</{}>", qname)
+    }
+    scope.pop();
+    Ok(())
+}
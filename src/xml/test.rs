@@ -1,11 +1,13 @@
+#[feature(phase)];
+
+#[phase(syntax, link)]
 extern mod xml;
 
 
 #[cfg(test)]
 mod base_tests {
-    use xml::{escape, unescape, unrecognized_entity};
-    use xml::{Element, Attribute, CharacterNode, CDATANode, CommentNode, PINode};
-    use std::hashmap::HashMap;
+    use xml::{escape, unescape, try_unescape, unrecognized_entity};
+    use xml::{Element, Attribute, CharacterNode, CDATANode, CommentNode, PINode, PI, Atom};
 
     #[test]
     fn test_escape() {
@@ -29,67 +31,83 @@ mod base_tests {
         })
     }
 
+    #[test]
+    fn test_try_unescape() {
+        assert_eq!(try_unescape("&lt;&#x41;"), Ok(~"<A"));
+        assert_eq!(try_unescape("&nbsp;&foo;"), Err(~"Unknown entity '&nbsp;'"));
+        assert_eq!(try_unescape("&#0;"), Err(~"Invalid reference '&#0;'"));
+        assert_eq!(try_unescape("a &b c"), Err(~"Unterminated reference '&b c'"));
+        assert_eq!(try_unescape("a&"), Err(~"Unterminated reference '&'"));
+        assert_eq!(unescape("a&"), ~"a&");
+    }
+
     #[test]
     fn test_to_str_element() {
         let elem = Element {
-            name: ~"a",
+            name: Atom::new("a"),
             ns: None,
-            default_ns: None,
-            prefixes: HashMap::new(),
+            prefix: None,
             attributes: ~[],
-            children: ~[]
+            children: ~[],
+            span: None,
+            trivia: None
         };
         assert_eq!(elem.to_str(), ~"<a/>");
 
         let elem = Element {
-            name: ~"a",
+            name: Atom::new("a"),
             ns: None,
-            default_ns: None,
-            prefixes: HashMap::new(),
+            prefix: None,
             attributes: ~[
-                Attribute { name: ~"href", ns: None, value: ~"http://rust-lang.org" }
+                Attribute { name: Atom::new("href"), ns: None, value: ~"http://rust-lang.org" }
             ],
-            children: ~[]
+            children: ~[],
+            span: None,
+            trivia: None
         };
         assert_eq!(elem.to_str(), ~"<a href='http://rust-lang.org'/>");
 
         let elem = Element {
-            name: ~"a",
+            name: Atom::new("a"),
             ns: None,
-            default_ns: None,
-            prefixes: HashMap::new(),
+            prefix: None,
             attributes: ~[],
             children: ~[
                 Element(Element {
-                    name: ~"b",
+                    name: Atom::new("b"),
                     ns: None,
-                    default_ns: None,
-                    prefixes: HashMap::new(),
+                    prefix: None,
                     attributes: ~[],
-                    children: ~[]
+                    children: ~[],
+                    span: None,
+                    trivia: None
                 })
-            ]
+            ],
+            span: None,
+            trivia: None
         };
         assert_eq!(elem.to_str(), ~"<a><b/></a>");
 
         let elem = Element {
-            name: ~"a",
+            name: Atom::new("a"),
             ns: None,
-            default_ns: None,
-            prefixes: HashMap::new(),
+            prefix: None,
             attributes: ~[
-                Attribute { name: ~"href", ns: None, value: ~"http://rust-lang.org" }
+                Attribute { name: Atom::new("href"), ns: None, value: ~"http://rust-lang.org" }
             ],
             children: ~[
                 Element(Element {
-                    name: ~"b",
+                    name: Atom::new("b"),
                     ns: None,
-                    default_ns: None,
-                    prefixes: HashMap::new(),
+                    prefix: None,
                     attributes: ~[],
-                    children: ~[]
+                    children: ~[],
+                    span: None,
+                    trivia: None
                 })
-            ]
+            ],
+            span: None,
+            trivia: None
         };
         assert_eq!(elem.to_str(), ~"<a href='http://rust-lang.org'><b/></a>");
     }
@@ -98,6 +116,16 @@ mod base_tests {
     fn test_to_str_characters() {
         let chars = CharacterNode(~"some text");
         assert_eq!(chars.to_str(), ~"some text");
+        let chars = CharacterNode(~"'a' & \"b\"\r");
+        assert_eq!(chars.to_str(), ~"'a' &amp; \"b\"&#13;");
+    }
+
+    #[test]
+    fn test_to_str_invalid() {
+        let elem = Element::new("a").text("\x01").attr("b", "\x02");
+        assert_eq!(elem.to_str(), ~"<a b='\uFFFD'>\uFFFD</a>");
+        assert_eq!(CommentNode(~"\x03").to_str(), ~"<!--\uFFFD-->");
+        assert_eq!(CharacterNode(~"<\x03").to_str(), ~"&lt;\uFFFD");
     }
 
     #[test]
@@ -114,32 +142,56 @@ mod base_tests {
 
     #[test]
     fn test_to_str_pi() {
-        let chars = PINode(~"xml version='1.0'");
-        assert_eq!(chars.to_str(), ~"<?xml version='1.0'?>");
+        let pi = PINode(PI::new("xml-stylesheet", "href='a.xsl'"));
+        assert_eq!(pi.to_str(), ~"<?xml-stylesheet href='a.xsl'?>");
+        let pi = PINode(PI { target: ~"a", space: ~"\n  ", data: ~"b " });
+        assert_eq!(pi.to_str(), ~"<?a\n  b ?>");
+        assert_eq!(PINode(PI::new("a", "")).to_str(), ~"<?a?>");
+    }
+
+    #[test]
+    fn test_pseudo_attributes() {
+        let pi = PI::new("xml-stylesheet", "type=\"text/xsl\"  href = 'a.xsl?x=1&amp;y=2'");
+        assert_eq!(pi.pseudo_attributes(), Ok(~[(~"type", ~"text/xsl"), (~"href", ~"a.xsl?x=1&y=2")]));
+        assert_eq!(pi.pseudo_attribute("href"), Some(~"a.xsl?x=1&y=2"));
+        assert_eq!(pi.pseudo_attribute("media"), None);
+
+        let pi = PI::new("a", "b='1'c='2'");
+        assert_eq!(pi.pseudo_attributes(), Err(~"Expected whitespace between pseudo-attributes"));
+        let pi = PI::new("a", "b=1");
+        assert_eq!(pi.pseudo_attributes(), Err(~"Pseudo-attribute value not enclosed in ' or \""));
+        assert_eq!(pi.pseudo_attribute("b"), None);
+
+        let pi = PI::new("xml-stylesheet", "type='text/xsl' href=\"&foo;\"");
+        assert_eq!(pi.pseudo_attributes(), Err(~"Unknown entity '&foo;'"));
+        assert_eq!(pi.pseudo_attribute("href"), None);
+        assert_eq!(pi.pseudo_attribute("type"), None);
     }
 
     #[test]
     fn test_content_str() {
         let elem = Element {
-            name: ~"a",
+            name: Atom::new("a"),
             ns: None,
-            default_ns: None,
-            prefixes: HashMap::new(),
+            prefix: None,
             attributes: ~[],
             children: ~[
-                PINode(~"processing information"),
+                PINode(PI::new("processing", "information")),
                 CDATANode(~"<hello/>"),
                 Element(Element{
-                    name: ~"b",
+                    name: Atom::new("b"),
                     ns: None,
-                    default_ns: None,
-                    prefixes: HashMap::new(),
+                    prefix: None,
                     attributes: ~[],
-                    children: ~[]
+                    children: ~[],
+                    span: None,
+                    trivia: None
                 }),
                 CharacterNode(~"World"),
                 CommentNode(~"Nothing to see")
-            ]
+            ],
+            span: None,
+            trivia: None
         };
         assert_eq!(elem.content_str(), ~"<hello/>World");
     }
@@ -170,10 +222,108 @@ mod base_bench {
     }
 }
 
+#[cfg(test)]
+mod parser_bench {
+    extern mod extra;
+    use self::extra::test::BenchHarness;
+    use xml::{Parser, Reader, ElementBuilder, Element};
+
+    // A profile like those retrieved from Salesforce, with `n` field permissions
+    fn profile(n: uint) -> ~str {
+        let mut doc = ~"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<Profile xmlns=\"http://soap.sforce.com/2006/04/metadata\">\n";
+        for i in range(0, n) {
+            doc.push_str(format!("    <fieldPermissions>
+        <editable>true</editable>
+        <field>Account.Field_{}__c</field>
+        <readable>true</readable>
+    </fieldPermissions>\n", i));
+        }
+        doc.push_str("    <description>Sales &amp; Marketing</description>\n</Profile>\n");
+        doc
+    }
+
+    #[bench]
+    fn bench_parse_str(bh: &mut BenchHarness) {
+        let input = profile(100);
+        bh.iter(|| {
+            let mut p = Parser::new();
+            p.parse_str(input, |event| { event.unwrap(); });
+        });
+        bh.bytes = input.len() as u64;
+    }
+
+    #[bench]
+    fn bench_parse_str_pieces(bh: &mut BenchHarness) {
+        let input = profile(100);
+        bh.iter(|| {
+            let mut p = Parser::new();
+            let mut start = 0;
+            while start < input.len() {
+                let end = ::std::cmp::min(start + 4096, input.len());
+                p.parse_str(input.slice(start, end), |event| { event.unwrap(); });
+                start = end;
+            }
+        });
+        bh.bytes = input.len() as u64;
+    }
+
+    // The least work the per-character state machine `Parser` was built on before
+    // reading with a `Reader` did for a document: decoding and buffering each
+    // character. Parsing should stay faster than this baseline.
+    #[bench]
+    fn bench_baseline(bh: &mut BenchHarness) {
+        let input = profile(100);
+        bh.iter(|| {
+            let mut buf = ~"";
+            for c in input.chars() {
+                if c == '<' || c == '>' {
+                    buf.clear();
+                } else {
+                    buf.push_char(c);
+                }
+            }
+        });
+        bh.bytes = input.len() as u64;
+    }
+
+    #[bench]
+    fn bench_reader(bh: &mut BenchHarness) {
+        let input = profile(100);
+        bh.iter(|| {
+            for event in Reader::new(input) {
+                event.unwrap();
+            }
+        });
+        bh.bytes = input.len() as u64;
+    }
+
+    #[bench]
+    fn bench_build_parser(bh: &mut BenchHarness) {
+        let input = profile(100);
+        bh.iter(|| {
+            let mut p = Parser::new();
+            let mut e = ElementBuilder::new();
+            p.parse_str(input, |event| { e.push_event(event.unwrap()).unwrap(); });
+        });
+        bh.bytes = input.len() as u64;
+    }
+
+    #[bench]
+    fn bench_build_reader(bh: &mut BenchHarness) {
+        let input = profile(100);
+        bh.iter(|| {
+            let elem: Option<Element> = from_str(input);
+            elem.unwrap();
+        });
+        bh.bytes = input.len() as u64;
+    }
+}
+
 #[cfg(test)]
 mod parser_tests {
     use xml::Parser;
-    use xml::{StartTag, EndTag, PI, Comment, CDATA, Characters};
+    use xml::{StartTag, EndTag, XmlDecl, PI, Comment, CDATA, Characters, Atom};
 
     #[test]
     fn test_start_tag() {
@@ -182,7 +332,7 @@ mod parser_tests {
         p.parse_str("<a>", |event| {
             i += 1;
             assert_eq!(event, Ok(StartTag(StartTag {
-                name: ~"a",
+                name: Atom::new("a"),
                 ns: None,
                 prefix:None,
                 attributes: ~[]
@@ -195,24 +345,121 @@ mod parser_tests {
     fn test_end_tag() {
         let mut p = Parser::new();
         let mut i = 0;
-        p.parse_str("</a>", |event| {
+        p.parse_str("<a></a>", |event| {
             i += 1;
-            assert_eq!(event, Ok(EndTag(EndTag { name: ~"a", ns: None, prefix: None })));
+            if i == 2 {
+                assert_eq!(event, Ok(EndTag(EndTag { name: Atom::new("a"), ns: None, prefix: None })));
+            }
         });
-        assert_eq!(i, 1);
+        assert_eq!(i, 2);
     }
 
     #[test]
     fn test_PI() {
         let mut p = Parser::new();
         let mut i = 0;
-        p.parse_str("<?xml version='1.0' encoding='utf-8'?>", |event| {
+        p.parse_str("<?xml-stylesheet href='style.xsl'?>", |event| {
             i += 1;
-            assert_eq!(event, Ok(PI(~"xml version='1.0' encoding='utf-8'")));
+            assert_eq!(event, Ok(PI(PI {
+                target: ~"xml-stylesheet",
+                space: ~" ",
+                data: ~"href='style.xsl'"
+            })));
+        });
+        assert_eq!(i, 1);
+    }
+
+    #[test]
+    fn test_xml_decl() {
+        let mut p = Parser::new();
+        let mut i = 0;
+        p.parse_str("<?xml version='1.0' encoding='utf-8' standalone=\"yes\"?>", |event| {
+            i += 1;
+            assert_eq!(event, Ok(XmlDecl(XmlDecl {
+                version: ~"1.0",
+                encoding: Some(~"utf-8"),
+                standalone: Some(true)
+            })));
         });
         assert_eq!(i, 1);
     }
 
+    #[test]
+    fn test_xml_decl_invalid() {
+        let mut p = Parser::new();
+        let mut errors = 0;
+        p.parse_str("<?xml encoding='utf-8' version='1.0'?>", |event| {
+            if event.is_err() {
+                errors += 1;
+            }
+        });
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn test_bytes_utf16() {
+        let mut p = Parser::new();
+        let mut names = ~[];
+        // "<a/>" in UTF-16LE, with byte order mark
+        let data = [0xFF, 0xFE, 0x3C, 0, 0x61, 0, 0x2F, 0, 0x3E, 0];
+        p.parse_bytes(data, |event| {
+            match event {
+                Ok(StartTag(StartTag { name, ns: _, prefix: _, attributes: _ })) => names.push(name.to_str()),
+                _ => ()
+            }
+        });
+        assert_eq!(names, ~[~"a"]);
+    }
+
+    #[test]
+    fn test_bytes_windows_1252() {
+        let mut p = Parser::new();
+        let mut text = ~"";
+        let data = bytes!("<?xml version='1.0' encoding='windows-1252'?><a>", 0x80u8, "</a>");
+        p.parse_bytes(data, |event| {
+            match event {
+                Ok(Characters(chars)) => text.push_str(chars),
+                _ => ()
+            }
+        });
+        assert_eq!(text, ~"\u20ac");
+    }
+
+    #[test]
+    fn test_bytes_declared_encoding() {
+        // The document in UTF-16LE, with byte order mark
+        fn utf16(doc: &str) -> ~[u8] {
+            let mut data = ~[0xFFu8, 0xFE];
+            for c in doc.chars() {
+                data.push(c as u8);
+                data.push(0);
+            }
+            data
+        }
+        fn errors(data: &[u8]) -> ~[~str] {
+            let mut p = Parser::new();
+            let mut errors = ~[];
+            p.parse_bytes(data, |event| {
+                match event {
+                    Err(e) => errors.push(e.msg),
+                    Ok(_) => ()
+                }
+            });
+            errors
+        }
+
+        assert_eq!(errors(utf16("<?xml version='1.0' encoding='UTF-16'?><a/>")), ~[]);
+        assert_eq!(errors(utf16("<?xml version='1.0' encoding='utf-16le'?><a/>")), ~[]);
+        assert_eq!(errors(utf16("<?xml version='1.0' encoding='UTF-16BE'?><a/>")),
+                   ~[~"Declared encoding 'UTF-16BE' does not match document"]);
+        assert_eq!(errors(utf16("<?xml version='1.0' encoding='UTF-8'?><a/>")),
+                   ~[~"Declared encoding 'UTF-8' does not match document"]);
+
+        assert_eq!(errors(bytes!("<?xml version='1.0' encoding='US-ASCII'?><a>b</a>")), ~[]);
+        assert_eq!(errors(bytes!("<?xml version='1.0' encoding='US-ASCII'?><a>", 0xE9u8, "</a>")),
+                   ~[~"Invalid US-ASCII byte 0xe9"]);
+    }
+
     #[test]
     fn test_comment() {
         let mut p = Parser::new();
@@ -227,11 +474,13 @@ mod parser_tests {
     fn test_CDATA() {
         let mut p = Parser::new();
         let mut i = 0;
-        p.parse_str("<![CDATA[<html><head><title>x</title></head><body/></html>]]>", |event| {
+        p.parse_str("<a><![CDATA[<html><head><title>x</title></head><body/></html>]]></a>", |event| {
             i += 1;
-            assert_eq!(event, Ok(CDATA(~"<html><head><title>x</title></head><body/></html>")));
+            if i == 2 {
+                assert_eq!(event, Ok(CDATA(~"<html><head><title>x</title></head><body/></html>")));
+            }
         });
-        assert_eq!(i, 1);
+        assert_eq!(i, 3);
     }
 
     #[test]
@@ -256,4 +505,1852 @@ mod parser_tests {
         });
         assert_eq!(i, 0);
     }
+
+    // Returns the messages of all errors reported for `data`, including at EOF
+    fn errors(data: &str) -> ~[~str] {
+        let mut p = Parser::new();
+        let mut errors = ~[];
+        p.parse_str(data, |event| {
+            match event {
+                Err(e) => errors.push(e.msg),
+                _ => ()
+            }
+        });
+        p.finish(|event| {
+            match event {
+                Err(e) => errors.push(e.msg),
+                _ => ()
+            }
+        });
+        errors
+    }
+
+    #[test]
+    fn test_well_formed() {
+        let errs = errors("<?xml version='1.0'?><!DOCTYPE a [<!ENTITY b '>'>]>\
+                           <a xmlns:x='urn:x' x:b='&#x41;&lt;'><x:c/>&#65;<!--c--></a>\n");
+        assert_eq!(errs, ~[]);
+    }
+
+    #[test]
+    fn test_mismatched_end_tag() {
+        assert_eq!(errors("<a><b></a>"), ~[~"Expected '</b>', found '</a>'"]);
+        assert_eq!(errors("</a>"), ~[~"Unexpected end tag '</a>'"]);
+    }
+
+    #[test]
+    fn test_duplicate_attribute() {
+        assert_eq!(errors("<a b='1' b='2'/>"), ~[~"Duplicate attribute 'b'"]);
+        assert_eq!(errors("<a xmlns:x='urn:x' xmlns:y='urn:x' x:b='1' y:b='2'/>"),
+                   ~[~"Duplicate attribute 'b'"]);
+    }
+
+    #[test]
+    fn test_content_outside_root() {
+        assert_eq!(errors("<a/><b/>"), ~[~"Element 'b' found after the root element"]);
+        assert_eq!(errors("<a/>text<b/>"), ~[~"Character data outside of root element"]);
+        assert_eq!(errors("<a/>text"), ~[~"Character data outside of root element"]);
+        assert_eq!(errors(""), ~[~"Document lacks a root element"]);
+    }
+
+    #[test]
+    fn test_invalid_markup() {
+        assert_eq!(errors("<a b='<'/>"), ~[~"'<' is not allowed in attribute values"]);
+        assert_eq!(errors("<1a/>"), ~[~"Unexpected '1' after '<'"]);
+        assert_eq!(errors("<a:b:c/>"), ~[~"Invalid name 'a:b:c'"]);
+        assert_eq!(errors("<x:a/>"), ~[~"Unbound prefix: 'x'"]);
+        assert_eq!(errors("<a b='1'c='2'/>"), ~[~"Expected whitespace between attributes"]);
+        assert_eq!(errors("<a>&b</a>"),
+                   ~[~"Unterminated reference, '&' must be escaped as '&amp;'"]);
+        assert_eq!(errors("<a>]]></a>"), ~[~"']]>' is not allowed in character data"]);
+        assert_eq!(errors("<a/><?xml version='1.0'?>"),
+                   ~[~"XML declaration only allowed at the start of the document"]);
+        assert_eq!(errors("<a><?XML b?></a>"), ~[~"Reserved processing instruction target 'XML'"]);
+        assert_eq!(errors("<a><?1 b?></a>"), ~[~"Invalid processing instruction target '1'"]);
+    }
+
+    #[test]
+    fn test_unexpected_eof() {
+        assert_eq!(errors("<a><b>"), ~[~"Unclosed element 'b'"]);
+        assert_eq!(errors("<a><b attr='1"), ~[~"Unexpected end of document inside tag"]);
+        assert_eq!(errors("<a><!-- x"), ~[~"Unexpected end of document inside comment"]);
+    }
+
+    #[test]
+    fn test_pieces() {
+        let data = "<a b='>' c=\"x\"><!-- c - d --><![CDATA[]]]]><?p q?>text&amp;<b/></a>";
+        let mut p = Parser::new();
+        let mut whole = ~[];
+        p.parse_str(data, |event| whole.push(event.map_err(|e| e.msg)));
+        p.finish(|event| whole.push(event.map_err(|e| e.msg)));
+
+        // Markup split over many pieces is read once its end is passed in
+        let mut p = Parser::new();
+        let mut pieces = ~[];
+        for i in range(0, data.len()) {
+            p.parse_str(data.slice(i, i + 1), |event| pieces.push(event.map_err(|e| e.msg)));
+        }
+        p.finish(|event| pieces.push(event.map_err(|e| e.msg)));
+        assert_eq!(pieces, whole);
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let mut p = Parser::new();
+        let mut events = ~[];
+        // "\r\n" split between two calls is still a single line break
+        p.parse_str("<a>x\r", |event| events.push(event));
+        p.parse_str("\ny\rz<![CDATA[\r\n]]><!--\r--></a>", |event| events.push(event));
+        assert_eq!(events[1], Ok(Characters(~"x\ny\nz")));
+        assert_eq!(events[2], Ok(CDATA(~"\n")));
+        assert_eq!(events[3], Ok(Comment(~"\n")));
+    }
+
+    #[test]
+    fn test_attribute_normalization() {
+        let mut p = Parser::new();
+        let mut attributes = ~[];
+        p.parse_str("<a b=' x\r\n\ty\n' c='&#9;&#10;&#13;&#32;&amp;'/>", |event| {
+            match event {
+                Ok(StartTag(tag)) => attributes = tag.attributes,
+                _ => ()
+            }
+        });
+        let values: ~[~str] = attributes.move_iter().map(|a| a.value).collect();
+        assert_eq!(values, ~[~" x  y ", ~"\t\n\r &"]);
+    }
+
+    #[test]
+    fn test_line_ending_positions() {
+        let mut p = Parser::new();
+        let mut position = None;
+        p.parse_str("<a>\r\n\r  <b></c></a>", |event| {
+            match event {
+                Err(e) => position = Some((e.line, e.col)),
+                _ => ()
+            }
+        });
+        assert_eq!(position, Some((3, 9)));
+    }
+}
+
+#[cfg(test)]
+mod span_tests {
+    use xml::{Parser, ElementBuilder, Root, Element, Position, Span, StartTag, EndTag, Characters};
+    use xml::{parse_recovering, parse_lossless};
+
+    static SOURCE: &'static str = "<a>\n  <b x='1'/>\n</a>";
+
+    #[test]
+    fn test_event_spans() {
+        let mut p = Parser::new();
+        let mut spans = ~[];
+        p.parse_str_spanned(SOURCE, |result| {
+            match result {
+                Ok((StartTag(_), span)) | Ok((EndTag(_), span)) | Ok((Characters(_), span)) => {
+                    spans.push(span)
+                }
+                _ => fail!("Unexpected parse result")
+            }
+        });
+        assert_eq!(spans.len(), 6);
+        assert_eq!(spans[0], Span {
+            start: Position { offset: 0, line: 1, col: 1 },
+            end: Position { offset: 3, line: 1, col: 4 }
+        });
+        assert_eq!(spans[1], Span {
+            start: Position { offset: 3, line: 1, col: 4 },
+            end: Position { offset: 6, line: 2, col: 3 }
+        });
+        assert_eq!(spans[5], Span {
+            start: Position { offset: 17, line: 3, col: 1 },
+            end: Position { offset: 21, line: 3, col: 5 }
+        });
+    }
+
+    #[test]
+    fn test_element_span() {
+        let mut p = Parser::new();
+        let mut e = ElementBuilder::new();
+        let mut root = None;
+        p.parse_str_spanned(SOURCE, |result| {
+            match result {
+                Ok((event, span)) => match e.push_event_at(event, span) {
+                    Ok(Some(Root(elem))) => root = Some(elem),
+                    _ => ()
+                },
+                Err(_) => fail!("Unexpected error")
+            }
+        });
+
+        let root = root.unwrap();
+        let b = root.child_with_name("b").unwrap();
+        let span = b.span.get_ref();
+        assert_eq!(span.start, Position { offset: 6, line: 2, col: 3 });
+        assert_eq!(span.end, Position { offset: 16, line: 2, col: 13 });
+        assert_eq!(span.snippet(SOURCE), ~"2 |   <b x='1'/>\n  |   ^^^^^^^^^^");
+        assert_eq!(root.span.get_ref().end.offset, SOURCE.len());
+    }
+
+    #[test]
+    fn test_equal_regardless_of_source() {
+        let plain: Element = from_str(SOURCE).unwrap();
+        let (spanned, errors) = parse_recovering(SOURCE);
+        assert_eq!(errors, ~[]);
+        assert!(spanned.get_ref().span.is_some());
+        assert_eq!(spanned, Some(plain.clone()));
+        let lossless = parse_lossless(SOURCE).unwrap();
+        assert!(lossless.trivia.is_some());
+        assert_eq!(lossless, plain);
+    }
+
+    #[test]
+    fn test_error_snippet() {
+        let mut p = Parser::new();
+        let source = "<a>\n<b c='<'/></a>";
+        p.parse_str(source, |result| {
+            match result {
+                Err(e) => assert_eq!(e.snippet(source), ~"2 | <b c='<'/></a>\n  |       ^"),
+                Ok(_) => ()
+            }
+        });
+    }
+
+    #[test]
+    fn test_snippet_without_position() {
+        let start = Position { offset: 0, line: 0, col: 0 };
+        let span = Span { start: start.clone(), end: start };
+        assert_eq!(span.snippet("<a/>"), ~"");
+    }
+}
+
+#[cfg(test)]
+mod whitespace_tests {
+    use xml::{Parser, ElementBuilder, Root, Element, WhitespaceMode};
+    use xml::{CharacterNode, Characters, KeepWhitespace, DropWhitespace, TrimWhitespace};
+
+    fn build(data: &str, mode: WhitespaceMode, coalesce: bool) -> Element {
+        let mut p = Parser::new();
+        let mut e = ElementBuilder::new();
+        e.set_whitespace(mode);
+        e.set_coalesce(coalesce);
+        let mut result = None;
+        p.parse_str(data, |event| {
+            match e.push_event(event.unwrap()) {
+                Ok(Some(Root(elem))) => result = Some(elem),
+                _ => ()
+            }
+        });
+        result.unwrap()
+    }
+
+    #[test]
+    fn test_parser_drop_whitespace() {
+        let mut p = Parser::new();
+        p.set_whitespace(DropWhitespace);
+        let mut text = ~[];
+        p.parse_str("<a>\n  <b> x </b>\n  <c xml:space='preserve'> </c>\n</a>", |event| {
+            match event {
+                Ok(Characters(chars)) => text.push(chars),
+                _ => ()
+            }
+        });
+        assert_eq!(text, ~[~" x ", ~" "]);
+    }
+
+    #[test]
+    fn test_builder_whitespace() {
+        let data = "<a>\n  <b> x </b>\n</a>";
+        assert_eq!(build(data, KeepWhitespace, false).children.len(), 3);
+
+        let elem = build(data, DropWhitespace, false);
+        assert_eq!(elem.children.len(), 1);
+        assert_eq!(elem.child_with_name("b").unwrap().children, ~[CharacterNode(~" x ")]);
+
+        let elem = build(data, TrimWhitespace, false);
+        assert_eq!(elem.child_with_name("b").unwrap().children, ~[CharacterNode(~"x")]);
+    }
+
+    #[test]
+    fn test_builder_coalesce() {
+        let elem = build("<a> x &amp; <![CDATA[<y>]]> </a>", TrimWhitespace, true);
+        assert_eq!(elem.children, ~[CharacterNode(~"x & <y>")]);
+    }
+
+    #[test]
+    fn test_builder_preserve() {
+        let elem = build("<a xml:space='preserve'> <b xml:space='default'> </b> </a>",
+                         DropWhitespace, false);
+        assert_eq!(elem.children.len(), 3);
+        assert_eq!(elem.child_with_name("b").unwrap().children.len(), 0);
+    }
+}
+
+#[cfg(test)]
+mod recovery_tests {
+    use xml::{parse_recovering, Parser, StartTag, EndTag};
+
+    #[test]
+    fn test_recover_mismatch_and_skip() {
+        let (root, errors) = parse_recovering("<r><a><b></a><c x='1' x='2'/><d>t</d></r>");
+        let msgs: ~[~str] = errors.move_iter().map(|e| e.msg).collect();
+        assert_eq!(msgs, ~[~"Expected '</b>', found '</a>'", ~"Duplicate attribute 'x'"]);
+
+        let root = root.unwrap();
+        assert!(root.child_with_name("a").unwrap().child_with_name("b").is_some());
+        assert!(root.child_with_name("c").is_none());
+        assert_eq!(root.child_with_name("d").unwrap().content_str(), ~"t");
+    }
+
+    #[test]
+    fn test_recover_unclosed() {
+        let (root, errors) = parse_recovering("<r><a>text");
+        let msgs: ~[~str] = errors.move_iter().map(|e| e.msg).collect();
+        assert_eq!(msgs, ~[~"Unclosed element 'a'", ~"Unclosed element 'r'"]);
+        assert_eq!(root.unwrap().child_with_name("a").unwrap().content_str(), ~"text");
+    }
+
+    #[test]
+    fn test_recover_balanced_events() {
+        let mut p = Parser::new();
+        p.set_recover(true);
+        let mut depth = 0;
+        let mut errors = 0;
+        p.parse_str("<a><b/x><c></d></c>", |result| {
+            match result {
+                Ok(StartTag(_)) => depth += 1,
+                Ok(EndTag(_)) => depth -= 1,
+                Ok(_) => (),
+                Err(_) => errors += 1
+            }
+        });
+        p.finish(|result| {
+            match result {
+                Ok(EndTag(_)) => depth -= 1,
+                Ok(_) => (),
+                Err(_) => errors += 1
+            }
+        });
+        assert_eq!(depth, 0);
+        assert_eq!(errors, 3);
+    }
+}
+
+#[cfg(test)]
+mod serialize_tests {
+    use xml::{Element, SerializeOptions, SortedOrder, CommentNode, ISO88591, Windows1252};
+
+    static PROFILE: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Profile xmlns=\"http://soap.sforce.com/2006/04/metadata\">
+    <custom>false</custom>
+    <fieldPermissions>
+        <editable>true</editable>
+        <field>Account.Rating</field>
+        <readable>true</readable>
+    </fieldPermissions>
+    <loginIpRanges/>
+    <userLicense>Salesforce</userLicense>
+</Profile>
+";
+
+    #[test]
+    fn test_pretty_round_trip() {
+        let elem: Element = from_str(PROFILE).unwrap();
+        assert_eq!(elem.serialize(&SerializeOptions::pretty()), Ok(PROFILE.to_owned()));
+    }
+
+    #[test]
+    fn test_compact_matches_to_str() {
+        let elem: Element = from_str("<a xmlns='urn:x' b='&amp;'><c>t</c><d/></a>").unwrap();
+        assert_eq!(elem.serialize(&SerializeOptions::compact()), Ok(elem.to_str()));
+        assert_eq!(elem.to_str(), ~"<a xmlns='urn:x' b='&amp;'><c>t</c><d/></a>");
+    }
+
+    #[test]
+    fn test_explicit_empty_tags() {
+        let elem: Element = from_str("<a><b/></a>").unwrap();
+        let mut opts = SerializeOptions::compact();
+        opts.self_closing = false;
+        assert_eq!(elem.serialize(&opts), Ok(~"<a><b></b></a>"));
+    }
+
+    #[test]
+    fn test_mixed_content_not_indented() {
+        let elem: Element = from_str("<a><b>x <i>y</i> z</b></a>").unwrap();
+        let mut opts = SerializeOptions::pretty();
+        opts.declaration = None;
+        opts.final_newline = false;
+        opts.newline = ~"\r\n";
+        opts.indent = Some(~"\t");
+        assert_eq!(elem.serialize(&opts), Ok(~"<a>\r\n\t<b>x <i>y</i> z</b>\r\n</a>"));
+    }
+
+    #[test]
+    fn test_sorted_attributes() {
+        let elem: Element = from_str("<a z='1' xmlns:q='urn:q' xmlns:p='urn:p' m='2' q:a='4' p:c='5' p:b='3'/>").unwrap();
+        let mut opts = SerializeOptions::compact();
+        opts.attribute_order = SortedOrder;
+        opts.quote = '"';
+        assert_eq!(elem.serialize(&opts), Ok(~"<a xmlns:q=\"urn:q\" xmlns:p=\"urn:p\" m=\"2\" z=\"1\" \
+                                               p:b=\"3\" p:c=\"5\" q:a=\"4\"/>"));
+    }
+
+    #[test]
+    fn test_escaping() {
+        let elem = Element::new("a").text("'\"\r").attr("b", "'\"\t\n");
+        assert_eq!(elem.serialize(&SerializeOptions::compact()),
+                   Ok(~"<a b='&apos;\"&#9;&#10;'>'\"&#13;</a>"));
+        let mut opts = SerializeOptions::pretty();
+        opts.declaration = None;
+        opts.final_newline = false;
+        assert_eq!(elem.serialize(&opts), Ok(~"<a b=\"&apos;&quot;&#9;&#10;\">&apos;&quot;&#13;</a>"));
+    }
+
+    #[test]
+    fn test_invalid_characters() {
+        let elem = Element::new("a").text("\x01");
+        assert_eq!(elem.serialize(&SerializeOptions::compact()), Err(~"Invalid character 0x1"));
+        let elem = Element::new("a").text("\x85\x9f");
+        assert_eq!(elem.serialize(&SerializeOptions::compact()), Ok(~"<a>\x85&#x9F;</a>"));
+    }
+
+    #[test]
+    fn test_encoding_references() {
+        let elem = Element::new("a").attr("b", "\u00e9\u20ac").text("\u00e9\u20ac");
+        let mut opts = SerializeOptions::compact();
+        opts.escape.encoding = ISO88591;
+        assert_eq!(elem.serialize(&opts), Ok(~"<a b='\u00e9&#x20AC;'>\u00e9&#x20AC;</a>"));
+        opts.escape.encoding = Windows1252;
+        assert_eq!(elem.serialize(&opts), Ok(~"<a b='\u00e9\u20ac'>\u00e9\u20ac</a>"));
+
+        let mut elem = Element::new("a").child(Element::new("b")).text("x");
+        elem.append_child(CommentNode(~"\u20ac"));
+        opts.escape.encoding = ISO88591;
+        assert_eq!(elem.serialize(&opts), Err(~"Character 0x20ac can not be written in ISO-8859-1"));
+    }
+}
+
+#[cfg(test)]
+mod writer_tests {
+    use xml::{XmlWriter, Parser, SerializeOptions, Attribute, Atom};
+    use xml::{Event, StartTag, EndTag, Characters, Comment};
+    use std::io::Decorator;
+    use std::io::mem::MemWriter;
+    use std::str;
+
+    fn start(name: &str, ns: Option<~str>, prefix: Option<~str>, attributes: ~[Attribute]) -> Event {
+        StartTag(StartTag { name: Atom::new(name), ns: ns, prefix: prefix, attributes: attributes })
+    }
+
+    fn end(name: &str, ns: Option<~str>) -> Event {
+        EndTag(EndTag { name: Atom::new(name), ns: ns, prefix: None })
+    }
+
+    fn write_all(events: ~[Event], opts: SerializeOptions) -> Result<~str, ~str> {
+        let mut w = XmlWriter::with_options(MemWriter::new(), opts);
+        for e in events.move_iter() {
+            match w.write_event(e) {
+                Ok(()) => (),
+                Err(msg) => return Err(msg)
+            }
+        }
+        match w.finish() {
+            Ok(()) => Ok(str::from_utf8_owned(w.unwrap().inner())),
+            Err(msg) => Err(msg)
+        }
+    }
+
+    #[test]
+    fn test_escaping() {
+        let attr = Attribute { name: Atom::new("a"), ns: None, value: ~"'&'" };
+        let out = write_all(~[start("r", None, None, ~[attr]), Characters(~"<x>"), end("r", None)],
+                            SerializeOptions::compact());
+        assert_eq!(out, Ok(~"<r a='&apos;&amp;&apos;'>&lt;x&gt;</r>"));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let attr = Attribute { name: Atom::new("id"), ns: Some(~"urn:b"), value: ~"1" };
+        let out = write_all(~[start("r", Some(~"urn:a"), None, ~[]),
+                              start("c", Some(~"urn:a"), None, ~[attr]),
+                              start("p", Some(~"urn:c"), Some(~"x"), ~[]),
+                              end("p", Some(~"urn:c")),
+                              end("c", Some(~"urn:a")),
+                              start("n", None, None, ~[]),
+                              end("n", None),
+                              end("r", Some(~"urn:a"))],
+                            SerializeOptions::compact());
+        assert_eq!(out, Ok(~"<r xmlns='urn:a'><c xmlns:ns0='urn:b' ns0:id='1'>\
+                            <x:p xmlns:x='urn:c'/></c><n xmlns=''/></r>"));
+    }
+
+    #[test]
+    fn test_unbalanced() {
+        let out = write_all(~[start("a", None, None, ~[]), end("b", None)], SerializeOptions::compact());
+        assert_eq!(out, Err(~"Elements not properly nested"));
+        let out = write_all(~[start("a", None, None, ~[])], SerializeOptions::compact());
+        assert_eq!(out, Err(~"Unclosed element 'a'"));
+        let out = write_all(~[Comment(~"a--b")], SerializeOptions::compact());
+        assert_eq!(out, Err(~"Comment contains '--'"));
+    }
+
+    #[test]
+    fn test_invalid_characters() {
+        let out = write_all(~[start("a", None, None, ~[]), Characters(~"\x01")], SerializeOptions::compact());
+        assert_eq!(out, Err(~"Invalid character 0x1"));
+        let attr = Attribute { name: Atom::new("b"), ns: None, value: ~"\x02" };
+        let out = write_all(~[start("a", None, None, ~[attr])], SerializeOptions::compact());
+        assert_eq!(out, Err(~"Invalid character 0x2"));
+    }
+
+    #[test]
+    fn test_failed_start_tag() {
+        let mut opts = SerializeOptions::pretty();
+        opts.declaration = None;
+        let mut w = XmlWriter::with_options(MemWriter::new(), opts);
+        let dup = ~[Attribute { name: Atom::new("x"), ns: None, value: ~"1" },
+                    Attribute { name: Atom::new("x"), ns: None, value: ~"2" }];
+        assert_eq!(w.write_event(start("a", None, None, ~[])), Ok(()));
+        assert_eq!(w.write_event(start("b", None, None, dup)), Err(~"Duplicate attribute 'x'"));
+        assert_eq!(w.write_event(start("1c", None, None, ~[])), Err(~"Invalid name '1c'"));
+        assert_eq!(w.write_event(end("a", None)), Ok(()));
+        assert_eq!(w.finish(), Ok(()));
+        assert_eq!(str::from_utf8_owned(w.unwrap().inner()), ~"<a/>\n");
+    }
+
+    #[test]
+    fn test_pretty_from_parser() {
+        let input = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Profile xmlns=\"http://soap.sforce.com/2006/04/metadata\">
+    <custom>false</custom>
+    <!-- note -->
+    <loginIpRanges/>
+</Profile>
+";
+        let mut p = Parser::new();
+        let mut w = XmlWriter::with_options(MemWriter::new(), SerializeOptions::pretty());
+        p.parse_str(input, |event| {
+            assert_eq!(w.write_event(event.unwrap()), Ok(()));
+        });
+        assert_eq!(w.finish(), Ok(()));
+        assert_eq!(str::from_utf8_owned(w.unwrap().inner()), input.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod c14n_tests {
+    use xml::{Element, C14NOptions, ExclusiveC14N};
+
+    fn canonical(input: &str, opts: &C14NOptions) -> ~str {
+        let elem: Element = from_str(input).unwrap();
+        elem.canonicalize(opts)
+    }
+
+    // W3C Canonical XML 1.0, example 3.1, without the content outside the root
+    #[test]
+    fn test_comments() {
+        let input = "<doc>Hello, world!<!-- Comment 1 --><?pi-without-data?></doc>";
+        let mut opts = C14NOptions::canonical();
+        assert_eq!(canonical(input, &opts), ~"<doc>Hello, world!<?pi-without-data?></doc>");
+        opts.with_comments = true;
+        assert_eq!(canonical(input, &opts),
+                   ~"<doc>Hello, world!<!-- Comment 1 --><?pi-without-data?></doc>");
+    }
+
+    // W3C Canonical XML 1.0, example 3.3, without the DTD
+    #[test]
+    fn test_start_and_end_tags() {
+        let input = "<doc>
+   <e1   />
+   <e2   ></e2>
+   <e3   name = \"elem3\"   id=\"elem3\"   />
+   <e4   name=\"elem4\"   id=\"elem4\"   ></e4>
+   <e5 a:attr=\"out\" b:attr=\"sorted\" attr2=\"all\" attr=\"I'm\"
+      xmlns:b=\"http://www.ietf.org\"
+      xmlns:a=\"http://www.w3.org\"
+      xmlns=\"http://example.org\"/>
+   <e6 xmlns=\"\" xmlns:a=\"http://www.w3.org\">
+      <e7 xmlns=\"http://www.ietf.org\">
+         <e8 xmlns=\"\" xmlns:a=\"http://www.w3.org\">
+            <e9 xmlns=\"\" xmlns:a=\"http://www.ietf.org\"/>
+         </e8>
+      </e7>
+   </e6>
+</doc>";
+        let expected = "<doc>
+   <e1></e1>
+   <e2></e2>
+   <e3 id=\"elem3\" name=\"elem3\"></e3>
+   <e4 id=\"elem4\" name=\"elem4\"></e4>
+   <e5 xmlns=\"http://example.org\" xmlns:a=\"http://www.w3.org\" xmlns:b=\"http://www.ietf.org\" \
+attr=\"I'm\" attr2=\"all\" b:attr=\"sorted\" a:attr=\"out\"></e5>
+   <e6 xmlns:a=\"http://www.w3.org\">
+      <e7 xmlns=\"http://www.ietf.org\">
+         <e8 xmlns=\"\">
+            <e9 xmlns:a=\"http://www.ietf.org\"></e9>
+         </e8>
+      </e7>
+   </e6>
+</doc>";
+        assert_eq!(canonical(input, &C14NOptions::canonical()), expected.to_owned());
+    }
+
+    // W3C Canonical XML 1.0, example 3.4, without the DTD
+    #[test]
+    fn test_character_modifications() {
+        let input = "<doc>
+   <text>First line&#x0d;&#10;Second line</text>
+   <value>&#x32;</value>
+   <compute><![CDATA[value>\"0\" && value<\"10\" ?\"valid\":\"error\"]]></compute>
+   <compute expr='value>\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"'>valid</compute>
+   <norm attr=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/>
+</doc>";
+        let expected = "<doc>
+   <text>First line&#xD;
+Second line</text>
+   <value>2</value>
+   <compute>value&gt;\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"</compute>
+   <compute expr=\"value>&quot;0&quot; &amp;&amp; value&lt;&quot;10&quot; ?&quot;valid&quot;:&quot;error&quot;\">valid</compute>
+   <norm attr=\" '    &#xD;&#xA;&#x9;   ' \"></norm>
+</doc>";
+        assert_eq!(canonical(input, &C14NOptions::canonical()), expected.to_owned());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let input = "<a>x\r\ny\rz</a>";
+        assert_eq!(canonical(input, &C14NOptions::canonical()), ~"<a>x\ny\nz</a>");
+    }
+
+    // W3C Exclusive XML Canonicalization 1.0, section 2.2
+    #[test]
+    fn test_exclusive() {
+        let input = "<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\">\
+<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\
+<n3:stuff xmlns:n3=\"ftp://example.org\"/></n1:elem2></n0:local>";
+        assert_eq!(canonical(input, &C14NOptions::canonical()),
+                   ~"<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\">\
+<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\
+<n3:stuff></n3:stuff></n1:elem2></n0:local>");
+        assert_eq!(canonical(input, &C14NOptions::exclusive()),
+                   ~"<n0:local xmlns:n0=\"foo:bar\">\
+<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\
+<n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff></n1:elem2></n0:local>");
+
+        let mut opts = C14NOptions::exclusive();
+        opts.method = ExclusiveC14N(~[~"n3"]);
+        assert_eq!(canonical(input, &opts),
+                   ~"<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\">\
+<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\
+<n3:stuff></n3:stuff></n1:elem2></n0:local>");
+    }
+
+    // The namespaces of W3C Canonical XML 1.0, example 3.3, element e5, with both
+    // prefixes bound to the same namespace: each element keeps the prefix it was written with
+    #[test]
+    fn test_prefixes_sharing_a_namespace() {
+        let input = "<doc xmlns:a=\"http://www.w3.org\" xmlns:b=\"http://www.w3.org\">\
+<a:e1/><b:e2><a:e3/></b:e2></doc>";
+        assert_eq!(canonical(input, &C14NOptions::canonical()),
+                   ~"<doc xmlns:a=\"http://www.w3.org\" xmlns:b=\"http://www.w3.org\">\
+<a:e1></a:e1><b:e2><a:e3></a:e3></b:e2></doc>");
+        assert_eq!(canonical(input, &C14NOptions::exclusive()),
+                   ~"<doc><a:e1 xmlns:a=\"http://www.w3.org\"></a:e1>\
+<b:e2 xmlns:b=\"http://www.w3.org\"><a:e3 xmlns:a=\"http://www.w3.org\"></a:e3></b:e2></doc>");
+
+        let input = "<doc xmlns=\"http://www.w3.org\" xmlns:a=\"http://www.w3.org\"><a:e1/><e2/></doc>";
+        assert_eq!(canonical(input, &C14NOptions::canonical()),
+                   ~"<doc xmlns=\"http://www.w3.org\" xmlns:a=\"http://www.w3.org\">\
+<a:e1></a:e1><e2></e2></doc>");
+    }
+}
+
+#[cfg(test)]
+mod xpath_tests {
+    use xml::{Element, XPath, XPathContext, XPathNode, XPathElement, XPathAttribute, XPathText};
+    use xml::{NodeSet, StringValue, NumberValue, BooleanValue};
+
+    static DOC: &'static str = "<Profile xmlns=\"http://soap.sforce.com/2006/04/metadata\">\
+<fieldPermissions><editable>true</editable><field>Account.Rating</field></fieldPermissions>\
+<fieldPermissions><editable>false</editable><field>Account.Site</field></fieldPermissions>\
+<!-- c --><userLicense>Salesforce</userLicense></Profile>";
+
+    fn names(nodes: ~[XPathNode]) -> ~[~str] {
+        nodes.iter().map(|n| match *n {
+            XPathElement(e) => e.name.to_str(),
+            XPathAttribute(a) => format!("@{}", a.name),
+            XPathText(ref t) => t.clone(),
+            _ => ~"?"
+        }).collect()
+    }
+
+    fn select(doc: &Element, expr: &str) -> ~[~str] {
+        let mut ctx = XPathContext::new();
+        ctx.bind_namespace("sf", "http://soap.sforce.com/2006/04/metadata");
+        names(XPath::compile(expr).unwrap().select(doc, &ctx).unwrap())
+    }
+
+    fn eval(doc: &Element, expr: &str) -> ~str {
+        let mut ctx = XPathContext::new();
+        ctx.bind_namespace("sf", "http://soap.sforce.com/2006/04/metadata");
+        XPath::compile(expr).unwrap().evaluate(doc, &ctx).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_location_paths() {
+        let doc: Element = from_str(DOC).unwrap();
+        assert_eq!(select(&doc, "/sf:Profile/sf:fieldPermissions/sf:field/text()"),
+                   ~[~"Account.Rating", ~"Account.Site"]);
+        assert_eq!(select(&doc, "//sf:field[../sf:editable = 'true']"), ~[~"field"]);
+        assert_eq!(select(&doc, "//sf:fieldPermissions[2]/*"), ~[~"editable", ~"field"]);
+        assert_eq!(select(&doc, "//sf:fieldPermissions[last()]/sf:field/text()"), ~[~"Account.Site"]);
+        assert_eq!(select(&doc, "//sf:editable/following::sf:*[1]"), ~[~"field", ~"field"]);
+        assert_eq!(select(&doc, "//sf:userLicense/preceding-sibling::*"),
+                   ~[~"fieldPermissions", ~"fieldPermissions"]);
+        assert_eq!(select(&doc, "(//sf:field)[1]/ancestor::*"), ~[~"Profile", ~"fieldPermissions"]);
+        assert_eq!(select(&doc, "//sf:field | //sf:userLicense"), ~[~"field", ~"field", ~"userLicense"]);
+        // Unprefixed names only match elements without namespace
+        assert_eq!(select(&doc, "//field"), ~[]);
+    }
+
+    #[test]
+    fn test_attributes_and_namespaces() {
+        let doc: Element = from_str("<a xmlns:p='urn:p' x='1' p:y='2'><b x='3'/></a>").unwrap();
+        assert_eq!(select(&doc, "//@x"), ~[~"@x", ~"@x"]);
+        assert_eq!(select(&doc, "/a/@*"), ~[~"@x", ~"@y"]);
+        assert_eq!(eval(&doc, "name(/a/@*[2])"), ~"p:y");
+        assert_eq!(eval(&doc, "namespace-uri(/a/@*[2])"), ~"urn:p");
+        assert_eq!(eval(&doc, "count(/a/b/namespace::*)"), ~"2");
+        assert_eq!(eval(&doc, "sum(//@x)"), ~"4");
+    }
+
+    #[test]
+    fn test_functions() {
+        let doc: Element = from_str(DOC).unwrap();
+        assert_eq!(eval(&doc, "count(//sf:fieldPermissions)"), ~"2");
+        assert_eq!(eval(&doc, "string(//sf:userLicense)"), ~"Salesforce");
+        assert_eq!(eval(&doc, "concat('a', 1, true())"), ~"a1true");
+        assert_eq!(eval(&doc, "substring('12345', 1.5, 2.6)"), ~"234");
+        assert_eq!(eval(&doc, "substring-after('Account.Rating', '.')"), ~"Rating");
+        assert_eq!(eval(&doc, "normalize-space('  a \n b ')"), ~"a b");
+        assert_eq!(eval(&doc, "translate('bar', 'abc', 'ABC')"), ~"BAr");
+        assert_eq!(eval(&doc, "string-length(local-name(/*))"), ~"7");
+        assert_eq!(eval(&doc, "round(2.5) + floor(-1.5) * ceiling(0.2)"), ~"1");
+        assert_eq!(eval(&doc, "10 div 4"), ~"2.5");
+        assert_eq!(eval(&doc, "7 mod 3 = 1 and not(1 > 2)"), ~"true");
+        assert_eq!(eval(&doc, "number('x')"), ~"NaN");
+    }
+
+    #[test]
+    fn test_values() {
+        let doc: Element = from_str("<a><b>1</b><b>2</b></a>").unwrap();
+        let ctx = XPathContext::new();
+        let eval = |expr: &str| XPath::compile(expr).unwrap().evaluate(&doc, &ctx).unwrap();
+        assert_eq!(eval("1 + 1"), NumberValue(2.0));
+        assert_eq!(eval("'a'"), StringValue(~"a"));
+        assert_eq!(eval("//b = 2"), BooleanValue(true));
+        assert_eq!(eval("//b > 1"), BooleanValue(true));
+        match eval("/a/b") {
+            NodeSet(nodes) => assert_eq!(nodes.len(), 2),
+            _ => fail!()
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        let doc: Element = from_str("<a><b n='x'/><b n='y'/></a>").unwrap();
+        let xpath = XPath::compile("/a/b[@n = $name]").unwrap();
+        let mut ctx = XPathContext::new();
+        ctx.set_variable("name", StringValue(~"y"));
+        assert_eq!(xpath.select(&doc, &ctx).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let doc: Element = from_str("<a/>").unwrap();
+        assert!(XPath::compile("/a[").is_err());
+        assert!(XPath::compile("foo(").is_err());
+        assert!(XPath::compile("1 +").is_err());
+        assert_eq!(doc.xpath("//p:a"), Err(~"Unbound namespace prefix 'p'"));
+        assert_eq!(doc.xpath("nope()"), Err(~"Unknown function 'nope'"));
+    }
+}
+
+#[cfg(test)]
+mod dom_tests {
+    use xml::{Element, Attribute, CharacterNode, CommentNode, Atom};
+
+    static SF_NS: &'static str = "http://soap.sforce.com/2006/04/metadata";
+
+    #[test]
+    fn test_fluent_constructor() {
+        let elem = Element::new("fieldPermissions").ns(SF_NS)
+            .child(Element::new("field").ns(SF_NS).text("Account.Phone"))
+            .child(Element::new("readable").ns(SF_NS).text("true"));
+        assert_eq!(elem.to_str(), format!("<fieldPermissions xmlns='{}'><field>Account.Phone</field>\
+<readable>true</readable></fieldPermissions>", SF_NS));
+    }
+
+    #[test]
+    fn test_children() {
+        let mut elem: Element = from_str("<a><b/><c/><d/></a>").unwrap();
+        elem.append_child(CommentNode(~"x"));
+        elem.insert_child(0, CharacterNode(~"t"));
+        assert_eq!(elem.to_str(), ~"<a>t<b/><c/><d/><!--x--></a>");
+
+        let removed = elem.remove_child(0);
+        assert_eq!(removed, CharacterNode(~"t"));
+        elem.replace_child(0, CharacterNode(~"u"));
+        elem.move_child(0, 2);
+        assert_eq!(elem.to_str(), ~"<a><c/><d/>u<!--x--></a>");
+
+        let comments = elem.remove_children_where(|c| match *c { CommentNode(_) => true, _ => false });
+        assert_eq!(comments, ~[CommentNode(~"x")]);
+        let d = elem.detach_child_with_name_and_ns("d", None).unwrap();
+        assert_eq!(d.name.as_slice(), "d");
+        assert!(elem.remove_child_where(|c| match *c { CommentNode(_) => true, _ => false }).is_none());
+        assert_eq!(elem.to_str(), ~"<a><c/>u</a>");
+    }
+
+    #[test]
+    fn test_text() {
+        let mut elem: Element = from_str("<a>x<b/>y</a>").unwrap();
+        elem.set_text("z");
+        assert_eq!(elem.content_str(), ~"z");
+        assert_eq!(elem.children.len(), 1);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut elem = Element::new("a").attr("x", "1");
+        elem.set_attribute("x", "2");
+        elem.set_attribute_with_ns("y", Some(~"urn:y"), "3");
+        assert_eq!(elem.to_str(), ~"<a xmlns:ns0='urn:y' x='2' ns0:y='3'/>");
+
+        assert_eq!(elem.remove_attribute_with_ns("y", Some(~"urn:y")),
+                   Some(Attribute { name: Atom::new("y"), ns: Some(~"urn:y"), value: ~"3" }));
+        assert_eq!(elem.remove_attribute("y"), None);
+        assert_eq!(elem.attribute_with_name("x").unwrap().value, ~"2");
+    }
+}
+
+#[cfg(test)]
+mod traverse_tests {
+    use xml::{Element, XML, CharacterNode, Visitor, VisitAction};
+    use xml::{VisitChildren, SkipChildren, StopVisit};
+
+    static DOC: &'static str = "<a><b><c/>x</b><d><e/></d></a>";
+
+    fn name(node: &XML) -> ~str {
+        match *node {
+            Element(ref e) => e.name.to_str(),
+            CharacterNode(ref t) => t.clone(),
+            _ => ~"?"
+        }
+    }
+
+    #[test]
+    fn test_descendants() {
+        let doc: Element = from_str(DOC).unwrap();
+        let depth_first: ~[~str] = doc.descendants().map(name).collect();
+        assert_eq!(depth_first, ~[~"b", ~"c", ~"x", ~"d", ~"e"]);
+        let breadth_first: ~[~str] = doc.descendants_breadth_first().map(name).collect();
+        assert_eq!(breadth_first, ~[~"b", ~"d", ~"c", ~"x", ~"e"]);
+    }
+
+    #[test]
+    fn test_walk() {
+        let doc: Element = from_str(DOC).unwrap();
+        let paths: ~[~str] = doc.walk().map(|(path, node)| {
+            let names: ~[~str] = path.iter().map(|e| e.name.to_str()).collect();
+            format!("{}/{}", names.connect("/"), name(node))
+        }).collect();
+        assert_eq!(paths, ~[~"a/b", ~"a/b/c", ~"a/b/x", ~"a/d", ~"a/d/e"]);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let doc: Element = from_str(DOC).unwrap();
+        let mut c = doc.cursor();
+        assert!(!c.parent());
+        assert!(!c.next_sibling());
+        assert!(c.first_child());
+        assert_eq!(c.element().unwrap().name.as_slice(), "b");
+        assert!(c.last_child());
+        assert_eq!(c.node(), Some(&CharacterNode(~"x")));
+        assert!(c.element().is_none());
+        assert!(!c.first_child());
+        let ancestors: ~[~str] = c.ancestors().iter().map(|e| e.name.to_str()).collect();
+        assert_eq!(ancestors, ~[~"b", ~"a"]);
+        assert!(c.prev_sibling());
+        assert_eq!(c.element().unwrap().name.as_slice(), "c");
+        assert!(!c.prev_sibling());
+        assert!(c.parent());
+        assert!(c.next_sibling());
+        assert_eq!(c.element().unwrap().name.as_slice(), "d");
+        assert_eq!(c.depth(), 1);
+        assert!(!c.next_sibling());
+    }
+
+    struct Recorder {
+        events: ~[~str],
+        skip: ~str,
+        stop: ~str
+    }
+
+    impl Visitor for Recorder {
+        fn enter(&mut self, elem: &Element) -> VisitAction {
+            self.events.push(format!("+{}", elem.name));
+            if elem.name.as_slice() == self.stop.as_slice() {
+                StopVisit
+            } else if elem.name.as_slice() == self.skip.as_slice() {
+                SkipChildren
+            } else {
+                VisitChildren
+            }
+        }
+
+        fn leave(&mut self, elem: &Element) {
+            self.events.push(format!("-{}", elem.name));
+        }
+
+        fn visit_node(&mut self, node: &XML) {
+            self.events.push(name(node));
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let doc: Element = from_str(DOC).unwrap();
+        let mut v = Recorder { events: ~[], skip: ~"b", stop: ~"" };
+        assert!(doc.accept(&mut v));
+        assert_eq!(v.events, ~[~"+a", ~"+b", ~"-b", ~"+d", ~"+e", ~"-e", ~"-d", ~"-a"]);
+
+        let mut v = Recorder { events: ~[], skip: ~"", stop: ~"c" };
+        assert!(!doc.accept(&mut v));
+        assert_eq!(v.events, ~[~"+a", ~"+b", ~"+c"]);
+    }
+}
+
+#[cfg(test)]
+mod namespace_tests {
+    use xml::{Parser, ElementBuilder, Root, Element};
+
+    #[test]
+    fn test_declares_namespaces() {
+        let mut elem = Element::new("a").ns("urn:a")
+            .child(Element::new("b").ns("urn:b"))
+            .child(Element::new("c"));
+        elem.set_attribute_with_ns("x", Some(~"urn:x"), "1");
+        assert_eq!(elem.to_str(), ~"<a xmlns='urn:a' xmlns:ns0='urn:x' ns0:x='1'>\
+<b xmlns='urn:b'/><c xmlns=''/></a>");
+    }
+
+    #[test]
+    fn test_reuses_prefixes() {
+        let mut elem: Element = from_str("<p:a xmlns:p='urn:p'><p:b/></p:a>").unwrap();
+        let mut c = Element::new("c").ns("urn:p");
+        c.set_attribute_with_ns("x", Some(~"urn:p"), "1");
+        elem.append_child(Element(c));
+        assert_eq!(elem.to_str(), ~"<p:a xmlns:p='urn:p'><p:b/><p:c p:x='1'/></p:a>");
+    }
+
+    #[test]
+    fn test_drops_redundant_declarations() {
+        let elem: Element = from_str("<a xmlns:p='urn:p'><b xmlns:p='urn:p'/></a>").unwrap();
+        assert_eq!(elem.to_str(), ~"<a xmlns:p='urn:p'><b/></a>");
+        let elem: Element = from_str("<a xmlns='urn:a'><b xmlns='urn:a'/><c xmlns=''/></a>").unwrap();
+        assert_eq!(elem.to_str(), ~"<a xmlns='urn:a'><b/><c xmlns=''/></a>");
+    }
+
+    #[test]
+    fn test_stores_namespace_uris() {
+        let elem: Element = from_str("<p:a xmlns:p='urn:p' xmlns='urn:d'><b/></p:a>").unwrap();
+        assert_eq!(elem.ns, Some(~"urn:p"));
+        assert_eq!(elem.child_with_name_and_ns("b", Some(~"urn:d")).unwrap().ns, Some(~"urn:d"));
+    }
+
+    #[test]
+    fn test_keeps_source_prefixes() {
+        let doc = "<a xmlns:p='urn:p' xmlns:q='urn:p'><p:b/><q:c/></a>";
+        let elem: Element = from_str(doc).unwrap();
+        assert_eq!(elem.to_str(), doc.to_owned());
+        let doc = "<a xmlns='urn:p' xmlns:p='urn:p'><p:b/><c/></a>";
+        let elem: Element = from_str(doc).unwrap();
+        assert_eq!(elem.to_str(), doc.to_owned());
+    }
+
+    #[test]
+    fn test_builder_prefixes() {
+        let mut p = Parser::new();
+        let mut e = ElementBuilder::new();
+        e.define_prefix(~"p", ~"urn:p");
+        e.set_default_ns(~"urn:d");
+        let mut root = None;
+        p.parse_str("<a xmlns='urn:a'/>", |event| {
+            match e.push_event(event.unwrap()) {
+                Ok(Some(Root(elem))) => root = Some(elem),
+                _ => ()
+            }
+        });
+        let mut root = root.unwrap();
+        root.append_child(Element(Element::new("b").ns("urn:p")));
+        assert_eq!(root.to_str(), ~"<a xmlns='urn:a' xmlns:p='urn:p'><p:b/></a>");
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use xml::{Element, DiffOptions, KeyAttribute, Move, UpdateText, UpdateAttribute};
+
+    fn round_trip(old: &str, new: &str, opts: &DiffOptions) -> ~str {
+        let mut old: Element = from_str(old).unwrap();
+        let new: Element = from_str(new).unwrap();
+        let script = old.diff(&new, opts).unwrap();
+        script.apply(&mut old).unwrap();
+        assert_eq!(old.to_str(), new.to_str());
+        assert!(old.diff(&new, opts).unwrap().is_empty());
+        format!("{}", script)
+    }
+
+    #[test]
+    fn test_keyed_children() {
+        let old = "<Profile><fieldPermissions><field>A.x</field><readable>true</readable></fieldPermissions>\
+<fieldPermissions><field>A.y</field><readable>true</readable></fieldPermissions></Profile>";
+        let new = "<Profile><fieldPermissions><field>A.y</field><readable>false</readable></fieldPermissions>\
+<fieldPermissions><field>A.z</field><readable>true</readable></fieldPermissions></Profile>";
+        assert_eq!(round_trip(old, new, &DiffOptions::metadata()), ~"\
+- /Profile/fieldPermissions[field='A.x']\n\
+~ /Profile/fieldPermissions[field='A.y']/readable/text(): \"true\" -> \"false\"\n\
++ /Profile/fieldPermissions[field='A.z']: \
+<fieldPermissions><field>A.z</field><readable>true</readable></fieldPermissions>\n");
+    }
+
+    #[test]
+    fn test_unkeyed_children() {
+        let old = "<a><b/><b x='1'/><c>t</c></a>";
+        let new = "<a><c>t</c><b x='2' y='3'/><b/></a>";
+        let script = round_trip(old, new, &DiffOptions::new());
+        assert_eq!(script, ~"\
+> /a/c: to position 1\n\
++ /a/b[1]/@x: \"2\"\n\
++ /a/b[1]/@y: \"3\"\n\
+- /a/b[2]/@x\n");
+    }
+
+    #[test]
+    fn test_indented_children() {
+        let old = "<a>\n  <b id='1'/>\n  <b id='2'/>\n</a>";
+        let new = "<a>\n  <b id='2'/>\n  <b id='1'/>\n</a>";
+        let mut opts = DiffOptions::new();
+        opts.keys.push((~"b", KeyAttribute(~"id")));
+        assert_eq!(round_trip(old, new, &opts), ~"\
+> /a/b[@id='2']: to position 2\n\
+> /a/b[@id='1']: to position 4\n");
+    }
+
+    #[test]
+    fn test_edits() {
+        let old: Element = from_str("<a><b id='1'>x</b><b id='2' z='1'/></a>").unwrap();
+        let new: Element = from_str("<a><b id='2'/><b id='1'>y</b></a>").unwrap();
+        let mut opts = DiffOptions::new();
+        opts.keys.push((~"b", KeyAttribute(~"id")));
+        let script = old.diff(&new, &opts).unwrap();
+        assert_eq!(script.edits.len(), 3);
+        match script.edits[0] {
+            Move(ref path, 0) => assert_eq!(path[0].label, ~"b[@id='2']"),
+            _ => fail!("Expected a move")
+        }
+        match script.edits[1] {
+            UpdateAttribute(_, ref name, None, Some(_), None) => assert_eq!(*name, ~"z"),
+            _ => fail!("Expected the removal of an attribute")
+        }
+        match script.edits[2] {
+            UpdateText(ref path, _, ref text) => {
+                assert_eq!(path.len(), 2);
+                assert_eq!(*text, ~"y");
+            }
+            _ => fail!("Expected a text update")
+        }
+
+        let mut other: Element = from_str("<a/>").unwrap();
+        assert!(script.apply(&mut other).is_err());
+        let c: Element = from_str("<c/>").unwrap();
+        assert_eq!(old.diff(&c, &opts).err(), Some(~"Root elements differ"));
+    }
+}
+
+#[cfg(test)]
+mod convert_tests {
+    use xml::{Element, FromXml, ToXml, XmlText};
+
+    #[deriving(Eq)]
+    struct Field {
+        name: ~str,
+        required: bool,
+        length: Option<uint>
+    }
+
+    xml_struct!(Field {
+        attribute name,
+        child required,
+        child length
+    })
+
+    #[deriving(Eq)]
+    struct Label {
+        lang: Option<~str>,
+        value: ~str
+    }
+
+    xml_struct!(Label {
+        attribute lang,
+        text value
+    })
+
+    #[deriving(Eq)]
+    struct Object {
+        label: Label,
+        fields: ~[Field],
+        cached: ~str
+    }
+
+    xml_struct!(Object {
+        child label,
+        child fields,
+        skip cached
+    })
+
+    #[test]
+    fn test_round_trip() {
+        let input = "<o xmlns='urn:o'><label lang='en'>Account</label>\
+<fields name='a'><required>true</required><length>80</length></fields>\
+<fields name='b'><required>0</required></fields></o>";
+        let elem: Element = from_str(input).unwrap();
+        let obj: Object = FromXml::from_xml(&elem).unwrap();
+        assert_eq!(obj, Object {
+            label: Label { lang: Some(~"en"), value: ~"Account" },
+            fields: ~[Field { name: ~"a", required: true, length: Some(80) },
+                      Field { name: ~"b", required: false, length: None }],
+            cached: ~""
+        });
+
+        let out = obj.to_xml("o", Some(~"urn:o"));
+        assert_eq!(out.to_str(), ~"<o xmlns='urn:o'><label lang='en'>Account</label>\
+<fields name='a'><required>true</required><length>80</length></fields>\
+<fields name='b'><required>false</required></fields></o>");
+    }
+
+    #[test]
+    fn test_errors() {
+        let elem: Element = from_str("<fields name='a'><length>80</length></fields>").unwrap();
+        let field: Result<Field, ~str> = FromXml::from_xml(&elem);
+        assert_eq!(field, Err(~"Missing element 'required'"));
+
+        let elem: Element = from_str("<fields><required>yes</required></fields>").unwrap();
+        let field: Result<Field, ~str> = FromXml::from_xml(&elem);
+        assert_eq!(field, Err(~"Missing attribute 'name'"));
+
+        let elem: Element = from_str("<fields name='a'><required>yes</required></fields>").unwrap();
+        let field: Result<Field, ~str> = FromXml::from_xml(&elem);
+        assert_eq!(field, Err(~"Invalid boolean 'yes'"));
+    }
+
+    #[test]
+    fn test_text() {
+        let n: Result<i32, ~str> = XmlText::from_xml_text(" -12 ");
+        assert_eq!(n, Ok(-12));
+        let n: Result<u8, ~str> = XmlText::from_xml_text("300");
+        assert_eq!(n, Err(~"Invalid number '300'"));
+        assert_eq!(1.5f64.to_xml_text(), ~"1.5");
+    }
+}
+
+#[cfg(test)]
+mod schema_tests {
+    use xml::{Element, Schema};
+
+    static XSD: &'static str = "<definitions xmlns='http://schemas.xmlsoap.org/wsdl/'><types>
+<xsd:schema xmlns:xsd='http://www.w3.org/2001/XMLSchema' xmlns:tns='urn:m'
+            targetNamespace='urn:m' elementFormDefault='qualified'>
+    <xsd:complexType name='Metadata'>
+        <xsd:sequence>
+            <xsd:element name='fullName' minOccurs='0' type='xsd:string'/>
+        </xsd:sequence>
+    </xsd:complexType>
+    <xsd:complexType name='Profile'>
+        <xsd:complexContent>
+            <xsd:extension base='tns:Metadata'>
+                <xsd:sequence>
+                    <xsd:element name='custom' minOccurs='0' type='xsd:boolean'/>
+                    <xsd:element name='fieldPermissions' minOccurs='0' maxOccurs='unbounded'
+                                 type='tns:ProfileFieldLevelSecurity'/>
+                    <xsd:element ref='tns:loginHours' minOccurs='0'/>
+                    <xsd:element name='userLicense' minOccurs='0' type='tns:License'/>
+                </xsd:sequence>
+            </xsd:extension>
+        </xsd:complexContent>
+    </xsd:complexType>
+    <xsd:complexType name='ProfileFieldLevelSecurity'>
+        <xsd:sequence>
+            <xsd:element name='editable' type='xsd:boolean'/>
+            <xsd:element name='field' type='xsd:string'/>
+            <xsd:element name='readable' minOccurs='0' type='xsd:boolean'/>
+        </xsd:sequence>
+    </xsd:complexType>
+    <xsd:simpleType name='License'>
+        <xsd:restriction base='xsd:string'>
+            <xsd:enumeration value='Salesforce'/>
+            <xsd:enumeration value='Chatter'/>
+        </xsd:restriction>
+    </xsd:simpleType>
+    <xsd:element name='loginHours'>
+        <xsd:complexType>
+            <xsd:sequence>
+                <xsd:element name='mondayStart' type='xsd:int'/>
+                <xsd:element name='changed' minOccurs='0' type='xsd:dateTime'/>
+            </xsd:sequence>
+        </xsd:complexType>
+    </xsd:element>
+</xsd:schema>
+</types></definitions>";
+
+    fn errors(doc: &str) -> ~[~str] {
+        let wsdl: Element = from_str(XSD).unwrap();
+        let schema = Schema::from_element(&wsdl).unwrap();
+        let doc: Element = from_str(doc).unwrap();
+        schema.validate_with_type(&doc, "Profile", Some(~"urn:m")).iter().map(|e| format!("{}", *e)).collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        let doc = "<Profile xmlns='urn:m'><fullName>Admin</fullName><custom>false</custom>
+<fieldPermissions><editable>true</editable><field>A.x</field><readable>1</readable></fieldPermissions>
+<fieldPermissions><editable>false</editable><field>A.y</field></fieldPermissions>
+<loginHours><mondayStart>480</mondayStart><changed>2014-01-31T08:00:00.000Z</changed></loginHours>
+<userLicense>Salesforce</userLicense></Profile>";
+        assert_eq!(errors(doc), ~[]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let doc = "<Profile xmlns='urn:m'>
+<fieldPermissions><editable>true</editable><field>A.x</field><readable>yes</readable></fieldPermissions>
+<fieldPermissions><field>A.y</field><visible>true</visible></fieldPermissions>
+<loginHours><mondayStart>8am</mondayStart><changed>2014-01-31</changed></loginHours>
+<userLicense>Platform</userLicense><custom>true</custom></Profile>";
+        assert_eq!(errors(doc), ~[
+            ~"/Profile/fieldPermissions[1]/readable: Invalid boolean 'yes'",
+            ~"/Profile/fieldPermissions[2]: Missing element 'editable'",
+            ~"/Profile/fieldPermissions[2]/visible: Unexpected element 'visible'",
+            ~"/Profile/loginHours/mondayStart: Invalid int '8am'",
+            ~"/Profile/loginHours/changed: Invalid dateTime '2014-01-31'",
+            ~"/Profile/userLicense: Value 'Platform' is not allowed",
+            ~"/Profile/custom: Unexpected element 'custom'"]);
+    }
+
+    #[test]
+    fn test_global_elements() {
+        let wsdl: Element = from_str(XSD).unwrap();
+        let schema = Schema::from_element(&wsdl).unwrap();
+        let doc: Element = from_str("<loginHours xmlns='urn:m'><mondayStart>1</mondayStart></loginHours>").unwrap();
+        assert_eq!(schema.validate(&doc), ~[]);
+        let doc: Element = from_str("<loginHours><mondayStart>1</mondayStart></loginHours>").unwrap();
+        assert_eq!(schema.validate(&doc)[0].msg, ~"No declaration for element 'loginHours'");
+
+        let bad: Element = from_str("<xsd:schema xmlns:xsd='http://www.w3.org/2001/XMLSchema'>\
+<xsd:element name='a' type='x:b'/></xsd:schema>").unwrap();
+        assert_eq!(Schema::from_element(&bad).err(), Some(~"Unbound prefix 'x'"));
+    }
+
+    #[test]
+    fn test_any_type() {
+        let xsd: Element = from_str("<xsd:schema xmlns:xsd='http://www.w3.org/2001/XMLSchema'>
+<xsd:element name='a'/><xsd:element name='b' type='xsd:anyType'/></xsd:schema>").unwrap();
+        let schema = Schema::from_element(&xsd).unwrap();
+        let doc: Element = from_str("<a x='1'>text<c><d/></c><!-- e --></a>").unwrap();
+        assert_eq!(schema.validate(&doc), ~[]);
+        let doc: Element = from_str("<b><a/>text</b>").unwrap();
+        assert_eq!(schema.validate(&doc), ~[]);
+    }
+}
+
+#[cfg(test)]
+mod sax_tests {
+    use xml::{ContentHandler, QName, BuildHandler, WriteHandler, parse_with_handler};
+    use xml::{Attribute, Error, Element, XmlWriter, SerializeOptions};
+    use std::io::Decorator;
+    use std::io::mem::MemWriter;
+    use std::str;
+
+    struct Recorder {
+        calls: ~[~str]
+    }
+
+    impl ContentHandler for Recorder {
+        fn start_document(&mut self) {
+            self.calls.push(~"start_document");
+        }
+
+        fn end_document(&mut self) {
+            self.calls.push(~"end_document");
+        }
+
+        fn start_prefix_mapping(&mut self, prefix: &str, ns: &str) {
+            self.calls.push(format!("start_prefix_mapping {} {}", prefix, ns));
+        }
+
+        fn end_prefix_mapping(&mut self, prefix: &str) {
+            self.calls.push(format!("end_prefix_mapping {}", prefix));
+        }
+
+        fn start_element(&mut self, name: &QName, attributes: &[Attribute]) {
+            let attrs: ~[~str] = attributes.iter().map(|a| a.name.to_str()).collect();
+            self.calls.push(format!("start_element {} {} {}", name.name,
+                                    name.ns.clone().unwrap_or(~""), attrs.connect(",")));
+        }
+
+        fn end_element(&mut self, name: &QName) {
+            self.calls.push(format!("end_element {}", name.name));
+        }
+
+        fn characters(&mut self, text: &str) {
+            self.calls.push(format!("characters {}", text));
+        }
+
+        fn comment(&mut self, text: &str) {
+            self.calls.push(format!("comment {}", text));
+        }
+
+        fn error(&mut self, err: &Error) {
+            self.calls.push(format!("error {}", err.msg));
+        }
+    }
+
+    // Forwards everything but comments to `next`
+    struct DropComments<H> {
+        next: H
+    }
+
+    impl<H: ContentHandler> ContentHandler for DropComments<H> {
+        fn end_document(&mut self) {
+            self.next.end_document();
+        }
+
+        fn start_prefix_mapping(&mut self, prefix: &str, ns: &str) {
+            self.next.start_prefix_mapping(prefix, ns);
+        }
+
+        fn start_element(&mut self, name: &QName, attributes: &[Attribute]) {
+            self.next.start_element(name, attributes);
+        }
+
+        fn end_element(&mut self, name: &QName) {
+            self.next.end_element(name);
+        }
+
+        fn characters(&mut self, text: &str) {
+            self.next.characters(text);
+        }
+
+        fn error(&mut self, err: &Error) {
+            self.next.error(err);
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let mut r = Recorder { calls: ~[] };
+        parse_with_handler("<a xmlns='urn:x' xmlns:p='urn:p' p:b='1'>t<!--c--><p:c/></a>", &mut r);
+        assert_eq!(r.calls, ~[
+            ~"start_document",
+            ~"start_prefix_mapping  urn:x",
+            ~"start_prefix_mapping p urn:p",
+            ~"start_element a urn:x b",
+            ~"characters t",
+            ~"comment c",
+            ~"start_element c urn:p ",
+            ~"end_element c",
+            ~"end_element a",
+            ~"end_prefix_mapping p",
+            ~"end_prefix_mapping ",
+            ~"end_document"]);
+    }
+
+    #[test]
+    fn test_error() {
+        let mut r = Recorder { calls: ~[] };
+        parse_with_handler("<a></b>", &mut r);
+        assert!(r.calls.iter().any(|c| c.starts_with("error ")));
+        assert_eq!(r.calls.last(), &~"end_document");
+    }
+
+    #[test]
+    fn test_build_pipeline() {
+        let mut f = DropComments { next: BuildHandler::new() };
+        parse_with_handler("<a xmlns:p='urn:p'><!--c--><p:b>x</p:b></a>", &mut f);
+        let built = f.next.unwrap().unwrap();
+        let expected: Element = from_str("<a xmlns:p='urn:p'><p:b>x</p:b></a>").unwrap();
+        assert_eq!(built, expected);
+
+        let mut b = BuildHandler::new();
+        parse_with_handler("<a>", &mut b);
+        assert!(b.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_write_pipeline() {
+        let writer = XmlWriter::with_options(MemWriter::new(), SerializeOptions::compact());
+        let mut f = DropComments { next: WriteHandler::new(writer) };
+        parse_with_handler("<a xmlns='urn:x'><!--c--><b k='v'>x</b></a>", &mut f);
+        let out = str::from_utf8_owned(f.next.unwrap().unwrap().inner());
+        assert_eq!(out, ~"<a xmlns='urn:x'><b k='v'>x</b></a>");
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use xml::{Parser, ElementBuilder, Root, Subtree};
+
+    // Returns the streamed elements as strings, followed by the root
+    fn stream(data: &str, e: &mut ElementBuilder) -> (~[~str], ~str) {
+        let mut p = Parser::new();
+        let mut streamed = ~[];
+        let mut root = None;
+        p.parse_str(data, |event| {
+            match e.push_event(event.unwrap()) {
+                Ok(Some(Root(elem))) => root = Some(elem.to_str()),
+                Ok(Some(Subtree(elem))) => streamed.push(elem.to_str()),
+                _ => ()
+            }
+        });
+        (streamed, root.unwrap())
+    }
+
+    #[test]
+    fn test_stream_depth() {
+        let mut e = ElementBuilder::new();
+        e.set_stream_depth(Some(1));
+        let (streamed, root) = stream("<r><a><b/></a>t<c/></r>", &mut e);
+        assert_eq!(streamed, ~[~"<a><b/></a>", ~"<c/>"]);
+        assert_eq!(root, ~"<r/>");
+    }
+
+    #[test]
+    fn test_stream_drops_text() {
+        let mut e = ElementBuilder::new();
+        e.set_stream_names(~[~"a"]);
+        let mut p = Parser::new();
+        let mut root = None;
+        p.parse_str("<r> <a/> x <!--c--> <b/> <a/> <?p?> </r>", |event| {
+            match e.push_event(event.unwrap()) {
+                Ok(Some(Root(elem))) => root = Some(elem),
+                _ => ()
+            }
+        });
+        // Only the element between the streamed ones is kept
+        let root = root.unwrap();
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.to_str(), ~"<r><b/></r>");
+    }
+
+    #[test]
+    fn test_stream_names() {
+        let mut e = ElementBuilder::new();
+        e.set_stream_names(~[~"p", ~"q"]);
+        let (streamed, root) = stream("<p><x><p><q/></p></x><y><q/></y><x/></p>", &mut e);
+        assert_eq!(streamed, ~[~"<p><q/></p>", ~"<q/>"]);
+        assert_eq!(root, ~"<p><x/><y/><x/></p>");
+    }
+}
+
+#[cfg(test)]
+mod reader_tests {
+    use xml::{Parser, Reader, Event, Text, Borrowed, Owned, CharactersRef, Atoms};
+
+    // The events of `data` up to the first error, as reported by `Parser`
+    fn parser_events(data: &str) -> ~[Result<Event, ~str>] {
+        let mut p = Parser::new();
+        let mut events = ~[];
+        p.parse_str(data, |event| events.push(event.map_err(|e| e.msg)));
+        p.finish(|event| events.push(event.map_err(|e| e.msg)));
+        events
+    }
+
+    fn reader_events(data: &str) -> ~[Result<Event, ~str>] {
+        let mut atoms = Atoms::new();
+        Reader::new(data).map(|event| {
+            event.map(|e| e.into_event(&mut atoms)).map_err(|e| e.msg)
+        }).collect()
+    }
+
+    fn text<'a>(data: &'a str) -> Text<'a> {
+        match Reader::new(data).nth(1) {
+            Some(Ok(CharactersRef(text))) => text,
+            _ => fail!("No character data")
+        }
+    }
+
+    #[test]
+    fn test_same_as_parser() {
+        let docs = [
+            "<?xml version='1.0'?><!DOCTYPE a [<!ENTITY x 'y>'>]>\n\
+<a xmlns='urn:a' xmlns:p='urn:p' p:b='&amp;1' c=\"2\">t&lt;<p:c/><!--c-->\
+<![CDATA[<d>]]><?pi x?>\u00e9</a>\n",
+            "<a>x</b>",
+            "<a b='1' b='2'/>",
+            "<a b='1'c='2'/>",
+            "<a>",
+            "<a/><b/>",
+            "<a>]]></a>",
+            "<a><p:b/></a>",
+            "<a x:y='1' xmlns:x='urn:x' xmlns:z='urn:x' z:y='2'/>",
+            "text<a/>",
+            "<a>&bogus</a>",
+            "<a b='<'/>",
+            "<a><!-- x -- y --></a>",
+            "<a><?p \n x ?><?XML b?></a>",
+            "<a></a >",
+            "<a b=' x\r\n\ty\n' c='&#9;&#10;&amp;'>x\r\ny\rz<![CDATA[\r\n]]><!--\r--><?p\r\nd\r?></a>",
+            ""
+        ];
+        for doc in docs.iter() {
+            assert_eq!(reader_events(*doc), parser_events(*doc));
+        }
+    }
+
+    #[test]
+    fn test_borrowed() {
+        assert_eq!(text("<a>plain</a>"), Borrowed("plain"));
+        assert_eq!(text("<a>&lt;b&gt;</a>"), Owned(~"<b>"));
+        assert_eq!(text("<a>x\r\ny</a>"), Owned(~"x\ny"));
+    }
+
+    #[test]
+    fn test_error_position() {
+        let err = Reader::new("<a>\n  <b></c></a>").last().unwrap().unwrap_err();
+        assert_eq!((err.line, err.col), (2, 9));
+        assert_eq!(err.msg, ~"Expected '</b>', found '</c>'");
+        let err = Reader::new("<a>\r\n\r  <b></c></a>").last().unwrap().unwrap_err();
+        assert_eq!((err.line, err.col), (3, 9));
+    }
+}
+
+#[cfg(test)]
+mod atom_tests {
+    use xml::{Atom, Atoms, Parser, ElementBuilder, Root};
+
+    #[test]
+    fn test_intern() {
+        let mut atoms = Atoms::new();
+        let a = atoms.intern("a");
+        assert_eq!(atoms.intern("a"), a);
+        assert!(atoms.intern("b") != a);
+        assert_eq!(atoms.len(), 2);
+        assert_eq!(a, Atom::new("a"));
+        assert_eq!(a.as_slice(), "a");
+        assert_eq!(a.to_str(), ~"a");
+    }
+
+    #[test]
+    fn test_parser_atoms() {
+        let mut p = Parser::new();
+        let mut e = ElementBuilder::new();
+        let b = p.atoms().intern("b");
+        let mut result = None;
+        p.parse_str("<a x='1'><b x='2'/><b/></a>", |event| {
+            match e.push_event(event.unwrap()) {
+                Ok(Some(Root(elem))) => result = Some(elem),
+                _ => ()
+            }
+        });
+        let root = result.unwrap();
+        let children = root.children_with_name("b");
+        assert_eq!(children.len(), 2);
+        assert!(children.iter().all(|c| c.name == b));
+        assert_eq!(p.atoms().len(), 3);
+    }
+}
+
+#[cfg(test)]
+mod lossless_tests {
+    use xml::{parse_lossless, Element, CommentNode, SerializeOptions};
+
+    fn doc() -> ~str {
+        let lines = ~["<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                      "<!DOCTYPE Profile>\n",
+                      "<!-- generated -->\n",
+                      "<Profile xmlns=\"urn:p\"  a = 'x' >\n",
+                      "    <fieldPermissions>\n",
+                      "        <editable>true</editable>\n",
+                      "        <field>A&amp;B &#65;</field>\n",
+                      "    </fieldPermissions>\n",
+                      "    <empty/>\n",
+                      "    <closed></closed>\n",
+                      "</Profile>\n"];
+        lines.concat()
+    }
+
+    fn child<'a>(elem: &'a mut Element, i: uint) -> &'a mut Element {
+        match elem.children[i] {
+            Element(ref mut child) => child,
+            _ => fail!("Not an element")
+        }
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let root = parse_lossless(doc()).unwrap();
+        assert_eq!(root.serialize_lossless(&SerializeOptions::pretty()), Ok(doc()));
+    }
+
+    #[test]
+    fn test_edit_text() {
+        let mut root = parse_lossless(doc()).unwrap();
+        child(child(&mut root, 1), 1).set_text("false");
+        assert_eq!(root.serialize_lossless(&SerializeOptions::pretty()),
+                   Ok(doc().replace("<editable>true<", "<editable>false<")));
+    }
+
+    #[test]
+    fn test_edit_attribute() {
+        let mut root = parse_lossless(doc()).unwrap();
+        root.set_attribute("a", "y");
+        assert_eq!(root.serialize_lossless(&SerializeOptions::pretty()),
+                   Ok(doc().replace("<Profile xmlns=\"urn:p\"  a = 'x' >", "<Profile xmlns=\"urn:p\" a=\"y\">")));
+    }
+
+    #[test]
+    fn test_add_child() {
+        let mut root = parse_lossless(doc()).unwrap();
+        child(&mut root, 3).append_child(Element(Element::new("x").ns("urn:p")));
+        assert_eq!(root.serialize_lossless(&SerializeOptions::pretty()),
+                   Ok(doc().replace("<empty/>", "<empty><x/></empty>")));
+    }
+
+    #[test]
+    fn test_insert_indented() {
+        let mut root = parse_lossless("<a>\n  <b/>\n  <c/>\n</a>\n").unwrap();
+        root.insert_child(2, Element(Element::new("x")));
+        root.append_child(Element(Element::new("y").text("1")));
+        root.insert_child(0, CommentNode(~" first "));
+        assert_eq!(root.serialize_lossless(&SerializeOptions::pretty()),
+                   Ok(~"<a>\n  <!-- first -->\n  <b/>\n  <x/>\n  <c/>\n  <y>1</y>\n</a>\n"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse_lossless("<a><b></a>").unwrap_err();
+        assert_eq!(err.msg, ~"Expected '</b>', found '</a>'");
+        assert!(parse_lossless("").is_err());
+    }
+
+    #[test]
+    fn test_without_trivia() {
+        let elem = Element::new("a").text("b");
+        assert_eq!(elem.serialize_lossless(&SerializeOptions::compact()), Ok(~"<a>b</a>"));
+    }
+}
+
+#[cfg(test)]
+mod limit_tests {
+    use xml::{Parser, Reader, Element, ParserConfig, ErrorKind, DepthLimitExceeded, AttributeLimitExceeded};
+    use xml::parse_with_config;
+    use xml::{NameLengthLimitExceeded, TextSizeLimitExceeded, DocumentSizeLimitExceeded};
+    use xml::EntityExpansionLimitExceeded;
+
+    // The kinds and messages of all errors reported for the parts of a document
+    fn errors(parts: &[&str], config: ParserConfig, recover: bool) -> ~[(ErrorKind, ~str)] {
+        let mut p = Parser::new();
+        p.set_config(config);
+        p.set_recover(recover);
+        let mut errors = ~[];
+        for part in parts.iter() {
+            p.parse_str(*part, |event| {
+                match event {
+                    Err(e) => errors.push((e.kind, e.msg)),
+                    _ => ()
+                }
+            });
+        }
+        p.finish(|event| {
+            match event {
+                Err(e) => errors.push((e.kind, e.msg)),
+                _ => ()
+            }
+        });
+        errors
+    }
+
+    // The errors reported for `data`, which must be the same for `Reader`
+    fn error(data: &str, config: ParserConfig) -> ~[(ErrorKind, ~str)] {
+        let read: ~[(ErrorKind, ~str)] = Reader::with_config(data, config.clone()).filter_map(|event| {
+            match event {
+                Err(e) => Some((e.kind, e.msg)),
+                _ => None
+            }
+        }).collect();
+        let parsed = errors([data], config, false);
+        assert_eq!(read, parsed);
+        parsed
+    }
+
+    static DOC: &'static str = "<a b='&amp;'><c d='1'>&lt;text&gt;<!--x--></c></a>";
+
+    #[test]
+    fn test_within_limits() {
+        assert_eq!(error(DOC, ParserConfig::new()), ~[]);
+        assert_eq!(error(DOC, ParserConfig::untrusted()), ~[]);
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut config = ParserConfig::new();
+        config.max_depth = Some(2);
+        assert_eq!(error("<a><b/></a>", config.clone()), ~[]);
+        assert_eq!(error("<a><b><c/></b></a>", config),
+                   ~[(DepthLimitExceeded, ~"Elements nested deeper than 2 levels")]);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut config = ParserConfig::new();
+        config.max_attributes = Some(2);
+        assert_eq!(error("<a x='1' y='2'/>", config.clone()), ~[]);
+        assert_eq!(error("<a x='1' y='2' z='3'/>", config),
+                   ~[(AttributeLimitExceeded, ~"Element 'a' has more than 2 attributes")]);
+    }
+
+    #[test]
+    fn test_name_length() {
+        let mut config = ParserConfig::new();
+        config.max_name_length = Some(3);
+        assert_eq!(error("<abc xyz='1'/>", config.clone()), ~[]);
+        assert_eq!(error("<abcd/>", config.clone()),
+                   ~[(NameLengthLimitExceeded, ~"Name longer than 3 bytes")]);
+        assert_eq!(error("<a wxyz='1'/>", config),
+                   ~[(NameLengthLimitExceeded, ~"Name longer than 3 bytes")]);
+    }
+
+    #[test]
+    fn test_text_size() {
+        let mut config = ParserConfig::new();
+        config.max_text_size = Some(3);
+        assert_eq!(error("<a b='xyz'>xyz<!--xyz--></a>", config.clone()), ~[]);
+        for doc in ["<a>wxyz</a>", "<a b='wxyz'/>", "<a><!--wxyz--></a>", "<a><![CDATA[wxyz]]></a>"].iter() {
+            assert_eq!(error(*doc, config.clone()),
+                       ~[(TextSizeLimitExceeded, ~"Text longer than 3 bytes")]);
+        }
+    }
+
+    #[test]
+    fn test_document_size() {
+        let mut config = ParserConfig::new();
+        config.max_document_size = Some(10);
+        assert_eq!(error("<a>xyz</a>", config.clone()), ~[]);
+        let expected = ~[(DocumentSizeLimitExceeded, ~"Document larger than 10 bytes")];
+        assert_eq!(error("<a>wxyz</a>", config.clone()), expected);
+        assert_eq!(errors(["<a>", "wxyz</a>"], config, false), expected);
+    }
+
+    #[test]
+    fn test_entity_expansions() {
+        let mut config = ParserConfig::new();
+        config.max_entity_expansions = Some(2);
+        assert_eq!(error("<a b='&amp;'>&lt;</a>", config.clone()), ~[]);
+        assert_eq!(error("<a b='&amp;'>&lt;&#65;</a>", config),
+                   ~[(EntityExpansionLimitExceeded, ~"More than 2 references expanded")]);
+    }
+
+    #[test]
+    fn test_parse_with_config() {
+        let nested = "<a>".repeat(256) + "</a>".repeat(256);
+        assert!(parse_with_config(nested, ParserConfig::untrusted()).is_ok());
+        let nested = "<a>".repeat(257) + "</a>".repeat(257);
+        let err = parse_with_config(nested, ParserConfig::untrusted()).unwrap_err();
+        assert_eq!(err.kind, DepthLimitExceeded);
+        // `from_str` does not limit anything
+        let elem: Option<Element> = from_str(nested);
+        assert!(elem.is_some());
+
+        let attributes: ~[~str] = range(0, 257).map(|i| format!("a{}='1'", i)).collect();
+        let err = parse_with_config(format!("<a {}/>", attributes.connect(" ")), ParserConfig::untrusted());
+        assert_eq!(err.unwrap_err().kind, AttributeLimitExceeded);
+        let err = parse_with_config(format!("<{}/>", "a".repeat(1025)), ParserConfig::untrusted());
+        assert_eq!(err.unwrap_err().kind, NameLengthLimitExceeded);
+        assert_eq!(parse_with_config("<a>", ParserConfig::untrusted()).unwrap_err().msg,
+                   ~"Unclosed element 'a'");
+    }
+
+    #[test]
+    fn test_not_recovered() {
+        let mut config = ParserConfig::new();
+        config.max_depth = Some(2);
+        assert_eq!(errors(["<a><b><c/></b><b><c/></b></a>"], config, true),
+                   ~[(DepthLimitExceeded, ~"Elements nested deeper than 2 levels")]);
+    }
+}
+
+#[cfg(test)]
+mod entity_tests {
+    use xml::{Parser, Reader, Element, Error, parse_recovering, parse_lossless};
+
+    static DOC: &'static str = "<a>\n  <b>&foo;</b>\n</a>";
+
+    fn parse_errors(data: &str) -> ~[Error] {
+        let mut p = Parser::new();
+        let mut errors = ~[];
+        p.parse_str(data, |event| {
+            match event {
+                Err(e) => errors.push(e),
+                _ => ()
+            }
+        });
+        p.finish(|event| {
+            match event {
+                Err(e) => errors.push(e),
+                _ => ()
+            }
+        });
+        errors
+    }
+
+    #[test]
+    fn test_parser() {
+        let errors = parse_errors(DOC);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].msg, ~"Unknown entity '&foo;'");
+        assert_eq!((errors[0].line, errors[0].col), (2u, 6u));
+
+        let errors = parse_errors("<a b='&foo;'/>");
+        assert_eq!(errors[0].msg, ~"Unknown entity '&foo;'");
+    }
+
+    #[test]
+    fn test_reader() {
+        let errors: ~[Error] = Reader::new(DOC).filter_map(|event| event.err()).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].msg, ~"Unknown entity '&foo;'");
+        assert_eq!((errors[0].line, errors[0].col), (2u, 6u));
+
+        let elem: Option<Element> = from_str(DOC);
+        assert!(elem.is_none());
+    }
+
+    #[test]
+    fn test_lossless() {
+        match parse_lossless(DOC) {
+            Err(e) => assert_eq!(e.msg, ~"Unknown entity '&foo;'"),
+            Ok(_) => fail!("Unknown entity accepted")
+        }
+    }
+
+    #[test]
+    fn test_recovering() {
+        let (root, errors) = parse_recovering(DOC);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].msg, ~"Unknown entity '&foo;'");
+        let root = root.unwrap();
+        assert_eq!(root.child_with_name("b").unwrap().content_str(), ~"");
+    }
 }
@@ -0,0 +1,302 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{XML, Element};
+use std::vec;
+
+/// Depth-first iterator over the descendant nodes of an `Element`, in document order
+pub struct Descendants<'a> {
+    priv stack: ~[vec::VecIterator<'a, XML>]
+}
+
+impl<'a> Iterator<&'a XML> for Descendants<'a> {
+    fn next(&mut self) -> Option<&'a XML> {
+        loop {
+            let l = self.stack.len();
+            if l == 0 {
+                return None;
+            }
+            let next = self.stack[l-1].next();
+            match next {
+                Some(node) => {
+                    match *node {
+                        Element(ref elem) => self.stack.push(elem.children.iter()),
+                        _ => ()
+                    }
+                    return Some(node);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Breadth-first iterator over the descendant nodes of an `Element`, level by level
+pub struct BreadthFirst<'a> {
+    priv queue: ~[&'a XML],
+    priv pos: uint
+}
+
+impl<'a> Iterator<&'a XML> for BreadthFirst<'a> {
+    fn next(&mut self) -> Option<&'a XML> {
+        if self.pos >= self.queue.len() {
+            return None;
+        }
+        let node = self.queue[self.pos];
+        self.pos += 1;
+        match *node {
+            Element(ref elem) => {
+                // Drop the visited part of the queue once it dominates
+                if self.pos > 64 && self.pos * 2 > self.queue.len() {
+                    self.queue = self.queue.slice_from(self.pos).to_owned();
+                    self.pos = 0;
+                }
+                for child in elem.children.iter() {
+                    self.queue.push(child);
+                }
+            }
+            _ => ()
+        }
+        Some(node)
+    }
+}
+
+/// Depth-first iterator over the descendant nodes of an `Element`, yielding each node
+/// together with its path: the `Element`s enclosing it, starting with the element
+/// the walk started from.
+pub struct Walk<'a> {
+    priv path: ~[&'a Element],
+    priv stack: ~[vec::VecIterator<'a, XML>]
+}
+
+impl<'a> Iterator<(~[&'a Element], &'a XML)> for Walk<'a> {
+    fn next(&mut self) -> Option<(~[&'a Element], &'a XML)> {
+        loop {
+            let l = self.stack.len();
+            if l == 0 {
+                return None;
+            }
+            let next = self.stack[l-1].next();
+            match next {
+                Some(node) => {
+                    let path = self.path.clone();
+                    match *node {
+                        Element(ref elem) => {
+                            self.path.push(elem);
+                            self.stack.push(elem.children.iter());
+                        }
+                        _ => ()
+                    }
+                    return Some((path, node));
+                }
+                None => {
+                    self.stack.pop();
+                    self.path.pop();
+                }
+            }
+        }
+    }
+}
+
+/// A position in a tree of `Element`s, which can be moved to parents, children and siblings.
+/// Moves which are not possible leave the cursor unchanged and return `false`.
+pub struct Cursor<'a> {
+    priv root: &'a Element,
+    // The child indices leading from the root to the current node
+    priv path: ~[uint]
+}
+
+impl<'a> Cursor<'a> {
+    /// Returns a new `Cursor` positioned at `root`
+    pub fn new(root: &'a Element) -> Cursor<'a> {
+        Cursor { root: root, path: ~[] }
+    }
+
+    // Returns the element containing the current node, `None` at the root
+    fn parent_element(&self) -> Option<&'a Element> {
+        if self.path.is_empty() {
+            return None;
+        }
+        let mut elem = self.root;
+        for &i in self.path.slice_to(self.path.len() - 1).iter() {
+            match elem.children[i] {
+                Element(ref child) => elem = child,
+                _ => fail!("Cursor path leads through a node which is not an element")
+            }
+        }
+        Some(elem)
+    }
+
+    /// Returns the current node, or `None` if the cursor is at the root `Element`
+    pub fn node(&self) -> Option<&'a XML> {
+        match self.parent_element() {
+            Some(parent) => Some(&parent.children[*self.path.last()]),
+            None => None
+        }
+    }
+
+    /// Returns the current node if it is an `Element`
+    pub fn element(&self) -> Option<&'a Element> {
+        match self.node() {
+            None => Some(self.root),
+            Some(&Element(ref elem)) => Some(elem),
+            Some(_) => None
+        }
+    }
+
+    /// Returns the number of `Element`s enclosing the current node
+    pub fn depth(&self) -> uint {
+        self.path.len()
+    }
+
+    /// Returns the `Element`s enclosing the current node, nearest first
+    pub fn ancestors(&self) -> ~[&'a Element] {
+        let mut ancestors = ~[self.root];
+        let mut elem = self.root;
+        for &i in self.path.iter() {
+            match elem.children[i] {
+                Element(ref child) => {
+                    ancestors.push(child);
+                    elem = child;
+                }
+                _ => ()
+            }
+        }
+        // The current node is not its own ancestor
+        if self.element().is_some() {
+            ancestors.pop();
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    /// Moves to the parent of the current node
+    pub fn parent(&mut self) -> bool {
+        self.path.pop_opt().is_some()
+    }
+
+    /// Moves to the first child of the current node
+    pub fn first_child(&mut self) -> bool {
+        match self.element() {
+            Some(elem) if !elem.children.is_empty() => {
+                self.path.push(0);
+                true
+            }
+            _ => false
+        }
+    }
+
+    /// Moves to the last child of the current node
+    pub fn last_child(&mut self) -> bool {
+        match self.element() {
+            Some(elem) if !elem.children.is_empty() => {
+                self.path.push(elem.children.len() - 1);
+                true
+            }
+            _ => false
+        }
+    }
+
+    /// Moves to the next sibling of the current node
+    pub fn next_sibling(&mut self) -> bool {
+        match self.parent_element() {
+            Some(parent) if *self.path.last() + 1 < parent.children.len() => {
+                let l = self.path.len();
+                self.path[l-1] += 1;
+                true
+            }
+            _ => false
+        }
+    }
+
+    /// Moves to the previous sibling of the current node
+    pub fn prev_sibling(&mut self) -> bool {
+        match self.parent_element() {
+            Some(_) if *self.path.last() > 0 => {
+                let l = self.path.len();
+                self.path[l-1] -= 1;
+                true
+            }
+            _ => false
+        }
+    }
+}
+
+#[deriving(Clone,Eq)]
+/// Tells `Element::accept` how to continue after entering an `Element`
+pub enum VisitAction {
+    /// Visit the children of the element
+    VisitChildren,
+    /// Skip the children of the element, continuing with its next sibling
+    SkipChildren,
+    /// End the traversal
+    StopVisit
+}
+
+/// Callbacks for a depth-first traversal of an `Element` tree with `Element::accept`
+pub trait Visitor {
+    /// Called before the children of `elem` are visited
+    fn enter(&mut self, _elem: &Element) -> VisitAction {
+        VisitChildren
+    }
+
+    /// Called after the children of `elem` have been visited or skipped
+    fn leave(&mut self, _elem: &Element) {
+    }
+
+    /// Called for each node which is not an `Element`
+    fn visit_node(&mut self, _node: &XML) {
+    }
+}
+
+// Visits `elem` and its subtree, returning false if the traversal was stopped
+fn visit<V: Visitor>(elem: &Element, v: &mut V) -> bool {
+    match v.enter(elem) {
+        StopVisit => return false,
+        SkipChildren => (),
+        VisitChildren => {
+            for child in elem.children.iter() {
+                match *child {
+                    Element(ref child) => if !visit(child, v) { return false; },
+                    ref node => v.visit_node(node)
+                }
+            }
+        }
+    }
+    v.leave(elem);
+    true
+}
+
+impl Element {
+    /// Returns a depth-first iterator over all descendant nodes, in document order
+    pub fn descendants<'a>(&'a self) -> Descendants<'a> {
+        Descendants { stack: ~[self.children.iter()] }
+    }
+
+    /// Returns a breadth-first iterator over all descendant nodes
+    pub fn descendants_breadth_first<'a>(&'a self) -> BreadthFirst<'a> {
+        BreadthFirst { queue: self.children.iter().collect(), pos: 0 }
+    }
+
+    /// Returns a depth-first iterator over all descendant nodes together with the
+    /// `Element`s enclosing them
+    pub fn walk<'a>(&'a self) -> Walk<'a> {
+        Walk { path: ~[self], stack: ~[self.children.iter()] }
+    }
+
+    /// Returns a `Cursor` positioned at this element
+    pub fn cursor<'a>(&'a self) -> Cursor<'a> {
+        Cursor::new(self)
+    }
+
+    /// Traverses the element and its descendants depth-first, calling `v`.
+    /// Returns false if the traversal was ended by `StopVisit`.
+    pub fn accept<V: Visitor>(&self, v: &mut V) -> bool {
+        visit(self, v)
+    }
+}
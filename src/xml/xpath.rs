@@ -0,0 +1,1403 @@
+// RustyXML
+// Copyright (c) 2013 Florian Zeitz
+//
+// This project is MIT licensed.
+// Please see the COPYING file for more information.
+
+use super::{Element, Attribute, CharacterNode, CDATANode, CommentNode, PINode, PI};
+use super::base::{is_name_start_char, is_name_char, is_space};
+use super::namespace::{Scope, XML_NS};
+use std::ascii::StrAsciiExt;
+use std::hashmap::HashMap;
+use std::ptr;
+use std::uint;
+
+#[deriving(Clone,Eq)]
+/// A node of the XPath data model
+pub enum XPathNode<'a> {
+    /// The root node, the parent of the document element
+    XPathRoot(&'a Element),
+    /// An element node
+    XPathElement(&'a Element),
+    /// An attribute node
+    XPathAttribute(&'a Attribute),
+    /// A text node, holding all adjacent character data and CDATA
+    XPathText(~str),
+    /// A comment node
+    XPathComment(&'a str),
+    /// A processing instruction node
+    XPathPI(&'a PI),
+    /// A namespace node, holding the prefix and namespace. The default namespace has
+    /// an empty prefix.
+    XPathNamespace(~str, ~str)
+}
+
+impl<'a> XPathNode<'a> {
+    /// Returns the string-value of the node, as defined by XPath
+    pub fn string_value(&self) -> ~str {
+        match *self {
+            XPathRoot(elem) | XPathElement(elem) => elem.content_str(),
+            XPathAttribute(attr) => attr.value.clone(),
+            XPathText(ref text) => text.clone(),
+            XPathComment(text) => text.to_owned(),
+            XPathPI(pi) => pi.data.clone(),
+            XPathNamespace(_, ref ns) => ns.clone()
+        }
+    }
+}
+
+#[deriving(Clone,Eq)]
+/// The result of evaluating an XPath expression
+pub enum XPathValue<'a> {
+    /// A set of nodes, in document order
+    NodeSet(~[XPathNode<'a>]),
+    /// A string
+    StringValue(~str),
+    /// A number
+    NumberValue(f64),
+    /// A boolean
+    BooleanValue(bool)
+}
+
+impl<'a> XPathValue<'a> {
+    /// Converts the value to a string, as the XPath `string()` function does
+    pub fn to_string(&self) -> ~str {
+        match *self {
+            NodeSet(ref nodes) => nodes.head_opt().map_default(~"", |n| n.string_value()),
+            StringValue(ref s) => s.clone(),
+            NumberValue(n) => number_to_str(n),
+            BooleanValue(b) => b.to_str()
+        }
+    }
+
+    /// Converts the value to a number, as the XPath `number()` function does
+    pub fn to_number(&self) -> f64 {
+        match *self {
+            NumberValue(n) => n,
+            BooleanValue(b) => if b { 1.0 } else { 0.0 },
+            _ => str_to_number(self.to_string())
+        }
+    }
+
+    /// Converts the value to a boolean, as the XPath `boolean()` function does
+    pub fn to_bool(&self) -> bool {
+        match *self {
+            NodeSet(ref nodes) => !nodes.is_empty(),
+            StringValue(ref s) => !s.is_empty(),
+            NumberValue(n) => n != 0.0 && !n.is_nan(),
+            BooleanValue(b) => b
+        }
+    }
+}
+
+/// Namespace bindings and variables used when evaluating an `XPath`
+pub struct XPathContext<'a> {
+    priv namespaces: HashMap<~str, ~str>,
+    priv variables: HashMap<~str, XPathValue<'a>>
+}
+
+impl<'a> XPathContext<'a> {
+    /// Returns a new `XPathContext` without bindings.
+    /// Only the `xml` prefix is bound.
+    pub fn new() -> XPathContext<'a> {
+        let mut namespaces = HashMap::new();
+        namespaces.insert(~"xml", XML_NS.to_owned());
+        XPathContext { namespaces: namespaces, variables: HashMap::new() }
+    }
+
+    /// Binds `prefix` to the namespace `ns` for use in name tests
+    pub fn bind_namespace(&mut self, prefix: &str, ns: &str) {
+        self.namespaces.insert(prefix.to_owned(), ns.to_owned());
+    }
+
+    /// Sets the variable `$name` to `value`
+    pub fn set_variable(&mut self, name: &str, value: XPathValue<'a>) {
+        self.variables.insert(name.to_owned(), value);
+    }
+}
+
+/// A compiled XPath 1.0 expression, which can be evaluated against many documents
+pub struct XPath {
+    priv expr: Expr
+}
+
+impl XPath {
+    /// Compiles `expr`.
+    /// Returns `Err("message")` if it is not a valid XPath expression.
+    pub fn compile(expr: &str) -> Result<XPath, ~str> {
+        let tokens = check!(tokenize(expr));
+        let mut p = ExprParser { tokens: tokens, pos: 0 };
+        let expr = check!(p.parse_expr());
+        if p.pos < p.tokens.len() {
+            return Err(~"Unexpected token after end of expression");
+        }
+        Ok(XPath { expr: expr })
+    }
+
+    /// Evaluates the expression with the root node of the document `root` as context node.
+    pub fn evaluate<'a>(&self, root: &'a Element, ctx: &XPathContext<'a>)
+      -> Result<XPathValue<'a>, ~str> {
+        let tree = Tree::new(root);
+        let e = Evaluator { tree: &tree, ctx: ctx };
+        match e.eval(&self.expr, 0, 1, 1) {
+            Ok(Nodes(nodes)) => Ok(NodeSet(nodes.iter().map(|&i| tree.nodes[i].node.clone()).collect())),
+            Ok(Str(s)) => Ok(StringValue(s)),
+            Ok(Num(n)) => Ok(NumberValue(n)),
+            Ok(Bool(b)) => Ok(BooleanValue(b)),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Evaluates the expression like `evaluate`, requiring the result to be a node-set.
+    pub fn select<'a>(&self, root: &'a Element, ctx: &XPathContext<'a>)
+      -> Result<~[XPathNode<'a>], ~str> {
+        match check!(self.evaluate(root, ctx)) {
+            NodeSet(nodes) => Ok(nodes),
+            _ => Err(~"Expression does not return a node-set")
+        }
+    }
+}
+
+impl Element {
+    /// Compiles and evaluates the XPath expression `expr`, with the root node of this
+    /// element's document as context node and no namespace bindings.
+    pub fn xpath<'a>(&'a self, expr: &str) -> Result<XPathValue<'a>, ~str> {
+        match XPath::compile(expr) {
+            Ok(xpath) => xpath.evaluate(self, &XPathContext::new()),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+// Formats a number as the XPath `string()` function does
+fn number_to_str(n: f64) -> ~str {
+    if n.is_nan() {
+        ~"NaN"
+    } else if n.is_infinite() {
+        if n > 0.0 { ~"Infinity" } else { ~"-Infinity" }
+    } else if n == n.floor() && n.abs() < 1e15 {
+        (n as i64).to_str()
+    } else {
+        n.to_str()
+    }
+}
+
+// Parses a number as the XPath `number()` function does
+fn str_to_number(s: &str) -> f64 {
+    let s = s.trim_chars(&is_space);
+    let (negative, digits) = if s.starts_with("-") { (true, s.slice_from(1)) } else { (false, s) };
+
+    let mut n = 0.0;
+    let mut scale = 1.0;
+    let mut fraction = false;
+    let mut seen_digit = false;
+    for c in digits.chars() {
+        match c {
+            '.' if !fraction => fraction = true,
+            '0'..'9' => {
+                let d = c.to_digit(10).unwrap() as f64;
+                if fraction {
+                    scale /= 10.0;
+                    n += d * scale;
+                } else {
+                    n = n * 10.0 + d;
+                }
+                seen_digit = true;
+            }
+            _ => return 0.0 / 0.0
+        }
+    }
+    if !seen_digit {
+        0.0 / 0.0
+    } else if negative {
+        -n
+    } else {
+        n
+    }
+}
+
+// Lexer
+
+#[deriving(Clone,Eq)]
+enum Token {
+    TSlash,
+    TDoubleSlash,
+    TLParen,
+    TRParen,
+    TLBracket,
+    TRBracket,
+    TDot,
+    TDotDot,
+    TAt,
+    TComma,
+    TColonColon,
+    TPipe,
+    TPlus,
+    TMinus,
+    TEquals,
+    TNotEquals,
+    TLess,
+    TLessEquals,
+    TGreater,
+    TGreaterEquals,
+    TMultiply,
+    TAnd,
+    TOr,
+    TMod,
+    TDiv,
+    TLiteral(~str),
+    TNumber(f64),
+    TVariable(~str),
+    // A name test as prefix and local name, `None` standing for '*'
+    TNameTest(Option<~str>, Option<~str>),
+    TNodeType(~str),
+    TFunctionName(~str),
+    TAxisName(~str)
+}
+
+// Returns true if a token following `prev` is to be read as an operator,
+// following the lexical disambiguation rules of XPath
+fn operator_expected(prev: Option<&Token>) -> bool {
+    match prev {
+        None => false,
+        Some(t) => match *t {
+            TAt | TColonColon | TLParen | TLBracket | TComma | TSlash | TDoubleSlash |
+            TPipe | TPlus | TMinus | TEquals | TNotEquals | TLess | TLessEquals | TGreater |
+            TGreaterEquals | TMultiply | TAnd | TOr | TMod | TDiv => false,
+            _ => true
+        }
+    }
+}
+
+#[inline]
+fn is_ncname_start(c: char) -> bool {
+    c != ':' && is_name_start_char(c)
+}
+
+#[inline]
+fn is_ncname_char(c: char) -> bool {
+    c != ':' && is_name_char(c)
+}
+
+// Reads an NCName starting at `pos`
+fn read_ncname(chars: &[char], pos: &mut uint) -> ~str {
+    let mut name = ~"";
+    while *pos < chars.len() && is_ncname_char(chars[*pos]) {
+        name.push_char(chars[*pos]);
+        *pos += 1;
+    }
+    name
+}
+
+// Returns the index of the next non-whitespace character at or after `pos`
+fn skip_space(chars: &[char], pos: uint) -> uint {
+    let mut pos = pos;
+    while pos < chars.len() && is_space(chars[pos]) {
+        pos += 1;
+    }
+    pos
+}
+
+fn tokenize(expr: &str) -> Result<~[Token], ~str> {
+    let chars: ~[char] = expr.chars().collect();
+    let mut tokens: ~[Token] = ~[];
+    let mut pos = 0;
+
+    loop {
+        pos = skip_space(chars, pos);
+        if pos >= chars.len() {
+            break;
+        }
+        let c = chars[pos];
+        let next = if pos + 1 < chars.len() { Some(chars[pos + 1]) } else { None };
+        let operator = operator_expected(tokens.last_opt());
+
+        let token = match c {
+            '(' => { pos += 1; TLParen }
+            ')' => { pos += 1; TRParen }
+            '[' => { pos += 1; TLBracket }
+            ']' => { pos += 1; TRBracket }
+            '@' => { pos += 1; TAt }
+            ',' => { pos += 1; TComma }
+            '|' => { pos += 1; TPipe }
+            '+' => { pos += 1; TPlus }
+            '-' => { pos += 1; TMinus }
+            '=' => { pos += 1; TEquals }
+            '/' if next == Some('/') => { pos += 2; TDoubleSlash }
+            '/' => { pos += 1; TSlash }
+            ':' if next == Some(':') => { pos += 2; TColonColon }
+            '!' if next == Some('=') => { pos += 2; TNotEquals }
+            '<' if next == Some('=') => { pos += 2; TLessEquals }
+            '<' => { pos += 1; TLess }
+            '>' if next == Some('=') => { pos += 2; TGreaterEquals }
+            '>' => { pos += 1; TGreater }
+            '*' if operator => { pos += 1; TMultiply }
+            '*' => { pos += 1; TNameTest(None, None) }
+            '.' if next == Some('.') => { pos += 2; TDotDot }
+            '.' if !next.map_default(false, |n| n.is_digit()) => { pos += 1; TDot }
+            '0'..'9' | '.' => {
+                let start = pos;
+                while pos < chars.len() && chars[pos].is_digit() {
+                    pos += 1;
+                }
+                if pos < chars.len() && chars[pos] == '.' {
+                    pos += 1;
+                    while pos < chars.len() && chars[pos].is_digit() {
+                        pos += 1;
+                    }
+                }
+                let s: ~str = chars.slice(start, pos).iter().map(|&c| c).collect();
+                TNumber(str_to_number(s))
+            }
+            '"' | '\'' => {
+                let start = pos + 1;
+                pos = start;
+                while pos < chars.len() && chars[pos] != c {
+                    pos += 1;
+                }
+                if pos >= chars.len() {
+                    return Err(~"Unterminated string literal");
+                }
+                pos += 1;
+                TLiteral(chars.slice(start, pos - 1).iter().map(|&c| c).collect())
+            }
+            '$' => {
+                pos += 1;
+                if pos >= chars.len() || !is_ncname_start(chars[pos]) {
+                    return Err(~"Expected variable name after '$'");
+                }
+                let mut name = read_ncname(chars, &mut pos);
+                if pos + 1 < chars.len() && chars[pos] == ':' && is_ncname_start(chars[pos + 1]) {
+                    pos += 1;
+                    name.push_char(':');
+                    name.push_str(read_ncname(chars, &mut pos));
+                }
+                TVariable(name)
+            }
+            c if is_ncname_start(c) => {
+                let name = read_ncname(chars, &mut pos);
+                if operator {
+                    match name.as_slice() {
+                        "and" => TAnd,
+                        "or" => TOr,
+                        "mod" => TMod,
+                        "div" => TDiv,
+                        _ => return Err(format!("Expected operator, found '{}'", name))
+                    }
+                } else if pos + 1 < chars.len() && chars[pos] == ':' && chars[pos + 1] == '*' {
+                    pos += 2;
+                    TNameTest(Some(name), None)
+                } else {
+                    let (prefix, local) = if pos + 1 < chars.len() && chars[pos] == ':' &&
+                                             is_ncname_start(chars[pos + 1]) {
+                        pos += 1;
+                        (Some(name), read_ncname(chars, &mut pos))
+                    } else {
+                        (None, name)
+                    };
+                    let after = skip_space(chars, pos);
+                    let paren = after < chars.len() && chars[after] == '(';
+                    let axis = after + 1 < chars.len() && chars[after] == ':' && chars[after + 1] == ':';
+                    match prefix {
+                        None if paren && (local.as_slice() == "comment" || local.as_slice() == "text" ||
+                                          local.as_slice() == "processing-instruction" ||
+                                          local.as_slice() == "node") => TNodeType(local),
+                        None if axis => TAxisName(local),
+                        Some(ref p) if paren => TFunctionName(format!("{}:{}", *p, local)),
+                        None if paren => TFunctionName(local),
+                        prefix => TNameTest(prefix, Some(local))
+                    }
+                }
+            }
+            c => return Err(format!("Unexpected character '{}'", c))
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// Syntax tree
+
+#[deriving(Clone,Eq)]
+enum BinaryOp {
+    OrOp,
+    AndOp,
+    EqualsOp,
+    NotEqualsOp,
+    LessOp,
+    LessEqualsOp,
+    GreaterOp,
+    GreaterEqualsOp,
+    AddOp,
+    SubtractOp,
+    MultiplyOp,
+    DivideOp,
+    ModuloOp,
+    UnionOp
+}
+
+#[deriving(Clone,Eq)]
+enum Axis {
+    AncestorAxis,
+    AncestorOrSelfAxis,
+    AttributeAxis,
+    ChildAxis,
+    DescendantAxis,
+    DescendantOrSelfAxis,
+    FollowingAxis,
+    FollowingSiblingAxis,
+    NamespaceAxis,
+    ParentAxis,
+    PrecedingAxis,
+    PrecedingSiblingAxis,
+    SelfAxis
+}
+
+#[deriving(Clone,Eq)]
+enum NodeTest {
+    // A name test as prefix and local name, `None` standing for '*'
+    NameTest(Option<~str>, Option<~str>),
+    NodeTypeTest,
+    TextTest,
+    CommentTest,
+    PITest(Option<~str>)
+}
+
+#[deriving(Clone,Eq)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: ~[Expr]
+}
+
+#[deriving(Clone,Eq)]
+enum PathStart {
+    // The root node of the document
+    RootStart,
+    // The context node
+    ContextStart,
+    // The node-set returned by a filter expression
+    FilterStart(~Expr)
+}
+
+#[deriving(Clone,Eq)]
+enum Expr {
+    Binary(BinaryOp, ~Expr, ~Expr),
+    Negate(~Expr),
+    Literal(~str),
+    Number(f64),
+    Variable(~str),
+    FunctionCall(~str, ~[Expr]),
+    Filter(~Expr, ~[Expr]),
+    Path(PathStart, ~[Step])
+}
+
+// Parser
+
+struct ExprParser {
+    tokens: ~[Token],
+    pos: uint
+}
+
+impl ExprParser {
+    fn peek<'a>(&'a self) -> Option<&'a Token> {
+        if self.pos < self.tokens.len() {
+            Some(&self.tokens[self.pos])
+        } else {
+            None
+        }
+    }
+
+    // Returns a copy of the next token, so the parser can advance while matching on it
+    fn next_token(&self) -> Option<Token> {
+        self.peek().map(|t| t.clone())
+    }
+
+    fn next_is(&self, t: &Token) -> bool {
+        self.peek() == Some(t)
+    }
+
+    fn expect(&mut self, t: Token, what: &str) -> Result<(), ~str> {
+        if self.next_is(&t) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected {}", what))
+        }
+    }
+
+    // Parses a chain of left-associative binary operators, `ops` mapping tokens
+    // to operators
+    fn parse_binary(&mut self, ops: &[(Token, BinaryOp)],
+                    operand: |&mut ExprParser| -> Result<Expr, ~str>) -> Result<Expr, ~str> {
+        let mut left = check!(operand(self));
+        loop {
+            let op = match self.peek() {
+                Some(t) => ops.iter().find(|&&(ref tok, _)| tok == t).map(|&(_, op)| op),
+                None => None
+            };
+            match op {
+                Some(op) => {
+                    self.pos += 1;
+                    let right = check!(operand(self));
+                    left = Binary(op, ~left, ~right);
+                }
+                None => return Ok(left)
+            }
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ~str> {
+        self.parse_binary([(TOr, OrOp)], |p| p.parse_and())
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ~str> {
+        self.parse_binary([(TAnd, AndOp)], |p| p.parse_equality())
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, ~str> {
+        self.parse_binary([(TEquals, EqualsOp), (TNotEquals, NotEqualsOp)],
+                          |p| p.parse_relational())
+    }
+
+    fn parse_relational(&mut self) -> Result<Expr, ~str> {
+        self.parse_binary([(TLess, LessOp), (TLessEquals, LessEqualsOp),
+                           (TGreater, GreaterOp), (TGreaterEquals, GreaterEqualsOp)],
+                          |p| p.parse_additive())
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ~str> {
+        self.parse_binary([(TPlus, AddOp), (TMinus, SubtractOp)], |p| p.parse_multiplicative())
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ~str> {
+        self.parse_binary([(TMultiply, MultiplyOp), (TDiv, DivideOp), (TMod, ModuloOp)],
+                          |p| p.parse_unary())
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ~str> {
+        if self.next_is(&TMinus) {
+            self.pos += 1;
+            Ok(Negate(~check!(self.parse_unary())))
+        } else {
+            self.parse_binary([(TPipe, UnionOp)], |p| p.parse_path())
+        }
+    }
+
+    fn starts_step(&self) -> bool {
+        match self.peek() {
+            Some(&TNameTest(_, _)) | Some(&TNodeType(_)) | Some(&TAt) | Some(&TAxisName(_)) |
+            Some(&TDot) | Some(&TDotDot) => true,
+            _ => false
+        }
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, ~str> {
+        let start = match self.next_token() {
+            Some(TSlash) => {
+                self.pos += 1;
+                if !self.starts_step() {
+                    return Ok(Path(RootStart, ~[]));
+                }
+                RootStart
+            }
+            Some(TDoubleSlash) => RootStart,
+            _ if self.starts_step() => ContextStart,
+            _ => {
+                let primary = check!(self.parse_primary());
+                let predicates = check!(self.parse_predicates());
+                let filter = if predicates.is_empty() {
+                    primary
+                } else {
+                    Filter(~primary, predicates)
+                };
+                match self.peek() {
+                    Some(&TSlash) | Some(&TDoubleSlash) => FilterStart(~filter),
+                    _ => return Ok(filter)
+                }
+            }
+        };
+
+        let mut steps = ~[];
+        // A filter expression or "//" is followed by a separator, anything else by a step
+        let mut separated = match start {
+            FilterStart(_) => false,
+            _ => !self.next_is(&TDoubleSlash)
+        };
+        loop {
+            if !separated {
+                match self.next_token() {
+                    Some(TSlash) => self.pos += 1,
+                    Some(TDoubleSlash) => {
+                        self.pos += 1;
+                        steps.push(Step { axis: DescendantOrSelfAxis, test: NodeTypeTest, predicates: ~[] });
+                    }
+                    _ => break
+                }
+            }
+            steps.push(check!(self.parse_step()));
+            separated = false;
+        }
+        Ok(Path(start, steps))
+    }
+
+    fn parse_step(&mut self) -> Result<Step, ~str> {
+        match self.next_token() {
+            Some(TDot) => {
+                self.pos += 1;
+                return Ok(Step { axis: SelfAxis, test: NodeTypeTest, predicates: ~[] });
+            }
+            Some(TDotDot) => {
+                self.pos += 1;
+                return Ok(Step { axis: ParentAxis, test: NodeTypeTest, predicates: ~[] });
+            }
+            _ => ()
+        }
+
+        let axis = match self.next_token() {
+            Some(TAt) => {
+                self.pos += 1;
+                AttributeAxis
+            }
+            Some(TAxisName(name)) => {
+                self.pos += 1;
+                let axis = match name.as_slice() {
+                    "ancestor" => AncestorAxis,
+                    "ancestor-or-self" => AncestorOrSelfAxis,
+                    "attribute" => AttributeAxis,
+                    "child" => ChildAxis,
+                    "descendant" => DescendantAxis,
+                    "descendant-or-self" => DescendantOrSelfAxis,
+                    "following" => FollowingAxis,
+                    "following-sibling" => FollowingSiblingAxis,
+                    "namespace" => NamespaceAxis,
+                    "parent" => ParentAxis,
+                    "preceding" => PrecedingAxis,
+                    "preceding-sibling" => PrecedingSiblingAxis,
+                    "self" => SelfAxis,
+                    _ => return Err(format!("Unknown axis '{}'", name))
+                };
+                check!(self.expect(TColonColon, "'::' after axis name"));
+                axis
+            }
+            _ => ChildAxis
+        };
+
+        let test = match self.next_token() {
+            Some(TNameTest(prefix, local)) => {
+                self.pos += 1;
+                NameTest(prefix, local)
+            }
+            Some(TNodeType(node_type)) => {
+                self.pos += 1;
+                check!(self.expect(TLParen, "'(' after node type"));
+                let test = match node_type.as_slice() {
+                    "node" => NodeTypeTest,
+                    "text" => TextTest,
+                    "comment" => CommentTest,
+                    _ => match self.next_token() {
+                        Some(TLiteral(target)) => {
+                            self.pos += 1;
+                            PITest(Some(target))
+                        }
+                        _ => PITest(None)
+                    }
+                };
+                check!(self.expect(TRParen, "')' after node type"));
+                test
+            }
+            _ => return Err(~"Expected node test")
+        };
+
+        let predicates = check!(self.parse_predicates());
+        Ok(Step { axis: axis, test: test, predicates: predicates })
+    }
+
+    fn parse_predicates(&mut self) -> Result<~[Expr], ~str> {
+        let mut predicates = ~[];
+        while self.next_is(&TLBracket) {
+            self.pos += 1;
+            predicates.push(check!(self.parse_expr()));
+            check!(self.expect(TRBracket, "']' after predicate"));
+        }
+        Ok(predicates)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ~str> {
+        match self.next_token() {
+            Some(TVariable(name)) => {
+                self.pos += 1;
+                Ok(Variable(name))
+            }
+            Some(TLiteral(s)) => {
+                self.pos += 1;
+                Ok(Literal(s))
+            }
+            Some(TNumber(n)) => {
+                self.pos += 1;
+                Ok(Number(n))
+            }
+            Some(TLParen) => {
+                self.pos += 1;
+                let e = check!(self.parse_expr());
+                check!(self.expect(TRParen, "')'"));
+                Ok(e)
+            }
+            Some(TFunctionName(name)) => {
+                self.pos += 1;
+                check!(self.expect(TLParen, "'(' after function name"));
+                let mut args = ~[];
+                if !self.next_is(&TRParen) {
+                    loop {
+                        args.push(check!(self.parse_expr()));
+                        if self.next_is(&TComma) {
+                            self.pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                check!(self.expect(TRParen, "')' after function arguments"));
+                Ok(FunctionCall(name, args))
+            }
+            Some(_) => Err(~"Unexpected token"),
+            None => Err(~"Unexpected end of expression")
+        }
+    }
+}
+
+// Evaluation
+
+// The nodes of a document in document order, with the links needed to follow all axes
+struct TreeNode<'a> {
+    node: XPathNode<'a>,
+    parent: Option<uint>,
+    children: ~[uint],
+    attributes: ~[uint],
+    namespaces: ~[uint],
+    // The index of the last node in the subtree rooted at this node
+    end: uint,
+    // The prefix of an element or attribute name
+    prefix: Option<~str>
+}
+
+struct Tree<'a> {
+    nodes: ~[TreeNode<'a>]
+}
+
+// Returns the prefix of a qualified name, if it has one
+fn prefix_of(qname: &str) -> Option<~str> {
+    qname.find(':').map(|i| qname.slice_to(i).to_owned())
+}
+
+impl<'a> Tree<'a> {
+    fn new(root: &'a Element) -> Tree<'a> {
+        let mut tree = Tree { nodes: ~[] };
+        tree.push(XPathRoot(root), None, None);
+        tree.add_element(root, 0, &mut Scope::new());
+        tree.nodes[0].end = tree.nodes.len() - 1;
+        tree
+    }
+
+    fn push(&mut self, node: XPathNode<'a>, parent: Option<uint>, prefix: Option<~str>) -> uint {
+        let index = self.nodes.len();
+        self.nodes.push(TreeNode {
+            node: node,
+            parent: parent,
+            children: ~[],
+            attributes: ~[],
+            namespaces: ~[],
+            end: index,
+            prefix: prefix
+        });
+        index
+    }
+
+    fn push_child(&mut self, node: XPathNode<'a>, parent: uint) {
+        let index = self.push(node, Some(parent), None);
+        self.nodes[parent].children.push(index);
+    }
+
+    // Adds `elem` and its subtree. `scope` names the element and attributes the way
+    // the serializer would and tracks the namespaces in scope.
+    fn add_element(&mut self, elem: &'a Element, parent: uint, scope: &mut Scope) {
+        let names = scope.enter(elem);
+        let index = self.push(XPathElement(elem), Some(parent), prefix_of(names.qname));
+        self.nodes[parent].children.push(index);
+
+        let mut namespaces = scope.in_scope();
+        namespaces.push((~"xml", XML_NS.to_owned()));
+        namespaces.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+        for (name, ns) in namespaces.move_iter() {
+            let ns_index = self.push(XPathNamespace(name, ns), Some(index), None);
+            self.nodes[index].namespaces.push(ns_index);
+        }
+
+        for &(ref name, attr) in names.attributes.iter() {
+            let attr_index = self.push(XPathAttribute(attr), Some(index), prefix_of(*name));
+            self.nodes[index].attributes.push(attr_index);
+        }
+
+        // Adjacent character data and CDATA form a single text node
+        let mut text: Option<~str> = None;
+        for child in elem.children.iter() {
+            match *child {
+                CharacterNode(ref data) | CDATANode(ref data) => {
+                    if text.is_some() {
+                        text.get_mut_ref().push_str(*data);
+                    } else {
+                        text = Some(data.clone());
+                    }
+                    continue;
+                }
+                _ => ()
+            }
+            match text.take() {
+                Some(t) => self.push_child(XPathText(t), index),
+                None => ()
+            }
+            match *child {
+                Element(ref child) => self.add_element(child, index, scope),
+                CommentNode(ref data) => self.push_child(XPathComment(data.as_slice()), index),
+                PINode(ref pi) => self.push_child(XPathPI(pi), index),
+                _ => ()
+            }
+        }
+        match text.take() {
+            Some(t) => self.push_child(XPathText(t), index),
+            None => ()
+        }
+
+        self.nodes[index].end = self.nodes.len() - 1;
+        scope.pop();
+    }
+
+    fn is_attribute_or_namespace(&self, node: uint) -> bool {
+        match self.nodes[node].node {
+            XPathAttribute(_) | XPathNamespace(_, _) => true,
+            _ => false
+        }
+    }
+
+    // Returns the index of `node`, comparing elements and attributes by identity
+    fn find(&self, node: &XPathNode<'a>) -> Option<uint> {
+        self.nodes.iter().position(|n| {
+            match (&n.node, node) {
+                (&XPathRoot(a), &XPathRoot(b)) |
+                (&XPathElement(a), &XPathElement(b)) => ptr::to_unsafe_ptr(a) == ptr::to_unsafe_ptr(b),
+                (&XPathAttribute(a), &XPathAttribute(b)) => ptr::to_unsafe_ptr(a) == ptr::to_unsafe_ptr(b),
+                (a, b) => a == b
+            }
+        })
+    }
+}
+
+// Values computed during evaluation, node-sets holding indices into the `Tree`
+enum Value {
+    Nodes(~[uint]),
+    Str(~str),
+    Num(f64),
+    Bool(bool)
+}
+
+struct Evaluator<'a, 'b> {
+    tree: &'b Tree<'a>,
+    ctx: &'b XPathContext<'a>
+}
+
+// Sorts `nodes` into document order, removing duplicates
+fn document_order(mut nodes: ~[uint]) -> ~[uint] {
+    nodes.sort_by(|a, b| a.cmp(b));
+    nodes.dedup();
+    nodes
+}
+
+// Rounds as the XPath `round()` function does
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+impl<'a, 'b> Evaluator<'a, 'b> {
+    fn string_value(&self, node: uint) -> ~str {
+        self.tree.nodes[node].node.string_value()
+    }
+
+    fn to_string(&self, value: &Value) -> ~str {
+        match *value {
+            Nodes(ref nodes) => nodes.head_opt().map_default(~"", |&n| self.string_value(n)),
+            Str(ref s) => s.clone(),
+            Num(n) => number_to_str(n),
+            Bool(b) => b.to_str()
+        }
+    }
+
+    fn to_number(&self, value: &Value) -> f64 {
+        match *value {
+            Num(n) => n,
+            Bool(b) => if b { 1.0 } else { 0.0 },
+            _ => str_to_number(self.to_string(value))
+        }
+    }
+
+    fn to_bool(&self, value: &Value) -> bool {
+        match *value {
+            Nodes(ref nodes) => !nodes.is_empty(),
+            Str(ref s) => !s.is_empty(),
+            Num(n) => n != 0.0 && !n.is_nan(),
+            Bool(b) => b
+        }
+    }
+
+    // Converts a value given to a variable to a `Value`
+    fn from_variable(&self, value: &XPathValue<'a>) -> Result<Value, ~str> {
+        match *value {
+            NodeSet(ref nodes) => {
+                let mut indices = ~[];
+                for node in nodes.iter() {
+                    match self.tree.find(node) {
+                        Some(i) => indices.push(i),
+                        None => return Err(~"Variable contains a node of another document")
+                    }
+                }
+                Ok(Nodes(document_order(indices)))
+            }
+            StringValue(ref s) => Ok(Str(s.clone())),
+            NumberValue(n) => Ok(Num(n)),
+            BooleanValue(b) => Ok(Bool(b))
+        }
+    }
+
+    fn eval(&self, expr: &Expr, node: uint, pos: uint, size: uint) -> Result<Value, ~str> {
+        match *expr {
+            Binary(OrOp, ref a, ref b) => {
+                let a = check!(self.eval(*a, node, pos, size));
+                if self.to_bool(&a) {
+                    return Ok(Bool(true));
+                }
+                let b = check!(self.eval(*b, node, pos, size));
+                Ok(Bool(self.to_bool(&b)))
+            }
+            Binary(AndOp, ref a, ref b) => {
+                let a = check!(self.eval(*a, node, pos, size));
+                if !self.to_bool(&a) {
+                    return Ok(Bool(false));
+                }
+                let b = check!(self.eval(*b, node, pos, size));
+                Ok(Bool(self.to_bool(&b)))
+            }
+            Binary(UnionOp, ref a, ref b) => {
+                let mut a = check!(self.eval_nodes(*a, node, pos, size));
+                let b = check!(self.eval_nodes(*b, node, pos, size));
+                a.push_all_move(b);
+                Ok(Nodes(document_order(a)))
+            }
+            Binary(op, ref a, ref b) => {
+                let a = check!(self.eval(*a, node, pos, size));
+                let b = check!(self.eval(*b, node, pos, size));
+                match op {
+                    EqualsOp | NotEqualsOp | LessOp | LessEqualsOp | GreaterOp | GreaterEqualsOp => {
+                        Ok(Bool(self.compare(op, &a, &b)))
+                    }
+                    _ => {
+                        let (a, b) = (self.to_number(&a), self.to_number(&b));
+                        Ok(Num(match op {
+                            AddOp => a + b,
+                            SubtractOp => a - b,
+                            MultiplyOp => a * b,
+                            DivideOp => a / b,
+                            _ => a % b
+                        }))
+                    }
+                }
+            }
+            Negate(ref e) => {
+                let v = check!(self.eval(*e, node, pos, size));
+                Ok(Num(-self.to_number(&v)))
+            }
+            Literal(ref s) => Ok(Str(s.clone())),
+            Number(n) => Ok(Num(n)),
+            Variable(ref name) => match self.ctx.variables.find(name) {
+                Some(value) => self.from_variable(value),
+                None => Err(format!("Unknown variable '{}'", *name))
+            },
+            FunctionCall(ref name, ref args) => self.call(*name, *args, node, pos, size),
+            Filter(ref e, ref predicates) => {
+                let nodes = check!(self.eval_nodes(*e, node, pos, size));
+                Ok(Nodes(check!(self.filter(nodes, *predicates))))
+            }
+            Path(ref start, ref steps) => {
+                let mut nodes = match *start {
+                    RootStart => ~[0],
+                    ContextStart => ~[node],
+                    FilterStart(ref e) => check!(self.eval_nodes(*e, node, pos, size))
+                };
+                for step in steps.iter() {
+                    nodes = check!(self.eval_step(step, nodes));
+                }
+                Ok(Nodes(nodes))
+            }
+        }
+    }
+
+    fn eval_nodes(&self, expr: &Expr, node: uint, pos: uint, size: uint) -> Result<~[uint], ~str> {
+        match check!(self.eval(expr, node, pos, size)) {
+            Nodes(nodes) => Ok(nodes),
+            _ => Err(~"Expression does not return a node-set")
+        }
+    }
+
+    // Compares two values with `op`, following the rules for node-sets of XPath
+    fn compare(&self, op: BinaryOp, a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (&Nodes(ref a), &Nodes(ref b)) => {
+                let b: ~[~str] = b.iter().map(|&n| self.string_value(n)).collect();
+                a.iter().any(|&n| {
+                    let a = Str(self.string_value(n));
+                    b.iter().any(|s| self.compare_atoms(op, &a, &Str(s.clone())))
+                })
+            }
+            (&Nodes(ref a), &Bool(_)) => self.compare_atoms(op, &Bool(!a.is_empty()), b),
+            (&Bool(_), &Nodes(ref b)) => self.compare_atoms(op, a, &Bool(!b.is_empty())),
+            (&Nodes(ref a), _) => a.iter().any(|&n| self.compare_atoms(op, &Str(self.string_value(n)), b)),
+            (_, &Nodes(ref b)) => b.iter().any(|&n| self.compare_atoms(op, a, &Str(self.string_value(n)))),
+            _ => self.compare_atoms(op, a, b)
+        }
+    }
+
+    // Compares two values which are not node-sets
+    fn compare_atoms(&self, op: BinaryOp, a: &Value, b: &Value) -> bool {
+        match op {
+            EqualsOp | NotEqualsOp => {
+                let equal = match (a, b) {
+                    (&Bool(_), _) | (_, &Bool(_)) => self.to_bool(a) == self.to_bool(b),
+                    (&Num(_), _) | (_, &Num(_)) => self.to_number(a) == self.to_number(b),
+                    _ => self.to_string(a) == self.to_string(b)
+                };
+                if op == EqualsOp { equal } else { !equal }
+            }
+            _ => {
+                let (a, b) = (self.to_number(a), self.to_number(b));
+                match op {
+                    LessOp => a < b,
+                    LessEqualsOp => a <= b,
+                    GreaterOp => a > b,
+                    _ => a >= b
+                }
+            }
+        }
+    }
+
+    // Returns the nodes on `axis` from `node`, in axis order
+    fn axis_nodes(&self, axis: Axis, node: uint) -> ~[uint] {
+        let tree = self.tree;
+        let n = &tree.nodes[node];
+        match axis {
+            ChildAxis => n.children.clone(),
+            AttributeAxis => n.attributes.clone(),
+            NamespaceAxis => n.namespaces.clone(),
+            SelfAxis => ~[node],
+            ParentAxis => n.parent.iter().map(|&p| p).collect(),
+            DescendantAxis | DescendantOrSelfAxis => {
+                let mut nodes = if axis == DescendantOrSelfAxis { ~[node] } else { ~[] };
+                if !tree.is_attribute_or_namespace(node) {
+                    for i in range(node + 1, n.end + 1) {
+                        if !tree.is_attribute_or_namespace(i) {
+                            nodes.push(i);
+                        }
+                    }
+                }
+                nodes
+            }
+            AncestorAxis | AncestorOrSelfAxis => {
+                let mut nodes = if axis == AncestorOrSelfAxis { ~[node] } else { ~[] };
+                let mut current = n.parent;
+                while current.is_some() {
+                    let p = current.unwrap();
+                    nodes.push(p);
+                    current = tree.nodes[p].parent;
+                }
+                nodes
+            }
+            FollowingSiblingAxis | PrecedingSiblingAxis => {
+                if tree.is_attribute_or_namespace(node) || n.parent.is_none() {
+                    return ~[];
+                }
+                let siblings = &tree.nodes[n.parent.unwrap()].children;
+                let i = siblings.position_elem(&node).unwrap();
+                if axis == FollowingSiblingAxis {
+                    siblings.slice_from(i + 1).to_owned()
+                } else {
+                    siblings.slice_to(i).rev_iter().map(|&s| s).collect()
+                }
+            }
+            FollowingAxis => {
+                range(n.end + 1, tree.nodes.len()).filter(|&i| !tree.is_attribute_or_namespace(i)).collect()
+            }
+            PrecedingAxis => {
+                let ancestors = self.axis_nodes(AncestorAxis, node);
+                range(0, node).invert().filter(|&i| {
+                    !tree.is_attribute_or_namespace(i) && !ancestors.contains(&i)
+                }).collect()
+            }
+        }
+    }
+
+    // Returns true if `node` passes `test` on `axis`
+    fn matches(&self, axis: Axis, test: &NodeTest, node: uint) -> Result<bool, ~str> {
+        let n = &self.tree.nodes[node].node;
+        match *test {
+            NodeTypeTest => Ok(true),
+            TextTest => Ok(match *n { XPathText(_) => true, _ => false }),
+            CommentTest => Ok(match *n { XPathComment(_) => true, _ => false }),
+            PITest(ref target) => Ok(match *n {
+                XPathPI(pi) => target.as_ref().map_default(true, |target| pi.target == *target),
+                _ => false
+            }),
+            NameTest(ref prefix, ref local) => {
+                let ns = match *prefix {
+                    Some(ref p) => match self.ctx.namespaces.find(p) {
+                        Some(ns) => Some(ns.clone()),
+                        None => return Err(format!("Unbound namespace prefix '{}'", *p))
+                    },
+                    None => None
+                };
+                let name_matches = |name: &str| local.as_ref().map_default(true, |l| l.as_slice() == name);
+                Ok(match (axis, n) {
+                    (AttributeAxis, &XPathAttribute(attr)) => attr.ns == ns && name_matches(attr.name.as_slice()),
+                    (NamespaceAxis, &XPathNamespace(ref p, _)) => ns.is_none() && name_matches(*p),
+                    (AttributeAxis, _) | (NamespaceAxis, _) => false,
+                    (_, &XPathElement(elem)) => elem.ns == ns && name_matches(elem.name.as_slice()),
+                    _ => false
+                })
+            }
+        }
+    }
+
+    // Applies `predicates` to `nodes`, which are in the order positions are counted in
+    fn filter(&self, nodes: ~[uint], predicates: &[Expr]) -> Result<~[uint], ~str> {
+        let mut nodes = nodes;
+        for predicate in predicates.iter() {
+            let size = nodes.len();
+            let mut kept = ~[];
+            for (i, &n) in nodes.iter().enumerate() {
+                let keep = match check!(self.eval(predicate, n, i + 1, size)) {
+                    Num(pos) => pos == (i + 1) as f64,
+                    other => self.to_bool(&other)
+                };
+                if keep {
+                    kept.push(n);
+                }
+            }
+            nodes = kept;
+        }
+        Ok(nodes)
+    }
+
+    fn eval_step(&self, step: &Step, context: ~[uint]) -> Result<~[uint], ~str> {
+        let mut result = ~[];
+        for &node in context.iter() {
+            let mut nodes = ~[];
+            for n in self.axis_nodes(step.axis, node).move_iter() {
+                if check!(self.matches(step.axis, &step.test, n)) {
+                    nodes.push(n);
+                }
+            }
+            result.push_all_move(check!(self.filter(nodes, step.predicates)));
+        }
+        Ok(document_order(result))
+    }
+
+    // Evaluates argument `i`, or returns the context node if it is missing
+    fn arg(&self, args: &[Expr], i: uint, node: uint, pos: uint, size: uint) -> Result<Value, ~str> {
+        if i < args.len() {
+            self.eval(&args[i], node, pos, size)
+        } else {
+            Ok(Nodes(~[node]))
+        }
+    }
+
+    fn arg_string(&self, args: &[Expr], i: uint, node: uint, pos: uint, size: uint) -> Result<~str, ~str> {
+        let v = check!(self.arg(args, i, node, pos, size));
+        Ok(self.to_string(&v))
+    }
+
+    fn arg_number(&self, args: &[Expr], i: uint, node: uint, pos: uint, size: uint) -> Result<f64, ~str> {
+        let v = check!(self.arg(args, i, node, pos, size));
+        Ok(self.to_number(&v))
+    }
+
+    // Returns the first node of argument `i`, or the context node if it is missing
+    fn arg_node(&self, args: &[Expr], i: uint, node: uint, pos: uint, size: uint) -> Result<Option<uint>, ~str> {
+        match check!(self.arg(args, i, node, pos, size)) {
+            Nodes(nodes) => Ok(nodes.head_opt().map(|&n| n)),
+            _ => Err(~"Argument is not a node-set")
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Expr], node: uint, pos: uint, size: uint) -> Result<Value, ~str> {
+        let (min, max) = match name {
+            "last" | "position" | "true" | "false" => (0, 0),
+            "local-name" | "namespace-uri" | "name" | "string" | "string-length" |
+            "normalize-space" | "number" => (0, 1),
+            "count" | "id" | "boolean" | "not" | "lang" | "sum" | "floor" | "ceiling" | "round" => (1, 1),
+            "starts-with" | "contains" | "substring-before" | "substring-after" => (2, 2),
+            "substring" => (2, 3),
+            "translate" => (3, 3),
+            "concat" => (2, uint::max_value),
+            _ => return Err(format!("Unknown function '{}'", name))
+        };
+        if args.len() < min || args.len() > max {
+            return Err(format!("Wrong number of arguments to '{}'", name));
+        }
+
+        Ok(match name {
+            "last" => Num(size as f64),
+            "position" => Num(pos as f64),
+            "count" => Num(check!(self.eval_nodes(&args[0], node, pos, size)).len() as f64),
+            "id" => {
+                let ids = match check!(self.eval(&args[0], node, pos, size)) {
+                    Nodes(nodes) => {
+                        let values: ~[~str] = nodes.iter().map(|&n| self.string_value(n)).collect();
+                        values.connect(" ")
+                    }
+                    other => self.to_string(&other)
+                };
+                let ids: ~[&str] = ids.split(is_space).filter(|s| !s.is_empty()).collect();
+                // Without a DTD, attributes named "id" or "xml:id" are taken to be IDs
+                let mut nodes = ~[];
+                for n in self.tree.nodes.iter() {
+                    match n.node {
+                        XPathAttribute(attr) if attr.name.as_slice() == "id" &&
+                                                (attr.ns.is_none() || attr.ns == Some(XML_NS.to_owned())) => {
+                            if ids.contains(&attr.value.as_slice()) {
+                                nodes.push(n.parent.unwrap());
+                            }
+                        }
+                        _ => ()
+                    }
+                }
+                Nodes(document_order(nodes))
+            }
+            "local-name" | "namespace-uri" | "name" => {
+                let n = match check!(self.arg_node(args, 0, node, pos, size)) {
+                    Some(n) => n,
+                    None => return Ok(Str(~""))
+                };
+                let prefix = &self.tree.nodes[n].prefix;
+                let (ns, local) = match self.tree.nodes[n].node {
+                    XPathElement(elem) => (elem.ns.clone(), elem.name.to_str()),
+                    XPathAttribute(attr) => (attr.ns.clone(), attr.name.to_str()),
+                    XPathPI(pi) => (None, pi.target.clone()),
+                    XPathNamespace(ref p, _) => (None, p.clone()),
+                    _ => (None, ~"")
+                };
+                Str(match name {
+                    "local-name" => local,
+                    "namespace-uri" => ns.unwrap_or(~""),
+                    _ => match *prefix {
+                        Some(ref p) => format!("{}:{}", *p, local),
+                        None => local
+                    }
+                })
+            }
+            "string" => Str(check!(self.arg_string(args, 0, node, pos, size))),
+            "concat" => {
+                let mut result = ~"";
+                for i in range(0, args.len()) {
+                    result.push_str(check!(self.arg_string(args, i, node, pos, size)));
+                }
+                Str(result)
+            }
+            "starts-with" | "contains" | "substring-before" | "substring-after" => {
+                let a = check!(self.arg_string(args, 0, node, pos, size));
+                let b = check!(self.arg_string(args, 1, node, pos, size));
+                match name {
+                    "starts-with" => Bool(a.starts_with(b)),
+                    "contains" => Bool(a.contains(b)),
+                    "substring-before" => Str(match a.find_str(b) {
+                        Some(i) => a.slice_to(i).to_owned(),
+                        None => ~""
+                    }),
+                    _ => Str(match a.find_str(b) {
+                        Some(i) => a.slice_from(i + b.len()).to_owned(),
+                        None => ~""
+                    })
+                }
+            }
+            "substring" => {
+                let s = check!(self.arg_string(args, 0, node, pos, size));
+                let start = round(check!(self.arg_number(args, 1, node, pos, size)));
+                let end = if args.len() > 2 {
+                    start + round(check!(self.arg_number(args, 2, node, pos, size)))
+                } else {
+                    1.0 / 0.0
+                };
+                Str(s.chars().enumerate().filter(|&(i, _)| {
+                    let p = (i + 1) as f64;
+                    p >= start && p < end
+                }).map(|(_, c)| c).collect())
+            }
+            "string-length" => Num(check!(self.arg_string(args, 0, node, pos, size)).char_len() as f64),
+            "normalize-space" => {
+                let s = check!(self.arg_string(args, 0, node, pos, size));
+                let words: ~[&str] = s.split(is_space).filter(|w| !w.is_empty()).collect();
+                Str(words.connect(" "))
+            }
+            "translate" => {
+                let s = check!(self.arg_string(args, 0, node, pos, size));
+                let from: ~[char] = check!(self.arg_string(args, 1, node, pos, size)).chars().collect();
+                let to: ~[char] = check!(self.arg_string(args, 2, node, pos, size)).chars().collect();
+                let mut result = ~"";
+                for c in s.chars() {
+                    match from.position_elem(&c) {
+                        Some(i) if i < to.len() => result.push_char(to[i]),
+                        Some(_) => (),
+                        None => result.push_char(c)
+                    }
+                }
+                Str(result)
+            }
+            "boolean" => {
+                let v = check!(self.eval(&args[0], node, pos, size));
+                Bool(self.to_bool(&v))
+            }
+            "not" => {
+                let v = check!(self.eval(&args[0], node, pos, size));
+                Bool(!self.to_bool(&v))
+            }
+            "true" => Bool(true),
+            "false" => Bool(false),
+            "lang" => {
+                let lang = check!(self.arg_string(args, 0, node, pos, size)).to_ascii_lower();
+                let mut result = false;
+                for &n in self.axis_nodes(AncestorOrSelfAxis, node).iter() {
+                    let found = match self.tree.nodes[n].node {
+                        XPathElement(elem) => elem.attribute_with_name_and_ns("lang", Some(XML_NS.to_owned())),
+                        _ => None
+                    };
+                    match found {
+                        Some(attr) => {
+                            let value = attr.value.to_ascii_lower();
+                            result = value == lang || value.starts_with(lang + "-");
+                            break;
+                        }
+                        None => ()
+                    }
+                }
+                Bool(result)
+            }
+            "number" => Num(check!(self.arg_number(args, 0, node, pos, size))),
+            "sum" => {
+                let nodes = check!(self.eval_nodes(&args[0], node, pos, size));
+                Num(nodes.iter().fold(0.0, |sum, &n| sum + str_to_number(self.string_value(n))))
+            }
+            "floor" => Num(check!(self.arg_number(args, 0, node, pos, size)).floor()),
+            "ceiling" => Num(check!(self.arg_number(args, 0, node, pos, size)).ceil()),
+            _ => Num(round(check!(self.arg_number(args, 0, node, pos, size))))
+        })
+    }
+}